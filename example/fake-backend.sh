@@ -0,0 +1,41 @@
+#!/bin/bash
+# Fake backend of the daemon which pretends to run launcher.sh without creating VMs
+# Usage: ./launcher.sh daemon --backend ./example/fake-backend.sh
+
+set -euo pipefail
+
+subcommand="$1"
+shift
+output=""
+project_dir="."
+suite_results=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -o | --output) output="$2"; shift ;;
+        -p | --project-dir) project_dir="$2"; shift ;;
+        --suite-results) suite_results="$2"; shift ;;
+    esac
+    shift
+done
+
+echo "fake backend: $subcommand"
+for step in up provision bench; do
+    echo "$step"
+    sleep "${FAKE_BACKEND_SLEEP:-1}"
+done
+
+if [ "$subcommand" = "delete" ]; then
+    exit 0
+fi
+mkdir -p "$project_dir"
+if [ -n "$output" ]; then
+    echo "fake result of $subcommand" > "$output"
+fi
+if [ -n "$suite_results" ]; then
+    mkdir -p "$(dirname "$suite_results")"
+    echo "records: []" > "$suite_results"
+fi
+cat > "$project_dir/run-metadata.yaml" <<METADATA
+subcommand: $subcommand
+backend: fake
+METADATA
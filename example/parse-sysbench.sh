@@ -0,0 +1,10 @@
+#!/bin/bash
+# Print the results of sysbench in the output file as YAML
+
+set -euo pipefail
+
+awk '
+/events per second:/ { print "events_per_second: " $4 }
+/total number of events:/ { print "events: " $5 }
+/total time:/ { sub("s$", "", $3); print "total_time: " $3 }
+' "$1"
@@ -0,0 +1,6 @@
+#!/bin/bash
+# Usage: sysbench.sh <test> [options...]
+
+set -euxo pipefail
+
+sysbench "$@" run
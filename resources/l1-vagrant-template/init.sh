@@ -14,6 +14,8 @@ sudo apt-get update && sudo apt-get install -y qemu libvirt-daemon-system libvir
 
 sudo adduser vagrant libvirt
 
-sudo growpart /dev/vda 3
+# root disk name depends on disk_bus
+root_pv=$(sudo pvs --noheadings -o pv_name | head -n 1 | tr -d ' ')
+sudo growpart "/dev/$(lsblk -no pkname "$root_pv")" "${root_pv##*[!0-9]}"
 sudo lvextend -l +100%FREE /dev/ubuntu-vg/ubuntu-lv
 sudo resize2fs /dev/mapper/ubuntu--vg-ubuntu--lv
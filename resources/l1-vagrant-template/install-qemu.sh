@@ -0,0 +1,31 @@
+#!/bin/bash
+
+set -euxo pipefail
+
+custom_qemu_dir=/home/vagrant/custom-qemu
+install_dir=/opt/custom-qemu
+
+debs=$(find "$custom_qemu_dir" -maxdepth 1 -name '*.deb')
+if [ -n "$debs" ]; then
+    # deb package replaces the system QEMU
+    apt-get install -y --allow-downgrades $debs
+else
+    rm -rf "$install_dir"
+    cp -r "$custom_qemu_dir" "$install_dir"
+    chmod -R a+rx "$install_dir/bin"
+    # allow libvirt and the AppArmor profiles of L2 VMs to run the emulator outside of the default
+    # locations, keeping the security driver enabled
+    if aa-enabled --quiet 2>/dev/null; then
+        rule="$install_dir/** rmix,"
+        for profile in usr.sbin.libvirtd usr.lib.libvirt.virt-aa-helper abstractions/libvirt-qemu; do
+            local_profile="/etc/apparmor.d/local/$profile"
+            mkdir -p "$(dirname "$local_profile")"
+            touch "$local_profile"
+            grep -qxF "$rule" "$local_profile" || echo "$rule" >> "$local_profile"
+        done
+        apparmor_parser -r /etc/apparmor.d/usr.sbin.libvirtd /etc/apparmor.d/usr.lib.libvirt.virt-aa-helper
+    fi
+fi
+systemctl restart libvirtd
+
+"$install_dir/bin/qemu-system-x86_64" --version || qemu-system-x86_64 --version
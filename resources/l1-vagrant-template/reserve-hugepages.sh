@@ -0,0 +1,21 @@
+#!/bin/bash
+
+set -euxo pipefail
+
+size_kb=$1
+count=$2
+default_size_kb=$(awk '/^Hugepagesize:/ {print $2}' /proc/meminfo)
+
+if [ "$size_kb" = "$default_size_kb" ]; then
+    echo "vm.nr_hugepages = $count" > /etc/sysctl.d/90-hugepages.conf
+    sysctl -w vm.nr_hugepages="$count"
+    reserved=$(cat "/sys/kernel/mm/hugepages/hugepages-${size_kb}kB/nr_hugepages")
+    if [ "$reserved" -lt "$count" ]; then
+        echo "only $reserved of $count hugepages are reserved" >&2
+        exit 1
+    fi
+else
+    # libvirt uses the default hugepage size, which can be changed only by kernel parameters
+    echo "GRUB_CMDLINE_LINUX_DEFAULT=\"\$GRUB_CMDLINE_LINUX_DEFAULT default_hugepagesz=${size_kb}K hugepagesz=${size_kb}K hugepages=$count\"" > /etc/default/grub.d/90-hugepages.cfg
+    update-grub
+fi
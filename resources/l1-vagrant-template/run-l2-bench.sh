@@ -1,6 +1,36 @@
 #!/bin/bash
 
-set -euxo pipefail
+set -euo pipefail
 
-ssh l2-vagrant "./run-bench.sh | tee /tmp/bench-results.txt"
-rsync -avr l2-vagrant:/tmp/bench-results.txt bench-results.txt
+# Usage: run-l2-bench.sh <host_name>:<role> [-e NAME=VALUE]... [-C workdir] [-s script] [-- args...]
+# Run the bench script on the L2 VM streaming its stdout and stderr separately.
+# The bench script, run-bench.sh in the home directory by default, is run with the environment
+# variables, working directory and arguments.
+# They are also saved to bench-results/<host_name>.txt and bench-results/<host_name>.stderr.txt.
+target=${1:-l2-vagrant:}
+shift $(($# > 0 ? 1 : 0))
+host=${target%%:*}
+role=${target#*:}
+
+env=("BENCH_ROLE=$role")
+workdir=.
+script=run-bench.sh
+while getopts "e:C:s:" opt; do
+    case $opt in
+        e) env+=("$OPTARG") ;;
+        C) workdir=$OPTARG ;;
+        s) script=$OPTARG ;;
+        *) exit 2 ;;
+    esac
+done
+shift $((OPTIND - 1))
+# quoted again since ssh runs the command with the shell of the L2 VM
+command="cd $(printf '%q' "$workdir") && env $(printf '%q ' "${env[@]}")\"\$HOME\"/$(printf '%q' "$script")"
+if [ $# -gt 0 ]; then
+    command+=" $(printf '%q ' "$@")"
+fi
+
+mkdir -p bench-results
+# stderr of ssh is teed through fd 3 while stdout goes to the outer pipe
+{ ssh "$host" "$command" 2>&1 1>&3 | tee "bench-results/$host.stderr.txt" 1>&2; } 3>&1 \
+    | tee "bench-results/$host.txt"
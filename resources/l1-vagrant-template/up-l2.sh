@@ -0,0 +1,11 @@
+#!/bin/bash
+
+set -euxo pipefail
+
+pushd /home/vagrant/l2-vagrant
+vagrant up --provision
+vagrant ssh-config > ~/.ssh/config
+for host in $(awk '/^Host / {print $2}' ~/.ssh/config); do
+    ssh "vagrant@$host" "echo 'hello world'"
+done
+popd
@@ -0,0 +1,24 @@
+require 'shellwords'
+
+# Define the provisioning steps of the config of a VM with `provision`, which calls the provisioner
+# of the Vagrantfile. Local files of the steps are staged by the launcher.
+def provision_steps(provision, vm_config)
+  (vm_config["provision"] || []).each_with_index do |step, index|
+    name = step["name"] || "provision-#{index + 1}"
+    privileged = step["privileged"] != false
+    reboot = step["reboot"] == true
+    staged = lambda { |path| "./provision/#{vm_config["host_name"]}/#{index}-#{File.basename(path)}" }
+    if step["shell"] != nil then
+      provision.call name, "shell", inline: step["shell"], privileged: privileged, reboot: reboot
+    elsif step["script"] != nil then
+      provision.call name, "shell", path: staged.call(step["script"]), args: step["args"] || [], privileged: privileged, reboot: reboot
+    elsif step["upload"] != nil then
+      provision.call name, "file", source: staged.call(step["upload"]), destination: step["destination"]
+    elsif !(step["packages"] || []).empty? then
+      packages = step["packages"].map { |package| Shellwords.escape(package) }.join(" ")
+      provision.call name, "shell", inline: "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y #{packages}", reboot: reboot
+    else
+      provision.call name, "shell", inline: "true", reboot: reboot
+    end
+  end
+end
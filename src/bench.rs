@@ -0,0 +1,484 @@
+use crate::error::LauncherError;
+use crate::migrate::PROJECT_VERSION;
+use crate::phase::{cancelled, Phase};
+use crate::vagrant::shell_quote;
+use crate::{bench_role_name, BenchRole};
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, info_span, warn};
+
+static ANSI_ESCAPE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\x1B\[([0-9;]+)?[A-Za-z]").unwrap());
+
+// Bench script given by --bench-script, relative to the home directory of L2 VMs
+pub const BENCH_SCRIPT: &str = "run-bench.sh";
+
+// Script in the home directory of L1 VM running the bench script on an L2 VM
+pub const L2_BENCH_SCRIPT: &str = "run-l2-bench.sh";
+
+// Environment variable set by the launcher, which --bench-env must not override
+const BENCH_ROLE_ENV: &str = "BENCH_ROLE";
+
+fn parse_bench_env(env: &str) -> Result<String, String> {
+    let Some((name, _)) = env.split_once('=') else {
+        return Err("must be NAME=VALUE".to_string());
+    };
+    let valid = !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid environment variable name {name}"));
+    }
+    if name == BENCH_ROLE_ENV {
+        return Err(format!("{BENCH_ROLE_ENV} is set by the launcher"));
+    }
+    Ok(env.to_string())
+}
+
+// Arguments, environment variables and working directory of the bench script, recorded in
+// run-metadata.yaml and the metadata file of each output
+#[derive(Debug, Clone, Default, Serialize, Deserialize, clap::Args)]
+pub struct BenchOptions {
+    #[clap(
+        long = "bench-arg",
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        help = "Argument of the bench script, repeated for multiple arguments"
+    )]
+    pub args: Vec<String>,
+    #[clap(
+        long = "bench-env",
+        value_name = "NAME=VALUE",
+        value_parser = parse_bench_env,
+        help = "Environment variable of the bench script, repeated for multiple variables"
+    )]
+    pub env: Vec<String>,
+    #[clap(
+        long = "bench-workdir",
+        value_name = "DIR",
+        help = "Working directory of the bench script in L2 VM, relative to the home directory"
+    )]
+    pub workdir: Option<String>,
+}
+
+// Bench script and its options of an output, written next to it since run-metadata.yaml is
+// overwritten by the next run
+#[derive(Debug, Serialize)]
+struct OutputMetadata<'a> {
+    version: u32,
+    host_name: &'a str,
+    bench_role: Option<BenchRole>,
+    script: &'a str,
+    #[serde(flatten)]
+    options: &'a BenchOptions,
+    started_at: String,
+}
+
+// Where the L2 VM running the bench script is placed
+enum Placement {
+    // L2 VM inside the L1 VM, reached through run-l2-bench.sh in the L1 VM
+    Nested { l1_vagrant_dir: PathBuf },
+    // VM directly on the host
+    NoNested { l2_vagrant_dir: PathBuf },
+}
+
+// L2 VM running the bench script
+pub struct BenchTarget {
+    pub host_name: String,
+    pub bench_role: Option<BenchRole>,
+    placement: Placement,
+}
+
+impl BenchTarget {
+    pub fn nested(l1_vagrant_dir: &Path, host_name: &str, bench_role: Option<BenchRole>) -> Self {
+        Self {
+            host_name: host_name.to_string(),
+            bench_role,
+            placement: Placement::Nested {
+                l1_vagrant_dir: l1_vagrant_dir.to_path_buf(),
+            },
+        }
+    }
+
+    pub fn no_nested(l2_vagrant_dir: &Path, host_name: &str, bench_role: Option<BenchRole>) -> Self {
+        Self {
+            host_name: host_name.to_string(),
+            bench_role,
+            placement: Placement::NoNested {
+                l2_vagrant_dir: l2_vagrant_dir.to_path_buf(),
+            },
+        }
+    }
+
+    // `vagrant ssh` running the script in the L1 VM, or in the VM for no-nested
+    fn vagrant_ssh(&self, script: &str) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new("vagrant");
+        match &self.placement {
+            Placement::Nested { l1_vagrant_dir } => {
+                command.current_dir(l1_vagrant_dir).arg("ssh");
+            }
+            Placement::NoNested { l2_vagrant_dir } => {
+                command.current_dir(l2_vagrant_dir).arg("ssh").arg(&self.host_name);
+            }
+        }
+        command.arg("-c").arg(script);
+        command
+    }
+
+    // Command running the script, a path relative to the home directory of the L2 VM
+    pub fn bench_command(
+        &self,
+        bench_script: &str,
+        options: &BenchOptions,
+    ) -> tokio::process::Command {
+        let role = bench_role_name(self.bench_role);
+        let mut script = match &self.placement {
+            // run-l2-bench.sh takes `<host_name>:<role>` of the L2 VM followed by the options
+            Placement::Nested { .. } => {
+                let mut script = format!("./{L2_BENCH_SCRIPT} {}:{role}", self.host_name);
+                for env in &options.env {
+                    script.push_str(&format!(" -e {}", shell_quote(env)));
+                }
+                if let Some(workdir) = &options.workdir {
+                    script.push_str(&format!(" -C {}", shell_quote(workdir)));
+                }
+                if bench_script != BENCH_SCRIPT {
+                    script.push_str(&format!(" -s {}", shell_quote(bench_script)));
+                }
+                if !options.args.is_empty() {
+                    script.push_str(" --");
+                }
+                script
+            }
+            Placement::NoNested { .. } => {
+                let mut script = String::new();
+                if let Some(workdir) = &options.workdir {
+                    script.push_str(&format!("cd {} && ", shell_quote(workdir)));
+                }
+                script.push_str(&format!("env {BENCH_ROLE_ENV}={role}"));
+                for env in &options.env {
+                    script.push_str(&format!(" {}", shell_quote(env)));
+                }
+                script.push_str(&format!(" \"$HOME\"/{}", shell_quote(bench_script)));
+                script
+            }
+        };
+        for arg in &options.args {
+            script.push_str(&format!(" {}", shell_quote(arg)));
+        }
+        self.vagrant_ssh(&script)
+    }
+
+    // Contents of the metadata file of the output of the target
+    pub fn output_metadata(
+        &self,
+        script: &str,
+        options: &BenchOptions,
+    ) -> Result<String, anyhow::Error> {
+        Ok(serde_yaml::to_string(&OutputMetadata {
+            version: PROJECT_VERSION,
+            host_name: &self.host_name,
+            bench_role: self.bench_role,
+            script,
+            options,
+            started_at: timestamp(),
+        })?)
+    }
+
+    fn stop_command(&self, script: &str) -> tokio::process::Command {
+        self.vm_command(&format!("pkill -f {}", shell_quote(&stop_pattern(script))))
+    }
+
+    // Command running the script in the L2 VM
+    pub fn vm_command(&self, script: &str) -> tokio::process::Command {
+        match &self.placement {
+            Placement::Nested { .. } => {
+                self.vagrant_ssh(&format!("ssh {} {}", self.host_name, shell_quote(script)))
+            }
+            Placement::NoNested { .. } => self.vagrant_ssh(script),
+        }
+    }
+
+    pub fn l1_vagrant_dir(&self) -> Option<&Path> {
+        match &self.placement {
+            Placement::Nested { l1_vagrant_dir } => Some(l1_vagrant_dir),
+            Placement::NoNested { .. } => None,
+        }
+    }
+}
+
+// Pattern of pkill matching the script. The first letter or digit is put in brackets such as
+// `[r]un-bench.sh` so that the pattern does not match the shells running pkill.
+fn stop_pattern(script: &str) -> String {
+    let bracketed = script.find(|c: char| c.is_ascii_alphanumeric());
+    script
+        .char_indices()
+        .map(|(i, c)| {
+            if Some(i) == bracketed {
+                format!("[{c}]")
+            } else if ".[]()*+?{}|^$\\".contains(c) {
+                format!("\\{c}")
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+// Output file of each L2 VM. `<stem>-<host_name>.<ext>` is used if there are multiple L2 VMs.
+pub fn l2_output_path(output_path: &Path, host_name: &str, multiple_vms: bool) -> PathBuf {
+    if !multiple_vms {
+        return output_path.to_path_buf();
+    }
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match output_path.extension() {
+        Some(ext) => format!("{stem}-{host_name}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{host_name}"),
+    };
+    output_path.with_file_name(file_name)
+}
+
+// File of stderr outputs next to the output file, `<stem>.stderr.<ext>`
+pub fn stderr_output_path(output_path: &Path) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match output_path.extension() {
+        Some(ext) => format!("{stem}.stderr.{}", ext.to_string_lossy()),
+        None => format!("{stem}.stderr"),
+    };
+    output_path.with_file_name(file_name)
+}
+
+// File of the metadata of the output next to it, `<stem>.metadata.yaml`
+pub fn metadata_output_path(output_path: &Path) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    output_path.with_file_name(format!("{stem}.metadata.yaml"))
+}
+
+// Current time in UTC such as `2023-11-01T12:34:56.789Z`
+pub fn timestamp() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+// Run a command logging its outputs line by line in a span of the label, so that they go to the
+// log file of the run and follow --log-format. Stdout is also written to stdout_path as is, since
+// it is the result read by the parsers of the suites and other tools, and stderr to stderr_path
+// with timestamps. Both outputs are logged with timestamps. ANSI escape sequences are removed from
+// every output.
+pub async fn stream_command_output(
+    mut command: tokio::process::Command,
+    label: &str,
+    stdout_path: Option<&Path>,
+    stderr_path: Option<&Path>,
+) -> Result<ExitStatus, anyhow::Error> {
+    let mut stdout_file = stdout_path.map(File::create).transpose()?;
+    let mut stderr_file = stderr_path.map(File::create).transpose()?;
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut child_stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut child_stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+    let span = info_span!("bench", vm = %label);
+
+    // read until both outputs are closed so that the last lines are not lost
+    let (mut stdout_closed, mut stderr_closed) = (false, false);
+    while !stdout_closed || !stderr_closed {
+        tokio::select! {
+            res = child_stdout.next_line(), if !stdout_closed => {
+                let Some(line) = res? else {
+                    stdout_closed = true;
+                    continue;
+                };
+                let line = ANSI_ESCAPE_PATTERN.replace_all(&line, "");
+                span.in_scope(|| info!(target: "bench", "{line}"));
+                if let Some(file) = &mut stdout_file {
+                    writeln!(file, "{line}")?;
+                }
+            }
+            res = child_stderr.next_line(), if !stderr_closed => {
+                let Some(line) = res? else {
+                    stderr_closed = true;
+                    continue;
+                };
+                let line = ANSI_ESCAPE_PATTERN.replace_all(&line, "");
+                span.in_scope(|| info!(target: "bench", stream = "stderr", "{line}"));
+                if let Some(file) = &mut stderr_file {
+                    writeln!(file, "{} {line}", timestamp())?;
+                }
+            }
+        }
+    }
+
+    Ok(child.wait().await?)
+}
+
+// Stop the bench script remaining in the target
+async fn stop_bench(target: &BenchTarget, script: &str) {
+    match target.stop_command(script).status().await {
+        // pkill exits with 1 if the script already finished
+        Ok(status) if status.success() || status.code() == Some(1) => {}
+        Ok(status) => warn!(
+            "stopping bench script of {} failed with status: {status}",
+            target.host_name
+        ),
+        Err(err) => warn!(
+            "stopping bench script of {} failed: {err}",
+            target.host_name
+        ),
+    }
+}
+
+// Run the bench script on every target concurrently. Aggressors are stopped once all the other
+// targets finish, and every target is stopped if the bench times out or is interrupted.
+pub async fn run_bench_targets(
+    targets: &[BenchTarget],
+    script: &str,
+    options: &BenchOptions,
+    output_path: Option<&PathBuf>,
+    timeout: Option<Duration>,
+) -> Result<(), anyhow::Error> {
+    let multiple_vms = targets.len() > 1;
+    let mut measured_tasks = vec![];
+    let mut aggressor_tasks = vec![];
+    for target in targets {
+        let output_path = output_path
+            .map(|output_path| l2_output_path(output_path, &target.host_name, multiple_vms));
+        let command = target.bench_command(script, options);
+        let host_name = target.host_name.clone();
+        let task = tokio::spawn(async move {
+            let stderr_path = output_path.as_deref().map(stderr_output_path);
+            let status = stream_command_output(
+                command,
+                &host_name,
+                output_path.as_deref(),
+                stderr_path.as_deref(),
+            )
+            .await?;
+            Ok::<_, anyhow::Error>((status, output_path))
+        });
+        if target.bench_role == Some(BenchRole::Aggressor) {
+            aggressor_tasks.push((target, task));
+        } else {
+            measured_tasks.push((target, task));
+        }
+    }
+    let wait_measured = async {
+        let mut results = vec![];
+        for (target, task) in measured_tasks {
+            results.push((&target.host_name, task.await?));
+        }
+        Ok::<_, anyhow::Error>(results)
+    };
+    let results = tokio::select! {
+        results = wait_measured => results?,
+        err = cancelled(Phase::Bench, timeout) => {
+            // killing local `vagrant ssh` leaves the bench script running in L2 VMs
+            warn!("{err}, stopping bench scripts");
+            for target in targets {
+                stop_bench(target, script).await;
+            }
+            return Err(err.into());
+        }
+    };
+
+    for (target, task) in aggressor_tasks {
+        stop_bench(target, script).await;
+        let _ = task.await;
+    }
+
+    for (host_name, result) in results {
+        let (status, output_path) = result?;
+        if !status.success() {
+            return Err(LauncherError::bench(anyhow!(
+                "running bench script on {host_name} failed with status: {}",
+                status.code().unwrap_or(-1)
+            )));
+        }
+        if let Some(output_path) = output_path {
+            info!(
+                "bench results written to {}",
+                output_path.to_str().unwrap_or("file")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_pattern_does_not_match_pkill() {
+        for script in ["run-bench.sh", "bench-suite/fio.v2/fio.sh", "./a+b.sh"] {
+            let pattern = Regex::new(&stop_pattern(script)).unwrap();
+            assert!(pattern.is_match(&format!("/bin/bash /home/vagrant/{script} --size 1G")));
+            let pkill = format!("bash -c pkill -f {}", shell_quote(&stop_pattern(script)));
+            assert!(!pattern.is_match(&pkill), "{pkill}");
+        }
+        assert!(!Regex::new(&stop_pattern("fio.sh")).unwrap().is_match("fioxsh"));
+    }
+
+    #[tokio::test]
+    async fn outputs_are_streamed_to_files() {
+        let dir = std::env::temp_dir().join(format!("marigold-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (stdout_path, stderr_path) = (dir.join("out.txt"), dir.join("out.stderr.txt"));
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo result; printf '\\033[1;31merror\\033[0m\\n' >&2; printf 'last'; exit 3");
+        let status = stream_command_output(command, "vm1", Some(&stdout_path), Some(&stderr_path))
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        // stdout is kept as is, including the last line without a newline
+        assert_eq!(std::fs::read_to_string(&stdout_path).unwrap(), "result\nlast\n");
+        let stderr = std::fs::read_to_string(&stderr_path).unwrap();
+        let (time, line) = stderr.trim_end().split_once(' ').unwrap();
+        assert_eq!(line, "error");
+        assert!(time.ends_with('Z') && time.len() == "2023-11-01T12:34:56.789Z".len(), "{stderr}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
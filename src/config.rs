@@ -0,0 +1,804 @@
+use crate::schema::{self, Property, Schema};
+use anyhow::{anyhow, Context};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// path of the user-level config, `~/.config/marigold/config.yaml` by default
+const USER_CONFIG_ENV: &str = "MARIGOLD_USER_CONFIG";
+
+// project-level config placed in the project directory
+const PROJECT_CONFIG_FILE: &str = "marigold.yaml";
+
+// `${VAR}` or `${VAR:-default}` in string values, `$${VAR}` being a literal `${VAR}` such as a
+// variable of a provision shell command
+static INTERPOLATION_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    L1,
+    L2,
+}
+
+impl Level {
+    // section of the level in the user and project configs, and the prefix of `--set` keys
+    fn section(self) -> &'static str {
+        match self {
+            Level::L1 => "l1",
+            Level::L2 => "l2",
+        }
+    }
+
+    // Schema of a config file of the level, which may have `extends` and, for L2, `count`
+    fn file_schema(self, schema: Schema) -> Schema {
+        let mut properties = vec![Property::new(
+            "extends",
+            Schema::Paths,
+            "Config files merged under this one, relative to this file",
+        )];
+        if self == Level::L2 {
+            properties.push(Property::new(
+                "count",
+                Schema::integer(1),
+                "Number of copies of this VM, `{index}` in host_name is replaced with 1, 2, ...",
+            ));
+        }
+        schema.with_properties(properties)
+    }
+}
+
+// VM config of a level, loaded from the layers over its defaults
+pub trait LayeredConfig: Serialize + DeserializeOwned + Default {
+    fn schema() -> Schema;
+}
+
+// Schema of a config file given with `--l1-config` or `--l2-config`
+pub fn file_schema<T: LayeredConfig>(level: Level) -> Schema {
+    let schema = level.file_schema(T::schema());
+    match level {
+        Level::L1 => schema,
+        Level::L2 => Schema::OneOrMany(Box::new(schema)),
+    }
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConfigOverrides {
+    #[clap(
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "Override a config value such as l1.cpus=4 or l1.kvm_options.nested=Y"
+    )]
+    set: Vec<String>,
+}
+
+// Config value merged from layers, remembering which layer set each value
+#[derive(Debug, Clone)]
+pub struct Layered {
+    value: Value,
+    // dotted key of each value to its source
+    sources: BTreeMap<String, String>,
+}
+
+fn key_name(key: &Value) -> String {
+    match key.as_str() {
+        Some(key) => key.to_string(),
+        None => serde_yaml::to_string(key).unwrap_or_default().trim().to_string(),
+    }
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+// Replace `${VAR}` in the strings of the value, returning the variables used
+fn interpolate(value: &mut Value, vars: &mut Vec<String>) -> Result<(), anyhow::Error> {
+    match value {
+        Value::String(text) => {
+            let mut interpolated = String::new();
+            let mut last = 0;
+            let mut whole = false;
+            for captures in INTERPOLATION_PATTERN.captures_iter(text) {
+                let matched = captures.get(0).unwrap();
+                interpolated.push_str(&text[last..matched.start()]);
+                last = matched.end();
+                if !captures[1].is_empty() {
+                    interpolated.push_str(&matched.as_str()[1..]);
+                    continue;
+                }
+                let var = &captures[2];
+                let var_value = match (std::env::var(var), captures.get(3)) {
+                    (Ok(var_value), _) => var_value,
+                    (Err(_), Some(default)) => default.as_str().to_string(),
+                    (Err(_), None) => {
+                        return Err(anyhow!("environment variable {var} is not set"))
+                    }
+                };
+                interpolated.push_str(&var_value);
+                vars.push(var.to_string());
+                whole = matched.range() == (0..text.len());
+            }
+            if last == 0 {
+                return Ok(());
+            }
+            interpolated.push_str(&text[last..]);
+            // `cpus: ${CPUS}` is a number as if it were written in the file
+            *value = if whole {
+                serde_yaml::from_str(&interpolated).unwrap_or(Value::String(interpolated))
+            } else {
+                Value::String(interpolated)
+            };
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                interpolate(value, vars)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                interpolate(value, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Interpolate the values and record the source of each of them
+fn annotate(
+    value: &mut Value,
+    path: &str,
+    source: &str,
+    sources: &mut BTreeMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    if let Value::Mapping(mapping) = value {
+        if !mapping.is_empty() {
+            for (key, value) in mapping.iter_mut() {
+                annotate(value, &join_key(path, &key_name(key)), source, sources)?;
+            }
+            return Ok(());
+        }
+    }
+    let mut vars = vec![];
+    interpolate(value, &mut vars).with_context(|| format!("invalid value of {path}"))?;
+    let source = if vars.is_empty() {
+        source.to_string()
+    } else {
+        format!("{source} (${{{}}})", vars.join("}, ${"))
+    };
+    sources.insert(path.to_string(), source);
+    Ok(())
+}
+
+fn merge_value(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_value(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Leaves of the value with their dotted keys, an empty mapping being a leaf
+fn leaves<'a>(value: &'a Value, path: &str, entries: &mut Vec<(String, &'a Value)>) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, value) in mapping {
+                leaves(value, &join_key(path, &key_name(key)), entries);
+            }
+        }
+        _ => entries.push((path.to_string(), value)),
+    }
+}
+
+impl Layered {
+    fn new(mut value: Value, source: &str) -> Result<Self, anyhow::Error> {
+        if !value.is_mapping() {
+            return Err(anyhow!("{source} must be a mapping"));
+        }
+        let mut sources = BTreeMap::new();
+        annotate(&mut value, "", source, &mut sources)?;
+        Ok(Self { value, sources })
+    }
+
+    fn merge(&mut self, overlay: Layered) {
+        merge_value(&mut self.value, overlay.value);
+        self.sources.extend(overlay.sources);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        self.sources.remove(key);
+        self.value.as_mapping_mut()?.remove(key)
+    }
+
+    // Move the values of deprecated keys of the schema to the keys replacing them
+    fn rename_deprecated(&mut self, schema: &Schema) -> Result<(), anyhow::Error> {
+        for (old, new) in schema.renamed() {
+            let Some(mapping) = self.value.as_mapping_mut() else {
+                return Ok(());
+            };
+            let Some(value) = mapping.remove(old) else {
+                continue;
+            };
+            if mapping.contains_key(new) {
+                return Err(anyhow!("{old} is deprecated and conflicts with {new}"));
+            }
+            mapping.insert(Value::from(new), value);
+            let sources = std::mem::take(&mut self.sources);
+            self.sources = sources
+                .into_iter()
+                .map(|(key, source)| match key.strip_prefix(old) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('.') => {
+                        (format!("{new}{rest}"), source)
+                    }
+                    _ => (key, source),
+                })
+                .collect();
+            let source = self.sources.get(new).map(String::as_str).unwrap_or("config");
+            warn!("{old} in {source} is deprecated, use {new}");
+        }
+        Ok(())
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, anyhow::Error> {
+        Ok(serde_yaml::from_value(self.value.clone())?)
+    }
+
+    pub fn host_name(&self) -> Option<&str> {
+        self.value.get("host_name").and_then(Value::as_str)
+    }
+
+    // Values with their dotted keys and sources, in the order of the merged config
+    pub fn entries(&self) -> Vec<(String, &Value, &str)> {
+        let mut entries = vec![];
+        leaves(&self.value, "", &mut entries);
+        entries
+            .into_iter()
+            .map(|(key, value)| {
+                let source = self.sources.get(&key).map(String::as_str).unwrap_or("");
+                (key, value, source)
+            })
+            .collect()
+    }
+}
+
+// Parse the file after checking it against the schema, so that unknown keys and values of wrong
+// types are reported with their line and column
+fn read_file(path: &Path, schema: &Schema) -> Result<Value, anyhow::Error> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    schema::validate_yaml(&text, schema)
+        .map_err(|err| anyhow!("invalid config {}: {err}", path.display()))?;
+    serde_yaml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+// Load the file with the files it extends merged under it. `extends` is a path or a list of
+// paths relative to the file.
+fn load_file(path: &Path, schema: &Schema, stack: &mut Vec<PathBuf>) -> Result<Layered, anyhow::Error> {
+    let value = read_file(path, schema)?;
+    resolve_extends(value, path, schema, stack)
+}
+
+fn resolve_extends(
+    value: Value,
+    path: &Path,
+    schema: &Schema,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Layered, anyhow::Error> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        return Err(anyhow!(
+            "extends of {} is circular: {}",
+            path.display(),
+            stack
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        ));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut layer = Layered::new(value, &path.display().to_string())
+        .and_then(|mut layer| {
+            layer.rename_deprecated(schema)?;
+            Ok(layer)
+        })
+        .with_context(|| format!("invalid config {}", path.display()))?;
+    schema::resolve_paths(&mut layer.value, schema, dir);
+    let Some(extends) = layer.remove("extends") else {
+        return Ok(layer);
+    };
+    let bases = match extends {
+        Value::String(base) => vec![base],
+        Value::Sequence(bases) => bases
+            .into_iter()
+            .map(|base| match base {
+                Value::String(base) => Ok(base),
+                _ => Err(anyhow!("extends of {} must be paths", path.display())),
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(anyhow!("extends of {} must be a path or a list of paths", path.display())),
+    };
+
+    stack.push(canonical);
+    let mut merged: Option<Layered> = None;
+    for base in bases {
+        let base = load_file(&dir.join(base), schema, stack)?;
+        match &mut merged {
+            Some(merged) => merged.merge(base),
+            None => merged = Some(base),
+        }
+    }
+    stack.pop();
+
+    let mut merged = merged.unwrap_or(Layered {
+        value: Value::Mapping(Mapping::new()),
+        sources: BTreeMap::new(),
+    });
+    merged.merge(layer);
+    Ok(merged)
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(USER_CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) => PathBuf::from(config_home),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("marigold/config.yaml"))
+}
+
+// Config of a level in the user or project config, which has `l1` and `l2` sections. Only the
+// section of the level is checked against the schema.
+fn load_section(
+    path: &Path,
+    level: Level,
+    schema: &Schema,
+    label: &str,
+) -> Result<Option<Layered>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let section_schema = |section: Level| {
+        if section == level {
+            level.file_schema(schema.clone())
+        } else {
+            Schema::Any
+        }
+    };
+    let config_schema = Schema::object(vec![
+        Property::new("l1", section_schema(Level::L1), "L1 VM config"),
+        Property::new("l2", section_schema(Level::L2), "L2 VM config"),
+    ]);
+    let mut value = read_file(path, &config_schema)?;
+    let Some(section) = value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(level.section()))
+    else {
+        return Ok(None);
+    };
+    let mut layer = Layered::new(section, &format!("{label} {}", path.display()))
+        .and_then(|mut layer| {
+            layer.rename_deprecated(schema)?;
+            Ok(layer)
+        })
+        .with_context(|| format!("invalid {} section of {}", level.section(), path.display()))?;
+    schema::resolve_paths(&mut layer.value, schema, path.parent().unwrap_or(Path::new(".")));
+    // extends in the section is resolved relative to the config
+    if let Some(extends) = layer.remove("extends") {
+        let mut extended = Mapping::new();
+        extended.insert(Value::from("extends"), extends);
+        let mut merged = resolve_extends(Value::Mapping(extended), path, schema, &mut vec![])?;
+        merged.merge(layer);
+        layer = merged;
+    }
+    Ok(Some(layer))
+}
+
+// `--set` override such as `l2.cpus=4`, the value being parsed as YAML
+#[derive(Debug)]
+struct Override {
+    arg: String,
+    level: Level,
+    layer: Layered,
+}
+
+fn parse_override(arg: &str) -> Result<Override, anyhow::Error> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("invalid --set {arg}: expected KEY=VALUE"))?;
+    let mut keys = key.split('.');
+    let level = match keys.next() {
+        Some("l1") => Level::L1,
+        Some("l2") => Level::L2,
+        _ => return Err(anyhow!("invalid --set {arg}: key must start with l1. or l2.")),
+    };
+    let keys: Vec<&str> = keys.collect();
+    if keys.is_empty() || keys.iter().any(|key| key.is_empty()) {
+        return Err(anyhow!("invalid --set {arg}: key must be like l1.cpus"));
+    }
+    let mut value: Value = serde_yaml::from_str(value)
+        .with_context(|| format!("invalid --set {arg}: failed to parse the value"))?;
+    for key in keys.iter().rev() {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::from(*key), value);
+        value = Value::Mapping(mapping);
+    }
+    let mut sources = BTreeMap::new();
+    annotate(&mut value, "", &format!("--set {arg}"), &mut sources)?;
+    Ok(Override {
+        arg: arg.to_string(),
+        level,
+        layer: Layered { value, sources },
+    })
+}
+
+// Copies of the L2 VM for `count`, whose host_name may contain `{index}`
+fn expand_count(mut layer: Layered) -> Result<Vec<Layered>, anyhow::Error> {
+    let Some(count) = layer.remove("count") else {
+        return Ok(vec![layer]);
+    };
+    let count = count
+        .as_u64()
+        .ok_or_else(|| anyhow!("count must be a positive integer"))?;
+    let host_name = layer.host_name().unwrap_or_default().to_string();
+    Ok((1..=count)
+        .map(|index| {
+            let mut vm = layer.clone();
+            let indexed = if host_name.contains("{index}") {
+                host_name.replace("{index}", &index.to_string())
+            } else {
+                format!("{host_name}-{index}")
+            };
+            if let Some(mapping) = vm.value.as_mapping_mut() {
+                mapping.insert(Value::from("host_name"), Value::from(indexed));
+            }
+            vm
+        })
+        .collect())
+}
+
+// Layers of configs, from the lowest: built-in defaults, the user config, the project config, the
+// config file given with its extends, and `--set` overrides
+pub struct ConfigLoader {
+    project_dir: Option<PathBuf>,
+    overrides: Vec<Override>,
+}
+
+impl ConfigLoader {
+    pub fn new(
+        project_dir: Option<&Path>,
+        overrides: &ConfigOverrides,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            project_dir: project_dir.map(Path::to_path_buf),
+            overrides: overrides
+                .set
+                .iter()
+                .map(|arg| parse_override(arg))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Whether `--set` overrides the config of the level
+    pub fn overrides(&self, level: Level) -> bool {
+        self.overrides.iter().any(|o| o.level == level)
+    }
+
+    fn base<T: LayeredConfig>(&self, level: Level) -> Result<Layered, anyhow::Error> {
+        let schema = T::schema();
+        let mut layer = Layered::new(serde_yaml::to_value(T::default())?, "default")?;
+        if let Some(path) = user_config_path() {
+            if let Some(user) = load_section(&path, level, &schema, "user config")? {
+                layer.merge(user);
+            }
+        }
+        if let Some(project_dir) = &self.project_dir {
+            let path = project_dir.join(PROJECT_CONFIG_FILE);
+            if let Some(project) = load_section(&path, level, &schema, "project config")? {
+                layer.merge(project);
+            }
+        }
+        Ok(layer)
+    }
+
+    fn apply_overrides<T: LayeredConfig>(
+        &self,
+        level: Level,
+        layer: &mut Layered,
+    ) -> Result<(), anyhow::Error> {
+        let schema = T::schema();
+        for o in self.overrides.iter().filter(|o| o.level == level) {
+            schema::validate_value(&o.layer.value, &schema)
+                .map_err(|err| anyhow!("invalid --set {}: {err}", o.arg))?;
+            let mut overlay = o.layer.clone();
+            overlay
+                .rename_deprecated(&schema)
+                .map_err(|err| anyhow!("invalid --set {}: {err}", o.arg))?;
+            layer.merge(overlay);
+        }
+        Ok(())
+    }
+
+    // Config generated for the project with `--set` overrides, changed by provision
+    pub fn with_overrides<T: LayeredConfig>(
+        &self,
+        level: Level,
+        current: &T,
+    ) -> Result<Layered, anyhow::Error> {
+        let mut layer = Layered::new(serde_yaml::to_value(current)?, "current config")?;
+        self.apply_overrides::<T>(level, &mut layer)?;
+        Ok(layer)
+    }
+
+    pub fn l1<T: LayeredConfig>(&self, path: Option<&Path>) -> Result<Layered, anyhow::Error> {
+        let mut layer = self.base::<T>(Level::L1)?;
+        if let Some(path) = path {
+            layer.merge(load_file(path, &file_schema::<T>(Level::L1), &mut vec![])?);
+        }
+        self.apply_overrides::<T>(Level::L1, &mut layer)?;
+        Ok(layer)
+    }
+
+    // L2 config file is a single VM, a list of VMs, or a VM with `count`. The other layers apply
+    // to every VM.
+    pub fn l2<T: LayeredConfig>(&self, path: Option<&Path>) -> Result<Vec<Layered>, anyhow::Error> {
+        let base = self.base::<T>(Level::L2)?;
+        let files = match path {
+            Some(path) => {
+                let vm_schema = Level::L2.file_schema(T::schema());
+                match read_file(path, &file_schema::<T>(Level::L2))? {
+                    Value::Sequence(vms) => vms
+                        .into_iter()
+                        .map(|vm| resolve_extends(vm, path, &vm_schema, &mut vec![]))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    value => vec![resolve_extends(value, path, &vm_schema, &mut vec![])?],
+                }
+            }
+            None => vec![],
+        };
+        let vms = if files.is_empty() {
+            vec![base]
+        } else {
+            files
+                .into_iter()
+                .map(|file| {
+                    let mut vm = base.clone();
+                    vm.merge(file);
+                    vm
+                })
+                .collect()
+        };
+
+        let mut expanded = vec![];
+        for mut vm in vms {
+            self.apply_overrides::<T>(Level::L2, &mut vm)?;
+            expanded.extend(expand_count(vm)?);
+        }
+        Ok(expanded)
+    }
+}
+
+// Print the merged config as `key: value  # source` lines
+pub fn print_layered(title: &str, layer: &Layered) {
+    println!("{title}:");
+    let lines: Vec<(String, &str)> = layer
+        .entries()
+        .into_iter()
+        .map(|(key, value, source)| {
+            let value = serde_json::to_string(value).unwrap_or_default();
+            (format!("  {key}: {value}"), source)
+        })
+        .collect();
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in lines {
+        println!("{line:width$}  # {source}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestConfig {
+        host_name: String,
+        cpus: u32,
+        memory: u64,
+        disk_size: Option<u64>,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                host_name: "vm".to_string(),
+                cpus: 1,
+                memory: 1024,
+                disk_size: None,
+            }
+        }
+    }
+
+    impl LayeredConfig for TestConfig {
+        fn schema() -> Schema {
+            Schema::object(vec![
+                Property::new("host_name", Schema::String, "Host name"),
+                Property::new("cpus", Schema::integer(1), "CPUs"),
+                Property::new("memory", Schema::integer(1), "Memory"),
+                Property::new("disk_size", Schema::integer(1).nullable(), "Disk size"),
+            ])
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("marigold-config-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    fn source<'a>(layer: &'a Layered, key: &str) -> &'a str {
+        layer.sources.get(key).map(String::as_str).unwrap_or("")
+    }
+
+    // The only test changing the user config, as the environment is shared by the tests
+    #[test]
+    fn layers_are_merged_in_order_of_precedence() {
+        let dir = temp_dir("precedence");
+        let user = dir.join("user.yaml");
+        fs::write(&user, "l1:\n  cpus: 2\n  memory: 2048\n  disk_size: 10\n").unwrap();
+        fs::write(dir.join(PROJECT_CONFIG_FILE), "l1:\n  cpus: 3\n  memory: 3072\n").unwrap();
+        let file = dir.join("l1.yaml");
+        fs::write(&file, "cpus: 4\n").unwrap();
+        std::env::set_var(USER_CONFIG_ENV, &user);
+
+        let overrides = ConfigOverrides {
+            set: vec!["l1.host_name=override".to_string()],
+        };
+        let loader = ConfigLoader::new(Some(&dir), &overrides).unwrap();
+        let layer = loader.l1::<TestConfig>(Some(&file)).unwrap();
+        let config: TestConfig = layer.deserialize().unwrap();
+        assert_eq!(config.host_name, "override");
+        assert_eq!(config.cpus, 4);
+        assert_eq!(config.memory, 3072);
+        assert_eq!(config.disk_size, Some(10));
+
+        assert_eq!(source(&layer, "host_name"), "--set l1.host_name=override");
+        assert_eq!(source(&layer, "cpus"), file.display().to_string());
+        assert_eq!(
+            source(&layer, "memory"),
+            format!("project config {}", dir.join(PROJECT_CONFIG_FILE).display())
+        );
+        assert_eq!(source(&layer, "disk_size"), format!("user config {}", user.display()));
+
+        // the sections of the other level are not applied
+        let layers = loader.l2::<TestConfig>(None).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(source(&layers[0], "cpus"), "default");
+    }
+
+    #[test]
+    fn merge_value_overlays_mappings() {
+        let mut base = yaml("a: 1\nb:\n  c: 2\n  d: 3\ne: [1, 2]\n");
+        merge_value(&mut base, yaml("b:\n  d: 4\ne: [3]\nf: 5\n"));
+        assert_eq!(base, yaml("a: 1\nb:\n  c: 2\n  d: 4\ne: [3]\nf: 5\n"));
+    }
+
+    #[test]
+    fn extends_is_relative_to_the_file() {
+        let dir = temp_dir("extends");
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::write(dir.join("base/base.yaml"), "cpus: 2\nmemory: 2048\n").unwrap();
+        let file = dir.join("vm.yaml");
+        fs::write(&file, "extends: base/base.yaml\ncpus: 4\n").unwrap();
+        let schema = Level::L1.file_schema(TestConfig::schema());
+        let layer = load_file(&file, &schema, &mut vec![]).unwrap();
+        assert_eq!(layer.value, yaml("cpus: 4\nmemory: 2048\n"));
+        assert_eq!(source(&layer, "cpus"), file.display().to_string());
+        assert_eq!(
+            source(&layer, "memory"),
+            dir.join("base/base.yaml").display().to_string()
+        );
+    }
+
+    #[test]
+    fn circular_extends_is_rejected() {
+        let dir = temp_dir("circular");
+        fs::write(dir.join("a.yaml"), "extends: b.yaml\ncpus: 2\n").unwrap();
+        fs::write(dir.join("b.yaml"), "extends: a.yaml\n").unwrap();
+        let schema = Level::L1.file_schema(TestConfig::schema());
+        let err = load_file(&dir.join("a.yaml"), &schema, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("is circular"), "{err}");
+    }
+
+    #[test]
+    fn interpolate_replaces_variables() {
+        std::env::set_var("MARIGOLD_TEST_CPUS", "4");
+        let mut value = yaml("cpus: ${MARIGOLD_TEST_CPUS}\nname: vm-${MARIGOLD_TEST_CPUS}\n");
+        let mut vars = vec![];
+        interpolate(&mut value, &mut vars).unwrap();
+        // a whole value is parsed as YAML
+        assert_eq!(value, yaml("cpus: 4\nname: vm-4\n"));
+        assert_eq!(vars, ["MARIGOLD_TEST_CPUS", "MARIGOLD_TEST_CPUS"]);
+
+        let mut value = yaml("cpus: ${MARIGOLD_TEST_UNSET:-2}\n");
+        interpolate(&mut value, &mut vec![]).unwrap();
+        assert_eq!(value, yaml("cpus: 2\n"));
+    }
+
+    #[test]
+    fn interpolate_keeps_escaped_variables() {
+        let mut value = Value::from("echo $${MARIGOLD_TEST_UNSET} $${HOME:-/root}");
+        let mut vars = vec![];
+        interpolate(&mut value, &mut vars).unwrap();
+        assert_eq!(value, Value::from("echo ${MARIGOLD_TEST_UNSET} ${HOME:-/root}"));
+        assert!(vars.is_empty());
+    }
+
+    #[test]
+    fn interpolate_rejects_unset_variable_without_default() {
+        let mut value = Value::from("${MARIGOLD_TEST_UNSET}");
+        let err = interpolate(&mut value, &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "environment variable MARIGOLD_TEST_UNSET is not set");
+    }
+
+    #[test]
+    fn parse_override_builds_nested_value() {
+        let o = parse_override("l2.kvm_options.nested=Y").unwrap();
+        assert_eq!(o.level, Level::L2);
+        assert_eq!(o.layer.value, yaml("kvm_options:\n  nested: Y\n"));
+        assert_eq!(source(&o.layer, "kvm_options.nested"), "--set l2.kvm_options.nested=Y");
+
+        let o = parse_override("l1.cpus=4").unwrap();
+        assert_eq!(o.layer.value, yaml("cpus: 4\n"));
+
+        for arg in ["l1.cpus", "l3.cpus=4", "l1=4", "l1..cpus=4"] {
+            assert!(parse_override(arg).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    fn expand_count_names_copies() {
+        let layer = Layered::new(yaml("host_name: vm-{index}-x\ncount: 2\n"), "file").unwrap();
+        let names: Vec<_> = expand_count(layer)
+            .unwrap()
+            .iter()
+            .map(|vm| vm.host_name().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["vm-1-x", "vm-2-x"]);
+
+        let layer = Layered::new(yaml("host_name: vm\ncount: 2\n"), "file").unwrap();
+        let vms = expand_count(layer).unwrap();
+        assert_eq!(vms[1].host_name(), Some("vm-2"));
+        assert!(vms[1].value.get("count").is_none());
+
+        let layer = Layered::new(yaml("host_name: vm\n"), "file").unwrap();
+        assert_eq!(expand_count(layer).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,632 @@
+mod api;
+
+use crate::config::ConfigLoader;
+use crate::phase;
+use crate::registry::{self, ProjectEntry, Registry};
+use crate::suite;
+use crate::{
+    load_l1_config, load_l2_configs, Args, CancelArgs, Command, DaemonArgs, QueueArgs, SubmitArgs,
+};
+use anyhow::anyhow;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use strum_macros::Display;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+const DAEMON_SOCKET: &str = "daemon.sock";
+const QUEUE_FILE: &str = "queue.yaml";
+// interval of checking finished jobs and starting queued jobs
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Experiment submitted to the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    name: Option<String>,
+    // arguments of the launcher such as [run-bench, --bench-script, ./run-bench.sh]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Job {
+    id: u64,
+    name: Option<String>,
+    args: Vec<String>,
+    // directory of the manifest, where the job runs and relative paths are resolved
+    working_dir: PathBuf,
+    state: JobState,
+    pid: Option<u32>,
+    exit_code: Option<i32>,
+    message: Option<String>,
+    // records of the runs of a bench suite job, given to the job by the daemon unless the
+    // manifest sets --suite-results
+    suite_results: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueFile {
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+enum Request {
+    Submit {
+        manifest: Manifest,
+        working_dir: PathBuf,
+    },
+    Queue,
+    Cancel {
+        id: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "kebab-case")]
+enum Response {
+    Submitted { id: u64 },
+    Queue { jobs: Vec<Job> },
+    Cancelled { id: u64 },
+    Error { message: String },
+}
+
+// Project and host resources a job occupies while it runs
+#[derive(Debug, Clone)]
+struct JobDemand {
+    project_dir: PathBuf,
+    cpus: u32,
+    memory: u64,
+}
+
+fn state_dir(state_dir: Option<PathBuf>) -> PathBuf {
+    if let Some(state_dir) = state_dir {
+        return state_dir;
+    }
+    match std::env::var_os("XDG_STATE_HOME") {
+        Some(state_home) => PathBuf::from(state_home).join("marigold"),
+        None => PathBuf::from(std::env::var_os("HOME").unwrap_or_default())
+            .join(".local/state/marigold"),
+    }
+}
+
+fn parse_job_args(args: &[String]) -> Result<Args, anyhow::Error> {
+    Args::try_parse_from(std::iter::once("launcher").chain(args.iter().map(String::as_str)))
+        .map_err(|err| anyhow!("invalid job args: {err}"))
+}
+
+fn job_demand(job: &Job) -> Result<JobDemand, anyhow::Error> {
+    let resolve = |path: Option<PathBuf>| {
+        let path = match path {
+            Some(path) => job.working_dir.join(path),
+            None => job.working_dir.clone(),
+        };
+        // components() drops `.` of projects not created yet
+        fs::canonicalize(&path).unwrap_or_else(|_| path.components().collect())
+    };
+    let project_dir = |path: Option<PathBuf>| JobDemand {
+        project_dir: resolve(path),
+        cpus: 0,
+        memory: 0,
+    };
+    // jobs other than create use resources already registered by the project
+    let demand = match parse_job_args(&job.args)?.command {
+        Command::Create(args) => {
+            let project_dir = resolve(args.project_dir);
+            let config_loader = ConfigLoader::new(Some(&project_dir), &args.config_overrides)?;
+            let config_path = |path: Option<PathBuf>| path.map(|path| job.working_dir.join(path));
+            let (cpus, memory) = if args.no_nested {
+                let l2_configs =
+                    load_l2_configs(&config_loader, config_path(args.l2_config).as_deref())?;
+                (
+                    l2_configs.iter().map(|l2_config| l2_config.cpus).sum(),
+                    l2_configs.iter().map(|l2_config| l2_config.memory).sum(),
+                )
+            } else {
+                let l1_config =
+                    load_l1_config(&config_loader, config_path(args.l1_config).as_deref())?;
+                (l1_config.cpus, l1_config.memory)
+            };
+            JobDemand {
+                project_dir,
+                cpus,
+                memory,
+            }
+        }
+        Command::Provision(args) => project_dir(args.project_dir),
+        Command::RunBench(args) => project_dir(args.project_dir),
+        Command::NetBench(args) => project_dir(args.project_dir),
+        Command::Delete(args) => project_dir(args.project_dir),
+        _ => {
+            return Err(anyhow!(
+                "only create, provision, run-bench, net-bench and delete jobs can be submitted"
+            ))
+        }
+    };
+    Ok(demand)
+}
+
+struct Daemon {
+    state_dir: PathBuf,
+    max_jobs: usize,
+    // executable running jobs, the launcher itself unless a fake backend is given
+    backend: PathBuf,
+    jobs: Vec<Job>,
+    running: HashMap<u64, (tokio::process::Child, JobDemand)>,
+}
+
+impl Daemon {
+    fn load(state_dir: PathBuf, max_jobs: usize, backend: PathBuf) -> Result<Self, anyhow::Error> {
+        let queue_path = state_dir.join(QUEUE_FILE);
+        let mut jobs = if queue_path.exists() {
+            let queue_file: QueueFile = serde_yaml::from_reader(fs::File::open(&queue_path)?)?;
+            queue_file.jobs
+        } else {
+            vec![]
+        };
+        // jobs running when the daemon stopped are not tracked anymore
+        for job in jobs.iter_mut().filter(|job| job.state == JobState::Running) {
+            job.state = JobState::Failed;
+            job.message = Some("interrupted by daemon restart".to_string());
+        }
+        let daemon = Self {
+            state_dir,
+            max_jobs,
+            backend,
+            jobs,
+            running: HashMap::new(),
+        };
+        daemon.save()?;
+        Ok(daemon)
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let queue_file = QueueFile {
+            jobs: self.jobs.clone(),
+        };
+        serde_yaml::to_writer(
+            fs::File::create(self.state_dir.join(QUEUE_FILE))?,
+            &queue_file,
+        )?;
+        Ok(())
+    }
+
+    fn log_path(&self, id: u64) -> PathBuf {
+        self.state_dir.join("logs").join(format!("{id}.log"))
+    }
+
+    fn job(&self, id: u64) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    fn job_mut(&mut self, id: u64) -> Result<&mut Job, anyhow::Error> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| anyhow!("job {id} is not found"))
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        let response = match request {
+            Request::Submit {
+                manifest,
+                working_dir,
+            } => self.submit(manifest, working_dir).map(|id| Response::Submitted { id }),
+            Request::Queue => Ok(Response::Queue {
+                jobs: self.jobs.clone(),
+            }),
+            Request::Cancel { id } => self.cancel(id).map(|()| Response::Cancelled { id }),
+        };
+        response.unwrap_or_else(|err| Response::Error {
+            message: err.to_string(),
+        })
+    }
+
+    fn submit(&mut self, manifest: Manifest, working_dir: PathBuf) -> Result<u64, anyhow::Error> {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let job = Job {
+            id,
+            name: manifest.name,
+            args: manifest.args,
+            working_dir,
+            state: JobState::Queued,
+            pid: None,
+            exit_code: None,
+            message: None,
+            suite_results: None,
+        };
+        job_demand(&job)?;
+        self.jobs.push(job);
+        self.save()?;
+        Ok(id)
+    }
+
+    fn cancel(&mut self, id: u64) -> Result<(), anyhow::Error> {
+        let job = self.job_mut(id)?;
+        match job.state {
+            JobState::Queued => {}
+            JobState::Running => {
+                let pid = job.pid.ok_or_else(|| anyhow!("pid of job {id} is unknown"))?;
+                terminate_job(id, pid)?;
+            }
+            state => return Err(anyhow!("job {id} is already {state}")),
+        }
+        job.state = JobState::Cancelled;
+        self.save()
+    }
+
+    // Stop the running jobs when the daemon stops, waiting for them to stop their VMs and
+    // commands so that they are not left running untracked
+    async fn shutdown(&mut self) -> Result<(), anyhow::Error> {
+        for (id, (child, _)) in &self.running {
+            if let Some(pid) = child.id() {
+                if let Err(err) = terminate_job(*id, pid) {
+                    warn!("{err}");
+                }
+            }
+        }
+        let running: Vec<_> = self.running.drain().collect();
+        for (id, (mut child, _)) in running {
+            info!("waiting for job {id} to stop");
+            let status = child.wait().await?;
+            let job = self.job_mut(id)?;
+            job.exit_code = status.code();
+            if job.state == JobState::Running {
+                job.state = JobState::Failed;
+                job.message = Some("interrupted by daemon shutdown".to_string());
+            }
+            info!("job {id} {}", job.state);
+        }
+        self.save()
+    }
+
+    fn reap(&mut self) -> Result<(), anyhow::Error> {
+        let mut finished = vec![];
+        for (id, (child, _)) in self.running.iter_mut() {
+            if let Some(status) = child.try_wait()? {
+                finished.push((*id, status));
+            }
+        }
+        for (id, status) in finished {
+            self.running.remove(&id);
+            let job = self.job_mut(id)?;
+            job.exit_code = status.code();
+            if job.state == JobState::Running {
+                job.state = if status.success() {
+                    JobState::Succeeded
+                } else {
+                    JobState::Failed
+                };
+            }
+            info!("job {id} {}", job.state);
+        }
+        Ok(())
+    }
+
+    // Whether the job fits in the host resources left by registered projects and running jobs
+    fn fits(
+        &self,
+        demand: &JobDemand,
+        running: &[JobDemand],
+        projects: &[ProjectEntry],
+    ) -> Result<bool, anyhow::Error> {
+        let (host_cpus, host_memory) = registry::host_capacity()?;
+        if demand.cpus > host_cpus || demand.memory > host_memory {
+            return Err(anyhow!(
+                "job needs {} cpus and {} MB memory, which exceed the host",
+                demand.cpus,
+                demand.memory
+            ));
+        }
+        let mut cpus = demand.cpus;
+        let mut memory = demand.memory;
+        for project in projects.iter().filter(|project| {
+            project.project_dir != demand.project_dir
+                && !running
+                    .iter()
+                    .any(|running| running.project_dir == project.project_dir)
+        }) {
+            cpus += project.cpus;
+            memory += project.memory;
+        }
+        for running in running {
+            cpus += running.cpus;
+            memory += running.memory;
+        }
+        Ok(cpus <= host_cpus && memory <= host_memory)
+    }
+
+    fn start(&mut self, id: u64, demand: JobDemand) -> Result<(), anyhow::Error> {
+        let log_path = self.log_path(id);
+        fs::create_dir_all(log_path.parent().unwrap())?;
+        let log = fs::File::create(&log_path)?;
+        let mut command = std::process::Command::new(&self.backend);
+        let job = self.job_mut(id)?;
+        command.args(&job.args);
+        if let Command::RunBench(args) = parse_job_args(&job.args)?.command {
+            if args.bench_suite.is_some() {
+                let suite_results = match args.suite_results {
+                    Some(path) => job.working_dir.join(path),
+                    None => {
+                        let path = suite::default_results_path(&demand.project_dir);
+                        command.arg("--suite-results").arg(&path);
+                        path
+                    }
+                };
+                job.suite_results = Some(suite_results);
+            }
+        }
+        command
+            .current_dir(&job.working_dir)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .process_group(0);
+        let child = tokio::process::Command::from(command).spawn()?;
+        job.state = JobState::Running;
+        job.pid = child.id();
+        info!("job {id} started, log: {}", log_path.display());
+        self.running.insert(id, (child, demand));
+        Ok(())
+    }
+
+    fn try_start(
+        &mut self,
+        id: u64,
+        running: &mut Vec<JobDemand>,
+        blocked_projects: &mut BTreeSet<PathBuf>,
+        projects: &[ProjectEntry],
+    ) -> Result<(), anyhow::Error> {
+        let demand = job_demand(self.job_mut(id)?)?;
+        if blocked_projects.contains(&demand.project_dir)
+            || running
+                .iter()
+                .any(|running| running.project_dir == demand.project_dir)
+            || !self.fits(&demand, running, projects)?
+        {
+            blocked_projects.insert(demand.project_dir);
+            return Ok(());
+        }
+        running.push(demand.clone());
+        self.start(id, demand)
+    }
+
+    // Start queued jobs in order. A job waits while another job runs on the same project or the
+    // host lacks resources, and later jobs of other projects may start before it. `projects` are
+    // the projects in the registry.
+    fn schedule(&mut self, projects: &[ProjectEntry]) -> Result<(), anyhow::Error> {
+        self.reap()?;
+        let mut running: Vec<JobDemand> = self
+            .running
+            .values()
+            .map(|(_, demand)| demand.clone())
+            .collect();
+        let mut blocked_projects = BTreeSet::new();
+        let queued: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|job| job.state == JobState::Queued)
+            .map(|job| job.id)
+            .collect();
+        for id in queued {
+            if running.len() >= self.max_jobs {
+                break;
+            }
+            let started = self.try_start(id, &mut running, &mut blocked_projects, projects);
+            if let Err(err) = started {
+                warn!("job {id} failed: {err}");
+                let job = self.job_mut(id)?;
+                job.state = JobState::Failed;
+                job.message = Some(err.to_string());
+            }
+        }
+        self.save()
+    }
+}
+
+// Send SIGTERM to the job, which runs in its own process group including vagrant and ssh
+fn terminate_job(id: u64, pid: u32) -> Result<(), anyhow::Error> {
+    let status = std::process::Command::new("kill")
+        .arg("-TERM")
+        .arg("--")
+        .arg(format!("-{pid}"))
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("killing job {id} failed with status: {status}"));
+    }
+    Ok(())
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    daemon: Arc<Mutex<Daemon>>,
+) -> Result<(), anyhow::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let Some(line) = BufReader::new(reader).lines().next_line().await? else {
+        return Ok(());
+    };
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => daemon.lock().await.handle(request),
+        Err(err) => Response::Error {
+            message: format!("invalid request: {err}"),
+        },
+    };
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    writer.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+// Projects in the registry, read on a blocking thread since the registry lock waits for
+// launchers holding it
+async fn registered_projects() -> Result<Vec<ProjectEntry>, anyhow::Error> {
+    tokio::task::spawn_blocking(|| Ok(Registry::lock()?.projects().to_vec())).await?
+}
+
+// Check finished jobs and start queued jobs periodically, reading the registry outside the
+// daemon lock
+async fn schedule_jobs(daemon: Arc<Mutex<Daemon>>) {
+    let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match registered_projects().await {
+            Ok(projects) => daemon.lock().await.schedule(&projects),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!("scheduling jobs failed: {err}");
+        }
+    }
+}
+
+pub async fn run_daemon(args: DaemonArgs) -> Result<(), anyhow::Error> {
+    if args.max_jobs == 0 {
+        return Err(anyhow!("max-jobs must be positive"));
+    }
+    let state_dir = state_dir(args.state_dir);
+    fs::create_dir_all(&state_dir)?;
+    let socket_path = state_dir.join(DAEMON_SOCKET);
+    if socket_path.exists() {
+        if UnixStream::connect(&socket_path).await.is_ok() {
+            return Err(anyhow!(
+                "daemon is already running on {}",
+                socket_path.display()
+            ));
+        }
+        fs::remove_file(&socket_path)?;
+    }
+
+    let backend = match args.backend {
+        Some(backend) => fs::canonicalize(backend)?,
+        None => std::env::current_exe()?,
+    };
+    let daemon = Arc::new(Mutex::new(Daemon::load(state_dir, args.max_jobs, backend)?));
+    let listener = UnixListener::bind(&socket_path)?;
+    info!("daemon listening on {}", socket_path.display());
+    if let Some(listen) = args.listen {
+        let token = api::load_token(args.token_file.as_deref())?;
+        api::serve(listen, token, daemon.clone())?;
+        info!("HTTP API listening on {listen}");
+    }
+    let mut scheduler = tokio::spawn(schedule_jobs(daemon.clone()));
+    // jobs in their own process groups do not receive Ctrl-C of the terminal, so they are stopped
+    // by the daemon. The second Ctrl-C exits without waiting for them.
+    let interrupted = phase::interrupted();
+    tokio::pin!(interrupted);
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                let (stream, _) = res?;
+                let daemon = daemon.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, daemon).await {
+                        warn!("handling request failed: {err}");
+                    }
+                });
+            }
+            res = &mut scheduler => {
+                res?;
+                return Err(anyhow!("job scheduler stopped"));
+            }
+            _ = &mut interrupted => {
+                // no job starts after the running jobs are stopped
+                scheduler.abort();
+                warn!("daemon is stopping, stopping running jobs");
+                daemon.lock().await.shutdown().await?;
+                fs::remove_file(&socket_path)?;
+                info!("daemon stopped");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn send_request(state_dir: &Path, request: &Request) -> Result<Response, anyhow::Error> {
+    let socket_path = state_dir.join(DAEMON_SOCKET);
+    let stream = UnixStream::connect(&socket_path).await.map_err(|err| {
+        anyhow!(
+            "failed to connect to daemon on {}: {err}",
+            socket_path.display()
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut request = serde_json::to_string(request)?;
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("daemon closed the connection"))?;
+    match serde_json::from_str(&line)? {
+        Response::Error { message } => Err(anyhow!(message)),
+        response => Ok(response),
+    }
+}
+
+pub async fn run_submit(args: SubmitArgs) -> Result<(), anyhow::Error> {
+    let manifest: Manifest = serde_yaml::from_reader(fs::File::open(&args.manifest)?)?;
+    let working_dir = fs::canonicalize(&args.manifest)?
+        .parent()
+        .ok_or_else(|| anyhow!("invalid manifest path: {}", args.manifest.display()))?
+        .to_path_buf();
+    let request = Request::Submit {
+        manifest,
+        working_dir,
+    };
+    if let Response::Submitted { id } = send_request(&state_dir(args.state_dir), &request).await? {
+        println!("Submitted job {id}");
+    }
+    Ok(())
+}
+
+pub async fn run_queue(args: QueueArgs) -> Result<(), anyhow::Error> {
+    let state_dir = state_dir(args.state_dir);
+    let Response::Queue { jobs } = send_request(&state_dir, &Request::Queue).await? else {
+        return Err(anyhow!("unexpected response from daemon"));
+    };
+    if jobs.is_empty() {
+        println!("No job is submitted");
+    }
+    for job in jobs {
+        println!(
+            "{}\t{}\t{}\t{}",
+            job.id,
+            job.state,
+            job.name.as_deref().unwrap_or("-"),
+            job.args.join(" ")
+        );
+        if let Some(message) = &job.message {
+            println!("\t{message}");
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_cancel(args: CancelArgs) -> Result<(), anyhow::Error> {
+    let request = Request::Cancel { id: args.id };
+    if let Response::Cancelled { id } = send_request(&state_dir(args.state_dir), &request).await? {
+        println!("Cancelled job {id}");
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use super::{parse_job_args, Daemon, Job, JobState, Manifest};
+use crate::bench::metadata_output_path;
+use crate::suite;
+use crate::Command;
+use anyhow::anyhow;
+use axum::body::{self, Body, Bytes};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+const API_TOKEN_ENV: &str = "MARIGOLD_API_TOKEN";
+// interval of polling the log file of a running job
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+struct ApiState {
+    daemon: Arc<Mutex<Daemon>>,
+    token: Arc<String>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: String,
+        }
+        (self.0, Json(ErrorBody { error: self.1 })).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, err.to_string())
+    }
+}
+
+fn not_found(id: u64) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("job {id} is not found"))
+}
+
+#[derive(Deserialize)]
+struct SubmitRequest {
+    #[serde(flatten)]
+    manifest: Manifest,
+    // directory where the job runs, the current directory of the daemon by default
+    working_dir: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct SubmitResponse {
+    id: u64,
+}
+
+// Output files of the job and run metadata of its project
+#[derive(Serialize)]
+struct JobResults {
+    files: BTreeMap<PathBuf, String>,
+    metadata: Option<serde_yaml::Value>,
+}
+
+// Read the API token from the file, or the environment variable
+pub fn load_token(token_file: Option<&Path>) -> Result<String, anyhow::Error> {
+    let token = match token_file {
+        Some(token_file) => std::fs::read_to_string(token_file)?,
+        None => std::env::var(API_TOKEN_ENV).map_err(|_| {
+            anyhow!("HTTP API requires a token, set --token-file or {API_TOKEN_ENV}")
+        })?,
+    };
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        return Err(anyhow!("API token is empty"));
+    }
+    Ok(token)
+}
+
+async fn authenticate<B>(
+    State(state): State<ApiState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // compared in constant time not to leak the token through response timing
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())));
+    if !authorized {
+        return Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "invalid API token".to_string(),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+async fn list_jobs(State(state): State<ApiState>) -> Json<Vec<Job>> {
+    Json(state.daemon.lock().await.jobs.clone())
+}
+
+async fn submit_job(
+    State(state): State<ApiState>,
+    Json(request): Json<SubmitRequest>,
+) -> Result<(StatusCode, Json<SubmitResponse>), ApiError> {
+    let working_dir = match request.working_dir {
+        Some(working_dir) => working_dir,
+        None => std::env::current_dir().map_err(anyhow::Error::from)?,
+    };
+    if !working_dir.is_absolute() {
+        return Err(anyhow!("working_dir must be an absolute path").into());
+    }
+    let id = state
+        .daemon
+        .lock()
+        .await
+        .submit(request.manifest, working_dir)?;
+    Ok((StatusCode::CREATED, Json(SubmitResponse { id })))
+}
+
+async fn get_job(
+    State(state): State<ApiState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<Job>, ApiError> {
+    let daemon = state.daemon.lock().await;
+    let job = daemon.job(id).ok_or_else(|| not_found(id))?;
+    Ok(Json(job.clone()))
+}
+
+async fn cancel_job(
+    State(state): State<ApiState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<SubmitResponse>, ApiError> {
+    let mut daemon = state.daemon.lock().await;
+    daemon.job(id).ok_or_else(|| not_found(id))?;
+    daemon.cancel(id)?;
+    Ok(Json(SubmitResponse { id }))
+}
+
+// Stream the log of the job until it finishes
+async fn job_log(
+    State(state): State<ApiState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Response, ApiError> {
+    let log_path = {
+        let daemon = state.daemon.lock().await;
+        daemon.job(id).ok_or_else(|| not_found(id))?;
+        daemon.log_path(id)
+    };
+    let (mut sender, log_body) = Body::channel();
+    tokio::spawn(async move {
+        let mut log_file = None;
+        loop {
+            // check the state before reading so that the last outputs are sent
+            let finished = state
+                .daemon
+                .lock()
+                .await
+                .job(id)
+                .is_none_or(|job| {
+                    !matches!(job.state, JobState::Queued | JobState::Running)
+                });
+            if log_file.is_none() {
+                log_file = tokio::fs::File::open(&log_path).await.ok();
+            }
+            if let Some(log_file) = &mut log_file {
+                let mut buf = vec![];
+                if log_file.read_to_end(&mut buf).await.is_ok()
+                    && !buf.is_empty()
+                    && sender.send_data(Bytes::from(buf)).await.is_err()
+                {
+                    return;
+                }
+            }
+            if finished {
+                return;
+            }
+            tokio::time::sleep(LOG_POLL_INTERVAL).await;
+        }
+    });
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body::boxed(log_body))
+        .unwrap())
+}
+
+// Output file and the files of each L2 VM named `<stem>-<host_name>.<ext>`, with their stderr
+// files `<stem>.stderr.<ext>` and metadata files `<stem>.metadata.yaml`
+fn output_files(output_path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    if output_path.is_file() {
+        files.push(output_path.to_path_buf());
+    }
+    let (Some(stem), Some(dir)) = (output_path.file_stem(), output_path.parent()) else {
+        return files;
+    };
+    let stem = stem.to_string_lossy();
+    let prefixes = [format!("{stem}-"), format!("{stem}.stderr")];
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let output = prefixes.iter().any(|prefix| name.starts_with(prefix))
+            && path.extension() == output_path.extension();
+        let metadata = (name.starts_with(&format!("{stem}.")) || name.starts_with(&format!("{stem}-")))
+            && name.ends_with(".metadata.yaml");
+        if (output || metadata) && path != output_path {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+fn job_results(job: &Job) -> Result<JobResults, anyhow::Error> {
+    // whether the metadata is written next to the output instead of run-metadata.yaml
+    let (project_dir, output, net_bench) = match parse_job_args(&job.args)?.command {
+        Command::Create(args) => (args.project_dir, args.output, false),
+        Command::Provision(args) => (args.project_dir, args.output, false),
+        Command::RunBench(args) => {
+            // outputs of a suite are written in the project directory by default
+            let output = args.output.or_else(|| {
+                args.bench_suite.as_ref().map(|_| {
+                    args.project_dir
+                        .clone()
+                        .unwrap_or_default()
+                        .join(suite::DEFAULT_OUTPUT)
+                })
+            });
+            (args.project_dir, output, false)
+        }
+        Command::NetBench(args) => {
+            let output = args.output.unwrap_or_else(|| {
+                args.project_dir
+                    .clone()
+                    .unwrap_or_default()
+                    .join("net-bench-results.yaml")
+            });
+            (args.project_dir, Some(output), true)
+        }
+        _ => return Err(anyhow!("job {} has no results", job.id)),
+    };
+    let project_dir = job.working_dir.join(project_dir.unwrap_or_default());
+
+    // components() drops `.` in relative paths of the manifest
+    let output: Option<PathBuf> =
+        output.map(|output| job.working_dir.join(output).components().collect());
+    let mut files = BTreeMap::new();
+    if let Some(output) = &output {
+        for path in output_files(output) {
+            files.insert(path.clone(), std::fs::read_to_string(&path)?);
+        }
+    }
+    if let Some(path) = &job.suite_results {
+        files.insert(path.clone(), std::fs::read_to_string(path)?);
+    }
+    let metadata_path = match &output {
+        Some(output) if net_bench => metadata_output_path(output),
+        _ => project_dir.join("run-metadata.yaml"),
+    };
+    let metadata = if metadata_path.exists() {
+        Some(serde_yaml::from_reader(std::fs::File::open(metadata_path)?)?)
+    } else {
+        None
+    };
+    Ok(JobResults { files, metadata })
+}
+
+async fn get_job_results(
+    State(state): State<ApiState>,
+    UrlPath(id): UrlPath<u64>,
+) -> Result<Json<JobResults>, ApiError> {
+    let job = {
+        let daemon = state.daemon.lock().await;
+        daemon.job(id).ok_or_else(|| not_found(id))?.clone()
+    };
+    if job.state != JobState::Succeeded {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            format!("job {id} is {}", job.state),
+        ));
+    }
+    Ok(Json(job_results(&job)?))
+}
+
+// Start the HTTP API sharing the job queue with the daemon
+pub fn serve(
+    addr: SocketAddr,
+    token: String,
+    daemon: Arc<Mutex<Daemon>>,
+) -> Result<tokio::task::JoinHandle<()>, anyhow::Error> {
+    let state = ApiState {
+        daemon,
+        token: Arc::new(token),
+    };
+    let app = Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/log", get(job_log))
+        .route("/jobs/:id/results", get(get_job_results))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state);
+    let server = axum::Server::try_bind(&addr)?.serve(app.into_make_service());
+    Ok(tokio::spawn(async move {
+        if let Err(err) = server.await {
+            warn!("HTTP API stopped: {err}");
+        }
+    }))
+}
@@ -0,0 +1,139 @@
+use crate::phase::PhaseError;
+use std::fmt;
+
+// Error of a launcher command, categorized so that the launcher exits with a stable status
+pub enum LauncherError {
+    // invalid config files or arguments
+    Config(anyhow::Error),
+    // host does not meet the requirements, such as kvm modules, hugepages, CPUs or subnets
+    Preflight(anyhow::Error),
+    // vagrant, libvirt or virsh failed
+    Backend(anyhow::Error),
+    // bench script failed
+    Bench(anyhow::Error),
+    Io(std::io::Error),
+    // phase failed, timed out or was interrupted, or VMs are not ready after it
+    Phase(PhaseError),
+    Other(anyhow::Error),
+}
+
+// Wrap the error in the category unless a callee already categorized it
+fn categorize(
+    err: impl Into<anyhow::Error>,
+    category: fn(anyhow::Error) -> LauncherError,
+) -> anyhow::Error {
+    let err = err.into();
+    if err.is::<LauncherError>() || err.is::<PhaseError>() {
+        return err;
+    }
+    category(err).into()
+}
+
+impl LauncherError {
+    pub fn config(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        categorize(err, LauncherError::Config)
+    }
+
+    pub fn preflight(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        categorize(err, LauncherError::Preflight)
+    }
+
+    pub fn backend(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        categorize(err, LauncherError::Backend)
+    }
+
+    pub fn bench(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        categorize(err, LauncherError::Bench)
+    }
+
+    // Rewrite the message of the error keeping its category
+    pub fn map(
+        err: anyhow::Error,
+        f: impl FnOnce(anyhow::Error) -> anyhow::Error,
+    ) -> anyhow::Error {
+        match err.downcast::<LauncherError>() {
+            Ok(LauncherError::Config(err)) => LauncherError::Config(f(err)).into(),
+            Ok(LauncherError::Preflight(err)) => LauncherError::Preflight(f(err)).into(),
+            Ok(LauncherError::Backend(err)) => LauncherError::Backend(f(err)).into(),
+            Ok(LauncherError::Bench(err)) => LauncherError::Bench(f(err)).into(),
+            Ok(err) => err.into(),
+            Err(err) => f(err),
+        }
+    }
+
+    // Exit status of the launcher, documented in README
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LauncherError::Other(_) => 1,
+            LauncherError::Config(_) => 2,
+            LauncherError::Preflight(_) => 3,
+            LauncherError::Backend(_) => 4,
+            LauncherError::Bench(_) => 6,
+            LauncherError::Io(_) => 7,
+            LauncherError::Phase(err) => err.exit_code(),
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            LauncherError::Config(_) => "config error: ",
+            LauncherError::Preflight(_) => "host preflight check failed: ",
+            LauncherError::Backend(_) => "backend failed: ",
+            LauncherError::Bench(_) => "bench failed: ",
+            LauncherError::Io(_) => "I/O error: ",
+            LauncherError::Phase(_) | LauncherError::Other(_) => "",
+        }
+    }
+}
+
+impl From<anyhow::Error> for LauncherError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<LauncherError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<PhaseError>() {
+            Ok(err) => return LauncherError::Phase(err),
+            Err(err) => err,
+        };
+        match err.downcast::<std::io::Error>() {
+            Ok(err) => LauncherError::Io(err),
+            Err(err) => LauncherError::Other(err),
+        }
+    }
+}
+
+// Concise message with the causes of the error
+impl fmt::Display for LauncherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.prefix())?;
+        match self {
+            LauncherError::Config(err)
+            | LauncherError::Preflight(err)
+            | LauncherError::Backend(err)
+            | LauncherError::Bench(err)
+            | LauncherError::Other(err) => write!(f, "{err:#}"),
+            LauncherError::Io(err) => write!(f, "{err}"),
+            LauncherError::Phase(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+// Message with the backtrace captured if RUST_LIB_BACKTRACE or RUST_BACKTRACE is set
+impl fmt::Debug for LauncherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.prefix())?;
+        match self {
+            LauncherError::Config(err)
+            | LauncherError::Preflight(err)
+            | LauncherError::Backend(err)
+            | LauncherError::Bench(err)
+            | LauncherError::Other(err) => write!(f, "{err:?}"),
+            LauncherError::Io(err) => write!(f, "{err}"),
+            LauncherError::Phase(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+// not chaining the source since Display already includes the causes
+impl std::error::Error for LauncherError {}
@@ -0,0 +1,273 @@
+use crate::bench::timestamp;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{info, info_span, Span, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+// name of the spans whose durations are reported in the timing summary
+const STEP_SPAN: &str = "step";
+
+// log file of the current run, opened once the project directory is known
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+// steps in the order they started
+static TIMINGS: Mutex<Vec<Timing>> = Mutex::new(vec![]);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+struct Timing {
+    step: String,
+    // number of enclosing steps
+    depth: usize,
+    elapsed: Option<Duration>,
+}
+
+struct StepStart {
+    index: usize,
+    start: Instant,
+}
+
+#[derive(Default)]
+struct StepVisitor(String);
+
+impl Visit for StepVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "step" {
+            self.0 = value.to_string();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "step" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+// Layer measuring how long each step span is open
+struct TimingLayer;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for TimingLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != STEP_SPAN {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = StepVisitor::default();
+        attrs.record(&mut visitor);
+        let depth = span
+            .scope()
+            .skip(1)
+            .filter(|parent| parent.name() == STEP_SPAN)
+            .count();
+        let mut timings = TIMINGS.lock().unwrap();
+        timings.push(Timing {
+            step: visitor.0,
+            depth,
+            elapsed: None,
+        });
+        span.extensions_mut().insert(StepStart {
+            index: timings.len() - 1,
+            start: Instant::now(),
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        if let Some(step) = extensions.get::<StepStart>() {
+            TIMINGS.lock().unwrap()[step.index].elapsed = Some(step.start.elapsed());
+        }
+    }
+}
+
+// Formatter of span fields for the log file. Fields are formatted once per formatter type, so the
+// log file needs its own type not to share the fields colored for the console.
+#[derive(Default)]
+struct LogFileFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for LogFileFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+struct LogFileWriter;
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Writer of the console. `vagrant ssh` of bench scripts puts the terminal in raw mode, where a
+// line feed does not return the cursor, so lines end with CRLF on a terminal.
+#[derive(Clone, Copy)]
+struct ConsoleWriter {
+    stderr: bool,
+    terminal: bool,
+}
+
+impl ConsoleWriter {
+    fn new(stderr: bool) -> Self {
+        let terminal = if stderr {
+            io::stderr().is_terminal()
+        } else {
+            io::stdout().is_terminal()
+        };
+        Self { stderr, terminal }
+    }
+
+    fn write_console(&self, buf: &[u8]) -> io::Result<()> {
+        if self.stderr {
+            io::stderr().write_all(buf)
+        } else {
+            io::stdout().write_all(buf)
+        }
+    }
+}
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.terminal {
+            self.write_console(buf)?;
+            return Ok(buf.len());
+        }
+        let mut converted = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            if byte == b'\n' && converted.last() != Some(&b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(byte);
+        }
+        self.write_console(&converted)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.stderr {
+            io::stderr().flush()
+        } else {
+            io::stdout().flush()
+        }
+    }
+}
+
+// Log to stdout, or stderr if stdout is the output of the command such as the plan of --dry-run,
+// and to the log file of the run once it is opened
+pub fn init(format: LogFormat, verbose: bool, stderr: bool) {
+    let level = if verbose {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    };
+    let json = format == LogFormat::Json;
+    let console_writer = ConsoleWriter::new(stderr);
+    let console = move || console_writer;
+    tracing_subscriber::registry()
+        .with(json.then(|| fmt::layer().json().with_writer(console)))
+        .with(json.then(|| fmt::layer().json().with_writer(|| LogFileWriter)))
+        .with((!json).then(|| {
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(console_writer.terminal)
+                .with_writer(console)
+        }))
+        .with((!json).then(|| {
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(false)
+                .fmt_fields(LogFileFields::default())
+                .with_writer(|| LogFileWriter)
+        }))
+        .with(TimingLayer)
+        .with(level)
+        .init();
+}
+
+// Open `<project-dir>/logs/<command>-<timestamp>.log` for the logs of this run
+pub fn open_log_file(project_dir: &Path, command: &str) -> Result<PathBuf, anyhow::Error> {
+    let log_dir = project_dir.join("logs");
+    std::fs::create_dir_all(&log_dir)?;
+    let path = log_dir.join(format!("{command}-{}.log", timestamp().replace(':', "-")));
+    *LOG_FILE.lock().unwrap() = Some(File::create(&path)?);
+    info!("logging to {}", path.display());
+    Ok(path)
+}
+
+// Span of a step whose duration is reported in the timing summary
+pub fn step(step: &str) -> Span {
+    info_span!("step", step)
+}
+
+// Step of the vagrant directory such as `template-copy l1-vagrant`
+pub fn step_in(step: &str, vagrant_dir: &Path) -> Span {
+    let dir_name = vagrant_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    self::step(&format!("{step} {dir_name}"))
+}
+
+// Log the duration of every step, indented under the enclosing steps for the text format and
+// as an event per step for the json format
+pub fn log_timing_summary(format: LogFormat) {
+    let timings = TIMINGS.lock().unwrap();
+    if timings.is_empty() {
+        return;
+    }
+    let mut summary = String::from("timing summary:");
+    for timing in timings.iter() {
+        let seconds = timing.elapsed.map(|elapsed| elapsed.as_secs_f64());
+        if format == LogFormat::Json {
+            info!(step = %timing.step, depth = timing.depth, seconds, "timing");
+            continue;
+        }
+        let elapsed = match seconds {
+            Some(seconds) => format!("{seconds:.1}s"),
+            None => "unfinished".to_string(),
+        };
+        let _ = write!(
+            summary,
+            "\n{:indent$}{}: {elapsed}",
+            "",
+            timing.step,
+            indent = 2 * (timing.depth + 1)
+        );
+    }
+    if format == LogFormat::Text {
+        info!("{summary}");
+    }
+}
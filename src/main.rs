@@ -1,15 +1,43 @@
+mod bench;
+mod config;
+mod daemon;
+mod error;
+mod logging;
+mod migrate;
+mod net_bench;
+mod network;
+mod phase;
+mod plan;
+mod provision;
+mod readiness;
+mod registry;
+mod schema;
+mod suite;
+mod vagrant;
+
 use anyhow::anyhow;
 use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process::{ExitStatus, Stdio};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::{fs, process};
-use std::io::Write;
-use once_cell::sync::Lazy;
 use strum_macros::EnumString;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use regex::Regex;
+use tracing::{info, warn, Instrument};
+use bench::{BenchOptions, BenchTarget, BENCH_SCRIPT, L2_BENCH_SCRIPT};
+use config::{ConfigLoader, ConfigOverrides, LayeredConfig, Level};
+use error::LauncherError;
+use logging::{step, step_in, LogFormat};
+use migrate::{GeneratedConfig, PROJECT_VERSION};
+use net_bench::NetBenchEndpoints;
+use network::{Ipv4Subnet, LIBVIRT_NETWORKS_SCRIPT};
+use phase::{Phase, PhaseOptions};
+use plan::{DryRunArgs, Executor};
+use provision::ProvisionStep;
+use readiness::ReadinessOptions;
+use registry::{ProjectEntry, RegisteredNetwork, Registry};
+use schema::{Property, Schema};
+use suite::Suite;
+use vagrant::VagrantCommand;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 enum Arch {
@@ -27,6 +55,8 @@ struct DeleteArgs {
     no_nested: bool,
     #[clap(short, long, help = "Delete without confirmation")]
     force: bool,
+    #[clap(flatten)]
+    dry_run: DryRunArgs,
 }
 
 #[derive(Parser)]
@@ -51,6 +81,16 @@ struct CreateArgs {
     overwrite: bool,
     #[clap(long, default_value_t = false, help = "Disable nested virtualization")]
     no_nested: bool,
+    #[clap(flatten)]
+    config_overrides: ConfigOverrides,
+    #[clap(flatten)]
+    bench_options: BenchOptions,
+    #[clap(flatten)]
+    phase_options: PhaseOptions,
+    #[clap(flatten)]
+    readiness: ReadinessOptions,
+    #[clap(flatten)]
+    dry_run: DryRunArgs,
 }
 
 #[derive(Parser)]
@@ -75,18 +115,176 @@ struct ProvisionArgs {
     sync: bool,
     #[clap(long, default_value_t = false, help = "Disable nested virtualization")]
     no_nested: bool,
+    #[clap(flatten)]
+    config_overrides: ConfigOverrides,
+    #[clap(flatten)]
+    bench_options: BenchOptions,
+    #[clap(flatten)]
+    phase_options: PhaseOptions,
+    #[clap(flatten)]
+    readiness: ReadinessOptions,
+    #[clap(flatten)]
+    dry_run: DryRunArgs,
 }
 
 #[derive(Parser)]
 struct RunBenchArgs {
     #[clap(short, long, help = "Path to project directory")]
     project_dir: Option<PathBuf>,
-    #[clap(short, long, help = "Path to bench script running in L2 VM")]
-    bench_script: PathBuf,
+    #[clap(
+        short,
+        long,
+        required_unless_present = "bench_suite",
+        conflicts_with = "bench_suite",
+        help = "Path to bench script running in L2 VM"
+    )]
+    bench_script: Option<PathBuf>,
+    #[clap(long, help = "Path to bench suite yaml file listing benchmarks run in order")]
+    bench_suite: Option<PathBuf>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        requires = "bench_suite",
+        help = "Names of the benchmarks of the suite to run"
+    )]
+    only: Vec<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        requires = "bench_suite",
+        help = "Names of the benchmarks of the suite to skip"
+    )]
+    skip: Vec<String>,
+    #[clap(
+        long,
+        requires = "bench_suite",
+        help = "Path to file recording the runs of the suite, <project-dir>/suite-results/<timestamp>.yaml by default"
+    )]
+    suite_results: Option<PathBuf>,
     #[clap(short, long, help = "Path to output file for benchmark results")]
     output: Option<PathBuf>,
     #[clap(long, default_value_t = false, help = "Disable nested virtualization")]
     no_nested: bool,
+    #[clap(flatten)]
+    bench_options: BenchOptions,
+    #[clap(flatten)]
+    phase_options: PhaseOptions,
+    #[clap(flatten)]
+    readiness: ReadinessOptions,
+    #[clap(flatten)]
+    dry_run: DryRunArgs,
+}
+
+#[derive(Parser)]
+struct NetBenchArgs {
+    #[clap(short, long, help = "Path to project directory")]
+    project_dir: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = NetBenchEndpoints::VmPair, help = "Endpoints of the network bench")]
+    endpoints: NetBenchEndpoints,
+    #[clap(long, help = "Host name of the VM running iperf3 client, the first VM by default")]
+    client: Option<String>,
+    #[clap(long, help = "Host name of the VM running iperf3 server with vm-pair endpoints")]
+    server: Option<String>,
+    #[clap(long, default_value_t = 10, help = "Duration of each measurement in seconds")]
+    duration: u32,
+    #[clap(long, default_value_t = 1, help = "Number of parallel streams")]
+    streams: u32,
+    #[clap(long, default_value_t = 5201, help = "Port of iperf3 server")]
+    port: u16,
+    #[clap(long, default_value_t = false, help = "Send data from the server to the client")]
+    reverse: bool,
+    #[clap(short, long, help = "Path to output file for network bench results")]
+    output: Option<PathBuf>,
+    #[clap(long, default_value_t = false, help = "Disable nested virtualization")]
+    no_nested: bool,
+    #[clap(long, help = "Timeout in seconds of the network bench")]
+    bench_timeout: Option<u64>,
+}
+
+#[derive(Parser)]
+struct DaemonArgs {
+    #[clap(long, help = "Path to directory storing the job queue and logs")]
+    state_dir: Option<PathBuf>,
+    #[clap(long, default_value_t = 1, help = "Maximum number of jobs running concurrently")]
+    max_jobs: usize,
+    #[clap(long, help = "Address of HTTP API such as 0.0.0.0:8080, disabled by default")]
+    listen: Option<std::net::SocketAddr>,
+    #[clap(long, help = "Path to file containing the token of HTTP API")]
+    token_file: Option<PathBuf>,
+    #[clap(long, help = "Executable running jobs instead of the launcher, e.g. a fake backend")]
+    backend: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct SubmitArgs {
+    #[clap(long, help = "Path to directory storing the job queue and logs")]
+    state_dir: Option<PathBuf>,
+    #[clap(help = "Path to job manifest yaml file")]
+    manifest: PathBuf,
+}
+
+#[derive(Parser)]
+struct QueueArgs {
+    #[clap(long, help = "Path to directory storing the job queue and logs")]
+    state_dir: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct CancelArgs {
+    #[clap(long, help = "Path to directory storing the job queue and logs")]
+    state_dir: Option<PathBuf>,
+    #[clap(help = "Id of the job to cancel")]
+    id: u64,
+}
+
+#[derive(Parser)]
+struct ConfigShowArgs {
+    #[clap(long, help = "Path to L1 VM config yaml file")]
+    l1_config: Option<PathBuf>,
+    #[clap(long, help = "Path to L2 VM config yaml file")]
+    l2_config: Option<PathBuf>,
+    #[clap(short, long, help = "Path to project directory")]
+    project_dir: Option<PathBuf>,
+    #[clap(long, default_value_t = false, help = "Show the config for no-nested virtualization")]
+    no_nested: bool,
+    #[clap(flatten)]
+    config_overrides: ConfigOverrides,
+}
+
+#[derive(Parser)]
+struct MigrateArgs {
+    #[clap(short, long, help = "Path to project directory")]
+    project_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ConfigKind {
+    L1,
+    // L2 config is the same for no-nested L2 VMs
+    #[value(alias = "l2-no-nested")]
+    L2,
+    // --bench-suite of run-bench
+    Suite,
+}
+
+#[derive(Parser)]
+struct ConfigSchemaArgs {
+    #[clap(value_enum, help = "Config file to print the schema of")]
+    kind: ConfigKind,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    #[command(about = "Print the merged config and the source of each value")]
+    Show(ConfigShowArgs),
+    #[command(about = "Print JSON Schema of the config files for editors")]
+    Schema(ConfigSchemaArgs),
+}
+
+#[derive(Parser)]
+struct ConfigArgs {
+    #[clap(subcommand)]
+    command: ConfigCommand,
 }
 
 #[derive(Subcommand)]
@@ -99,15 +297,47 @@ enum Command {
     Provision(ProvisionArgs),
     #[command(about = "Run bench script")]
     RunBench(RunBenchArgs),
+    #[command(about = "Run network bench between VMs or a VM and the host")]
+    NetBench(NetBenchArgs),
+    #[command(about = "List projects registered on the host")]
+    Projects,
+    #[command(about = "Run daemon executing submitted jobs")]
+    Daemon(DaemonArgs),
+    #[command(about = "Submit a job manifest to the daemon")]
+    Submit(SubmitArgs),
+    #[command(about = "List jobs of the daemon")]
+    Queue(QueueArgs),
+    #[command(about = "Cancel a queued or running job")]
+    Cancel(CancelArgs),
+    #[command(about = "Inspect the layered VM configs")]
+    Config(ConfigArgs),
+    #[command(about = "Upgrade a project directory created by an older launcher")]
+    Migrate(MigrateArgs),
+}
+
+impl Command {
+    fn dry_run(&self) -> DryRunArgs {
+        match self {
+            Command::Create(args) => args.dry_run,
+            Command::Provision(args) => args.dry_run,
+            Command::RunBench(args) => args.dry_run,
+            Command::Delete(args) => args.dry_run,
+            _ => DryRunArgs::default(),
+        }
+    }
 }
 
 #[derive(Parser)]
 struct Args {
     #[clap(subcommand)]
     command: Command,
+    #[clap(long, global = true, help = "Print debug logs, and the causes and the backtrace of errors")]
+    verbose: bool,
+    #[clap(long, global = true, value_enum, default_value_t = LogFormat::Text, help = "Format of logs")]
+    log_format: LogFormat,
 }
 
-#[derive(Debug, PartialEq, EnumString, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, EnumString, Serialize, Deserialize)]
 enum CpuMode {
     #[strum(serialize = "custom")]
     #[serde(rename = "custom")]
@@ -115,6 +345,7 @@ enum CpuMode {
     #[strum(serialize = "host-passthrough")]
     #[serde(rename = "host-passthrough")]
     HostPassthrough,
+    #[default]
     #[strum(serialize = "host-model")]
     #[serde(rename = "host-model")]
     HostModel,
@@ -123,12 +354,662 @@ enum CpuMode {
     Maximum,
 }
 
-impl Default for CpuMode {
-    fn default() -> Self {
-        Self::HostModel
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CpuFeatures {
+    #[serde(default)]
+    enable: Vec<String>,
+    #[serde(default)]
+    disable: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CpuTopology {
+    sockets: u32,
+    cores: u32,
+    threads: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NumaNode {
+    // vCPU list of this cell such as "0-1"
+    cpus: String,
+    // in MB
+    memory: u64,
+}
+
+// vCPU, emulator thread and iothread pinning applied with virsh after the VM boots.
+// CPU lists refer to host CPUs for L1 and no-nested VMs, and to L1 vCPUs for L2 VM.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CpuPinning {
+    // vCPU index -> CPU list such as "2-3"
+    #[serde(default)]
+    vcpus: BTreeMap<u32, String>,
+    emulator: Option<String>,
+    // CPU list of each iothread, iothread ids are assigned from 1
+    #[serde(default)]
+    iothreads: Vec<String>,
+}
+
+impl CpuPinning {
+    fn cpu_lists(&self) -> impl Iterator<Item = &String> {
+        self.vcpus
+            .values()
+            .chain(self.emulator.iter())
+            .chain(self.iothreads.iter())
+    }
+
+    // All CPUs used by vCPUs, the emulator and iothreads
+    fn pinned_cpus(&self) -> Result<BTreeSet<u32>, anyhow::Error> {
+        let mut cpus = BTreeSet::new();
+        for cpu_list in self.cpu_lists() {
+            cpus.extend(parse_cpu_list(cpu_list)?);
+        }
+        Ok(cpus)
+    }
+
+    fn validate(&self, vcpus: u32, available_cpus: &BTreeSet<u32>) -> Result<(), anyhow::Error> {
+        for cpu_list in self.cpu_lists() {
+            let cpus = parse_cpu_list(cpu_list)?;
+            if let Some(cpu) = cpus.difference(available_cpus).next() {
+                return Err(anyhow!("cpu {cpu} in cpu_pinning is not available"));
+            }
+        }
+        if let Some(vcpu) = self.vcpus.keys().find(|vcpu| **vcpu >= vcpus) {
+            return Err(anyhow!("vcpu {vcpu} in cpu_pinning exceeds cpus {vcpus}"));
+        }
+
+        Ok(())
+    }
+
+    fn virsh_commands(&self, domain: &str) -> Vec<String> {
+        let mut commands = vec![];
+        for (vcpu, cpu_list) in &self.vcpus {
+            commands.push(format!(
+                "virsh -c qemu:///system vcpupin {domain} {vcpu} {cpu_list} --live --config"
+            ));
+        }
+        if let Some(cpu_list) = &self.emulator {
+            commands.push(format!(
+                "virsh -c qemu:///system emulatorpin {domain} {cpu_list} --live --config"
+            ));
+        }
+        for (i, cpu_list) in self.iothreads.iter().enumerate() {
+            let id = i + 1;
+            commands.push(format!(
+                "(virsh -c qemu:///system iothreadinfo {domain} | grep -q '^ *{id} ' || virsh -c qemu:///system iothreadadd {domain} {id} --live --config)"
+            ));
+            commands.push(format!(
+                "virsh -c qemu:///system iothreadpin {domain} {id} {cpu_list} --live --config"
+            ));
+        }
+        commands
+    }
+}
+
+// Parse a CPU list in the format of /sys/devices/system/cpu/online and libvirt cpuset like "0-3,^2,6"
+fn parse_cpu_list(cpu_list: &str) -> Result<BTreeSet<u32>, anyhow::Error> {
+    let mut cpus = BTreeSet::new();
+    let mut excluded = BTreeSet::new();
+    for item in cpu_list.trim().split(',').filter(|item| !item.is_empty()) {
+        let (item, target) = match item.strip_prefix('^') {
+            Some(item) => (item, &mut excluded),
+            None => (item, &mut cpus),
+        };
+        let range = match item.split_once('-') {
+            Some((start, end)) => start.trim().parse::<u32>()?..=end.trim().parse::<u32>()?,
+            None => {
+                let cpu = item.trim().parse::<u32>()?;
+                cpu..=cpu
+            }
+        };
+        if range.is_empty() {
+            return Err(anyhow!("invalid cpu list: {cpu_list}"));
+        }
+        target.extend(range);
+    }
+
+    Ok(cpus.difference(&excluded).copied().collect())
+}
+
+fn host_online_cpus() -> Result<BTreeSet<u32>, anyhow::Error> {
+    parse_cpu_list(&fs::read_to_string("/sys/devices/system/cpu/online")?)
+}
+
+fn host_isolated_cpus() -> BTreeSet<u32> {
+    fs::read_to_string("/sys/devices/system/cpu/isolated")
+        .ok()
+        .and_then(|cpu_list| parse_cpu_list(&cpu_list).ok())
+        .unwrap_or_default()
+}
+
+// Validate pinning of a VM running on the host machine against the host CPU topology
+fn validate_host_cpu_pinning(cpu_pinning: &CpuPinning, vcpus: u32) -> Result<(), anyhow::Error> {
+    cpu_pinning.validate(vcpus, &host_online_cpus()?)?;
+
+    let isolated_cpus = host_isolated_cpus();
+    for cpu_list in cpu_pinning.vcpus.values() {
+        if !parse_cpu_list(cpu_list)?.is_subset(&isolated_cpus) {
+            warn!("cpu {cpu_list} pinned to vCPU is not isolated on the host");
+        }
+    }
+
+    Ok(())
+}
+
+// CPU options passed through to libvirt in addition to `cpu_mode`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CpuOptions {
+    // only effective with `cpu_mode: custom`
+    cpu_model: Option<String>,
+    #[serde(default)]
+    cpu_features: CpuFeatures,
+    cpu_topology: Option<CpuTopology>,
+    #[serde(default)]
+    numa_nodes: Vec<NumaNode>,
+    cpu_pinning: Option<CpuPinning>,
+}
+
+impl CpuOptions {
+    fn properties() -> Vec<Property> {
+        let cpu_list = || Schema::String;
+        vec![
+            Property::new(
+                "cpu_model",
+                Schema::String.nullable(),
+                "CPU model such as Skylake-Server, only effective with cpu_mode custom",
+            ),
+            Property::new(
+                "cpu_features",
+                Schema::object(vec![
+                    Property::new("enable", Schema::array(Schema::String), "CPU features to enable"),
+                    Property::new("disable", Schema::array(Schema::String), "CPU features to disable"),
+                ]),
+                "CPU features enabled or disabled on top of the CPU model",
+            ),
+            Property::new(
+                "cpu_topology",
+                Schema::object(vec![
+                    Property::new("sockets", Schema::integer(1), "Number of sockets").required(),
+                    Property::new("cores", Schema::integer(1), "Number of cores per socket").required(),
+                    Property::new("threads", Schema::integer(1), "Number of threads per core").required(),
+                ])
+                .nullable(),
+                "CPU topology, whose product must equal cpus",
+            ),
+            Property::new(
+                "numa_nodes",
+                Schema::array(Schema::object(vec![
+                    Property::new("cpus", cpu_list(), "vCPU list of the node such as 0-1").required(),
+                    Property::new("memory", Schema::integer(1), "Memory of the node in MB").required(),
+                ])),
+                "NUMA nodes, whose memory must add up to memory",
+            ),
+            Property::new(
+                "cpu_pinning",
+                Schema::object(vec![
+                    Property::new("vcpus", Schema::map(cpu_list()), "vCPU index to CPU list such as 2-3"),
+                    Property::new("emulator", cpu_list().nullable(), "CPU list of the emulator threads"),
+                    Property::new("iothreads", Schema::array(cpu_list()), "CPU list of each iothread"),
+                ])
+                .nullable(),
+                "Pinning to host CPUs for L1 and no-nested VMs, and to L1 vCPUs for L2 VMs",
+            ),
+        ]
+    }
+
+    fn validate(&self, cpu_mode: &CpuMode, cpus: u32, memory: u64) -> Result<(), anyhow::Error> {
+        if cpus == 0 {
+            return Err(anyhow!("cpus must be greater than 0"));
+        }
+        if self.cpu_model.is_some() && *cpu_mode != CpuMode::Custom {
+            return Err(anyhow!("cpu_model requires cpu_mode to be custom"));
+        }
+        if let Some(feature) = self
+            .cpu_features
+            .enable
+            .iter()
+            .find(|feature| self.cpu_features.disable.contains(feature))
+        {
+            return Err(anyhow!("cpu feature {feature} is both enabled and disabled"));
+        }
+        if let Some(topology) = &self.cpu_topology {
+            let topology_cpus = topology
+                .sockets
+                .checked_mul(topology.cores)
+                .and_then(|cpus| cpus.checked_mul(topology.threads))
+                .ok_or_else(|| anyhow!("cpu_topology has too many vCPUs"))?;
+            if topology_cpus != cpus {
+                return Err(anyhow!(
+                    "cpu_topology has {topology_cpus} vCPUs but cpus is {cpus}"
+                ));
+            }
+        }
+        for node in &self.numa_nodes {
+            if let Some(cpu) = parse_cpu_list(&node.cpus)?.into_iter().find(|cpu| *cpu >= cpus) {
+                return Err(anyhow!("cpu {cpu} in numa_nodes exceeds cpus {cpus}"));
+            }
+        }
+        if !self.numa_nodes.is_empty() {
+            let numa_memory: u64 = self.numa_nodes.iter().map(|node| node.memory).sum();
+            if numa_memory != memory {
+                return Err(anyhow!(
+                    "numa_nodes have {numa_memory} MB memory in total but memory is {memory}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum HugepageSize {
+    #[serde(rename = "2M")]
+    Size2M,
+    #[serde(rename = "1G")]
+    Size1G,
+}
+
+impl HugepageSize {
+    fn kib(self) -> u64 {
+        match self {
+            Self::Size2M => 2 * 1024,
+            Self::Size1G => 1024 * 1024,
+        }
+    }
+
+    // Number of hugepages to back `memory` MB of guest memory
+    fn pages_for(self, memory: u64) -> u64 {
+        (memory * 1024).div_ceil(self.kib())
+    }
+}
+
+// Memory backing options passed through to libvirt
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MemoryOptions {
+    hugepage_size: Option<HugepageSize>,
+    #[serde(default)]
+    lock_memory: bool,
+    #[serde(default)]
+    shared_memory: bool,
+    // libvirt adds a memballoon device if not specified
+    memballoon: Option<bool>,
+}
+
+impl MemoryOptions {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::new(
+                "hugepage_size",
+                Schema::Enum(&["2M", "1G"]).nullable(),
+                "Back the memory with hugepages of the size",
+            ),
+            Property::new("lock_memory", Schema::Boolean, "Lock the memory not to be swapped out"),
+            Property::new("shared_memory", Schema::Boolean, "Back the memory with shared memory"),
+            Property::new(
+                "memballoon",
+                Schema::Boolean.nullable(),
+                "Whether to add a memballoon device, added by libvirt if not set",
+            ),
+        ]
+    }
+
+    fn validate(&self, memory: u64) -> Result<(), anyhow::Error> {
+        if memory == 0 {
+            return Err(anyhow!("memory must be greater than 0"));
+        }
+        if let Some(hugepage_size) = self.hugepage_size {
+            if !(memory * 1024).is_multiple_of(hugepage_size.kib()) {
+                return Err(anyhow!(
+                    "memory {memory} MB is not a multiple of hugepage size {} kB",
+                    hugepage_size.kib()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Check that the host reserves enough hugepages of the default size for the VM.
+// libvirt backs guest memory with the default hugepage size when the page size is not specified.
+fn check_host_hugepages(hugepage_size: HugepageSize, memory: u64) -> Result<(), anyhow::Error> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let default_kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("failed to read default hugepage size from /proc/meminfo"))?;
+    if default_kib != hugepage_size.kib() {
+        return Err(anyhow!(
+            "hugepage size {} kB differs from the default hugepage size {default_kib} kB of the host",
+            hugepage_size.kib()
+        ));
+    }
+
+    let nr_hugepages_path = format!(
+        "/sys/kernel/mm/hugepages/hugepages-{}kB/nr_hugepages",
+        hugepage_size.kib()
+    );
+    let reserved = fs::read_to_string(&nr_hugepages_path)?.trim().parse::<u64>()?;
+    let required = hugepage_size.pages_for(memory);
+    if reserved < required {
+        return Err(anyhow!(
+            "{required} hugepages of {} kB are required but only {reserved} are reserved on the host (see {nr_hugepages_path})",
+            hugepage_size.kib()
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum DiskBus {
+    #[serde(rename = "virtio-blk")]
+    VirtioBlk,
+    #[serde(rename = "virtio-scsi")]
+    VirtioScsi,
+    #[serde(rename = "sata")]
+    Sata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DiskCache {
+    None,
+    Writeback,
+    Writethrough,
+    Directsync,
+    Unsafe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DiskIo {
+    Native,
+    Threads,
+    IoUring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ImageFormat {
+    Qcow2,
+    Raw,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DataDisk {
+    // in GB
+    size: u64,
+}
+
+// Disk options passed through to libvirt. The root disk is always a qcow2 overlay of the box image,
+// so `disk_format` applies to data disks only.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct StorageOptions {
+    disk_bus: Option<DiskBus>,
+    disk_cache: Option<DiskCache>,
+    disk_io: Option<DiskIo>,
+    disk_format: Option<ImageFormat>,
+    #[serde(default)]
+    data_disks: Vec<DataDisk>,
+}
+
+impl StorageOptions {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::new(
+                "disk_bus",
+                Schema::Enum(&["virtio-blk", "virtio-scsi", "sata"]).nullable(),
+                "Bus of the disks",
+            ),
+            Property::new(
+                "disk_cache",
+                Schema::Enum(&["none", "writeback", "writethrough", "directsync", "unsafe"]).nullable(),
+                "Cache mode of the disks",
+            ),
+            Property::new(
+                "disk_io",
+                Schema::Enum(&["native", "threads", "io_uring"]).nullable(),
+                "I/O mode of the disks, native requires disk_cache none or directsync",
+            ),
+            Property::new(
+                "disk_format",
+                Schema::Enum(&["qcow2", "raw"]).nullable(),
+                "Image format of the data disks",
+            ),
+            Property::new(
+                "data_disks",
+                Schema::array(Schema::object(vec![Property::new(
+                    "size",
+                    Schema::integer(1),
+                    "Size in GB",
+                )
+                .required()])),
+                "Data disks attached in addition to the root disk",
+            ),
+        ]
+    }
+
+    fn validate(&self, disk_size: Option<u64>) -> Result<(), anyhow::Error> {
+        if disk_size == Some(0) {
+            return Err(anyhow!("disk_size must be greater than 0"));
+        }
+        // QEMU requires O_DIRECT for native AIO
+        if self.disk_io == Some(DiskIo::Native)
+            && !matches!(self.disk_cache, Some(DiskCache::None | DiskCache::Directsync))
+        {
+            return Err(anyhow!("disk_io native requires disk_cache none or directsync"));
+        }
+        if self.data_disks.iter().any(|disk| disk.size == 0) {
+            return Err(anyhow!("size of data disk must be greater than 0"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NicModel {
+    Virtio,
+    E1000,
+}
+
+const DEFAULT_MANAGEMENT_NETWORK_NAME: &str = "vagrant-net0";
+// avoid network segment conflict with default network
+const DEFAULT_MANAGEMENT_NETWORK_ADDRESS: &str = "192.168.124.0/24";
+// Management network of L1 VM created on the host by vagrant-libvirt, routed inside L1 VM
+const L1_MANAGEMENT_NETWORK: (&str, &str) = ("vagrant-libvirt", "192.168.121.0/24");
+// Default network of libvirt installed inside L1 VM, assumed until L1 VM can be queried
+const L1_DEFAULT_LIBVIRT_NETWORK: (&str, &str) = ("default", "192.168.122.0/24");
+
+// Network options of L2 VM passed through to libvirt
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct NetworkOptions {
+    management_network_name: Option<String>,
+    management_network_address: Option<String>,
+    nic_model: Option<NicModel>,
+    // number of virtio-net queues
+    nic_queues: Option<u32>,
+    mtu: Option<u32>,
+}
+
+impl NetworkOptions {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::new(
+                "management_network_name",
+                Schema::String.nullable(),
+                "Name of the management network, vagrant-net0 by default",
+            ),
+            Property::new(
+                "management_network_address",
+                Schema::String.nullable(),
+                "Subnet of the management network, 192.168.124.0/24 by default",
+            ),
+            Property::new("nic_model", Schema::Enum(&["virtio", "e1000"]).nullable(), "Model of the NICs"),
+            Property::new(
+                "nic_queues",
+                Schema::integer(1).nullable(),
+                "Number of virtio-net queues",
+            ),
+            Property::new(
+                "mtu",
+                Schema::Integer {
+                    minimum: 68,
+                    maximum: Some(65535),
+                }
+                .nullable(),
+                "MTU of the NICs",
+            ),
+        ]
+    }
+
+    fn management_network_name(&self) -> &str {
+        self.management_network_name
+            .as_deref()
+            .unwrap_or(DEFAULT_MANAGEMENT_NETWORK_NAME)
+    }
+
+    fn management_subnet(&self) -> Result<Ipv4Subnet, anyhow::Error> {
+        self.management_network_address
+            .as_deref()
+            .unwrap_or(DEFAULT_MANAGEMENT_NETWORK_ADDRESS)
+            .parse()
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        self.management_subnet()?;
+        if let Some(nic_queues) = self.nic_queues {
+            if nic_queues == 0 {
+                return Err(anyhow!("nic_queues must be greater than 0"));
+            }
+            if self.nic_model == Some(NicModel::E1000) {
+                return Err(anyhow!("nic_queues requires nic_model virtio"));
+            }
+        }
+        if let Some(mtu) = self.mtu {
+            if !(68..=65535).contains(&mtu) {
+                return Err(anyhow!("mtu must be between 68 and 65535"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check that the management network does not collide with existing libvirt networks.
+    // A network with the same name is the one created for this VM, so it is skipped.
+    fn check_subnet_collision(
+        &self,
+        networks: &[(String, Ipv4Subnet)],
+    ) -> Result<(), anyhow::Error> {
+        let subnet = self.management_subnet()?;
+        for (name, network_subnet) in networks {
+            if name != self.management_network_name() && subnet.overlaps(network_subnet) {
+                return Err(anyhow!(
+                    "management network {subnet} collides with libvirt network {name} ({network_subnet})"
+                ));
+            }
+        }
+
+        Ok(())
     }
 }
 
+// libvirt networks inside L1 VM, queried over vagrant ssh if L1 VM has been created, including
+// the management network of L1 VM
+fn l1_libvirt_networks(l1_vagrant_dir: &Path) -> Result<Vec<(String, Ipv4Subnet)>, anyhow::Error> {
+    let known_network = |(name, subnet): (&str, &str)| -> Result<_, anyhow::Error> {
+        Ok((name.to_string(), subnet.parse()?))
+    };
+    let mut networks = vec![known_network(L1_MANAGEMENT_NETWORK)?];
+    let queried = if l1_vagrant_dir.join(".vagrant").join("machines").exists() {
+        match process::Command::new("vagrant")
+            .current_dir(l1_vagrant_dir)
+            .arg("ssh")
+            .arg("-c")
+            .arg(LIBVIRT_NETWORKS_SCRIPT)
+            .stdin(process::Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => Some(network::parse_libvirt_networks(
+                &String::from_utf8_lossy(&output.stdout),
+            )),
+            _ => {
+                warn!("failed to list libvirt networks in L1 VM, assuming the default network");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    match queried {
+        Some(queried) => networks.extend(queried),
+        None => networks.push(known_network(L1_DEFAULT_LIBVIRT_NETWORK)?),
+    }
+    Ok(networks)
+}
+
+fn host_libvirt_networks() -> Vec<(String, Ipv4Subnet)> {
+    match process::Command::new("sh")
+        .arg("-c")
+        .arg(LIBVIRT_NETWORKS_SCRIPT)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            network::parse_libvirt_networks(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => {
+            warn!("failed to list libvirt networks on the host");
+            vec![]
+        }
+    }
+}
+
+// Interface of the default route of the host
+fn host_default_interface() -> Option<String> {
+    let output = process::Command::new("ip")
+        .args(["route", "show", "default"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    network::parse_default_interface(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Properties shared by VMs of every level
+fn vm_properties() -> Vec<Property> {
+    vec![
+        Property::new("host_name", Schema::String, "Host name of the VM"),
+        Property::new("cpus", Schema::integer(1), "Number of vCPUs"),
+        Property::new("memory", Schema::integer(1), "Memory in MB"),
+        Property::new(
+            "cpu_mode",
+            Schema::Enum(&["custom", "host-passthrough", "host-model", "maximum"]),
+            "CPU mode of libvirt",
+        ),
+    ]
+}
+
+fn disk_size_property() -> Property {
+    Property::new(
+        "disk_size",
+        Schema::integer(1).nullable(),
+        "Size of the root disk in GB, the size of the box image if not set",
+    )
+}
+
+fn bench_role_property() -> Property {
+    Property::new(
+        "bench_role",
+        Schema::Enum(&["victim", "aggressor"]).nullable(),
+        "Role of the VM in the bench, aggressors are stopped when the other VMs finish",
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct L1VagrantConfig {
     host_name: String,
@@ -136,19 +1017,41 @@ struct L1VagrantConfig {
     memory: u64,
     #[serde(default)]
     cpu_mode: CpuMode,
+    #[serde(flatten)]
+    cpu_options: CpuOptions,
+    #[serde(flatten)]
+    memory_options: MemoryOptions,
+    #[serde(flatten)]
+    storage_options: StorageOptions,
     network_interface: Option<String>,
     kvm_options: HashMap<String, String>,
     disk_size: Option<u64>,
+    // Local QEMU install prefix or .deb package used as the emulator for L2 VMs
+    custom_qemu: Option<PathBuf>,
+    #[serde(default)]
+    provision: Vec<ProvisionStep>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GeneratedL1VagrantConfig {
+    version: u32,
     #[serde(flatten)]
     l1_vagrant_config: L1VagrantConfig,
 
     arch: Arch,
     l2_vagrant_dir: PathBuf,
+    domain_prefix: Option<String>,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BenchRole {
+    // measured VM, bench results are collected after it finishes
+    Victim,
+    // VM generating noise, stopped when all the other VMs finish
+    Aggressor,
 }
+
+// L2 VM, which runs either inside L1 VM or on the host with --no-nested
 #[derive(Debug, Serialize, Deserialize)]
 struct L2VagrantConfig {
     host_name: String,
@@ -156,37 +1059,193 @@ struct L2VagrantConfig {
     memory: u64,
     #[serde(default)]
     cpu_mode: CpuMode,
+    #[serde(flatten)]
+    cpu_options: CpuOptions,
+    #[serde(flatten)]
+    memory_options: MemoryOptions,
+    #[serde(flatten)]
+    storage_options: StorageOptions,
+    #[serde(flatten)]
+    network_options: NetworkOptions,
     #[serde(default)]
     enable_network_bridge: bool,
+    // bridge devices of the machine running the VM, which is L1 VM or the host with --no-nested.
+    // The one of the placement enables the bridge as well, the default interface of the machine
+    // being used if only enable_network_bridge is set.
+    l1_bridge_device: Option<String>,
+    host_bridge_device: Option<String>,
     disk_size: Option<u64>,
+    bench_role: Option<BenchRole>,
+    #[serde(default)]
+    provision: Vec<ProvisionStep>,
+}
+
+// Value of BENCH_ROLE passed to the bench script
+fn bench_role_name(bench_role: Option<BenchRole>) -> &'static str {
+    match bench_role {
+        Some(BenchRole::Victim) => "victim",
+        Some(BenchRole::Aggressor) => "aggressor",
+        None => "",
+    }
+}
+
+// L1 config merged from the built-in defaults, the user and project configs, the config file and
+// `--set` overrides
+fn load_l1_config(
+    config_loader: &ConfigLoader,
+    l1_config_path: Option<&Path>,
+) -> Result<L1VagrantConfig, anyhow::Error> {
+    config_loader
+        .l1::<L1VagrantConfig>(l1_config_path)?
+        .deserialize()
+        .map_err(|err| anyhow!("invalid L1 config: {err}"))
+}
+
+fn load_l2_configs(
+    config_loader: &ConfigLoader,
+    l2_config_path: Option<&Path>,
+) -> Result<Vec<L2VagrantConfig>, anyhow::Error> {
+    config_loader
+        .l2::<L2VagrantConfig>(l2_config_path)?
+        .iter()
+        .map(|vm| {
+            vm.deserialize().map_err(|err| {
+                anyhow!("invalid L2 config {}: {err}", vm.host_name().unwrap_or_default())
+            })
+        })
+        .collect()
+}
+
+// New L1 config of provision, which is the current config with `--set` overrides unless a config
+// file is given. None if the config is not changed.
+fn provision_l1_config(
+    config_loader: &ConfigLoader,
+    l1_config_path: Option<&Path>,
+    current: &L1VagrantConfig,
+) -> Result<Option<L1VagrantConfig>, anyhow::Error> {
+    if l1_config_path.is_some() {
+        return load_l1_config(config_loader, l1_config_path).map(Some);
+    }
+    if !config_loader.overrides(Level::L1) {
+        return Ok(None);
+    }
+    config_loader
+        .with_overrides(Level::L1, current)?
+        .deserialize()
+        .map(Some)
+        .map_err(|err| anyhow!("invalid L1 config: {err}"))
+}
+
+fn provision_l2_configs<'a>(
+    config_loader: &ConfigLoader,
+    l2_config_path: Option<&Path>,
+    current: impl IntoIterator<Item = &'a L2VagrantConfig>,
+) -> Result<Option<Vec<L2VagrantConfig>>, anyhow::Error> {
+    if l2_config_path.is_some() {
+        return load_l2_configs(config_loader, l2_config_path).map(Some);
+    }
+    if !config_loader.overrides(Level::L2) {
+        return Ok(None);
+    }
+    current
+        .into_iter()
+        .map(|vm| {
+            config_loader
+                .with_overrides(Level::L2, vm)?
+                .deserialize()
+                .map_err(|err| anyhow!("invalid L2 config {}: {err}", vm.host_name))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+// Where L2 VMs run. Fields of the generated config depending on it are derived from the
+// placement, so that the same L2 config drives a nested L2 VM and its no-nested twin.
+#[derive(Debug)]
+enum L2Placement {
+    // inside L1 VM, with the emulator installed by custom_qemu of L1 VM
+    Nested { emulator_path: Option<PathBuf> },
+    // on the host, with the domain prefix of the project
+    NoNested { domain_prefix: Option<String> },
+}
+
+impl L2Placement {
+    fn nested(&self) -> bool {
+        matches!(self, L2Placement::Nested { .. })
+    }
+
+    // run-bench.sh in the L2 vagrant directory, seen from the machine running vagrant
+    fn bench_script_path(&self, l2_vagrant_dir: &Path) -> PathBuf {
+        match self {
+            L2Placement::Nested { .. } => PathBuf::from("/home/vagrant/l2-vagrant/run-bench.sh"),
+            L2Placement::NoNested { .. } => l2_vagrant_dir.join("run-bench.sh"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct GeneratedL2VagrantConfig {
+struct GeneratedL2Vm {
     #[serde(flatten)]
     l2_vagrant_config: L2VagrantConfig,
-    bench_script_path: Option<PathBuf>,
+    // interface bridged to the public network, derived from the placement
     network_interface: Option<String>,
-    enable_provision_script: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct L2NoNestedVagrantConfig {
-    host_name: String,
-    cpus: u32,
-    memory: u64,
-    #[serde(default)]
-    cpu_mode: CpuMode,
-    network_interface: Option<String>,
-    disk_size: Option<u64>,
+impl GeneratedL2Vm {
+    fn new(l2_vagrant_config: L2VagrantConfig, nested: bool) -> Result<Self, anyhow::Error> {
+        let network_interface = l2_vagrant_config.bridge_interface(nested)?;
+        Ok(Self {
+            l2_vagrant_config,
+            network_interface,
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct GeneratedL2NoNestedVagrantConfig {
-    #[serde(flatten)]
-    l2_vagrant_config: L2NoNestedVagrantConfig,
+struct GeneratedL2VagrantConfig {
+    version: u32,
+    vms: Vec<GeneratedL2Vm>,
     bench_script_path: Option<PathBuf>,
+    // directory of the scripts of --bench-suite, uploaded to `~/bench-suite` of the VMs
+    bench_suite_path: Option<PathBuf>,
     enable_provision_script: bool,
+    // emulator inside L1 VM, only for nested L2 VMs
+    emulator_path: Option<PathBuf>,
+    // prefix of libvirt domains on the host, only for no-nested L2 VMs
+    domain_prefix: Option<String>,
+}
+
+impl GeneratedL2VagrantConfig {
+    // Config of the L2 VMs in the placement, the scripts being copied into `l2_vagrant_dir`
+    fn new(
+        l2_vagrant_dir: &Path,
+        l2_configs: Vec<L2VagrantConfig>,
+        bench_script: bool,
+        provision_script: bool,
+        placement: &L2Placement,
+    ) -> Result<Self, anyhow::Error> {
+        let (emulator_path, domain_prefix) = match placement {
+            L2Placement::Nested { emulator_path } => (emulator_path.clone(), None),
+            L2Placement::NoNested { domain_prefix } => (None, domain_prefix.clone()),
+        };
+        Ok(Self {
+            version: PROJECT_VERSION,
+            vms: l2_configs
+                .into_iter()
+                .map(|l2_config| GeneratedL2Vm::new(l2_config, placement.nested()))
+                .collect::<Result<_, _>>()?,
+            bench_script_path: bench_script
+                .then(|| placement.bench_script_path(l2_vagrant_dir)),
+            bench_suite_path: None,
+            enable_provision_script: provision_script,
+            emulator_path,
+            domain_prefix,
+        })
+    }
+
+    fn into_l2_configs(self) -> Vec<L2VagrantConfig> {
+        self.vms.into_iter().map(|vm| vm.l2_vagrant_config).collect()
+    }
 }
 
 impl Default for L1VagrantConfig {
@@ -196,9 +1255,14 @@ impl Default for L1VagrantConfig {
             cpus: 2,
             memory: 4096,
             cpu_mode: CpuMode::Custom,
+            cpu_options: CpuOptions::default(),
+            memory_options: MemoryOptions::default(),
+            storage_options: StorageOptions::default(),
             network_interface: None,
             kvm_options: HashMap::new(),
             disk_size: None,
+            custom_qemu: None,
+            provision: vec![],
         }
     }
 }
@@ -210,694 +1274,1590 @@ impl Default for L2VagrantConfig {
             cpus: 2,
             memory: 2048,
             cpu_mode: CpuMode::Custom,
+            cpu_options: CpuOptions::default(),
+            memory_options: MemoryOptions::default(),
+            storage_options: StorageOptions::default(),
+            network_options: NetworkOptions::default(),
             enable_network_bridge: false,
+            l1_bridge_device: None,
+            host_bridge_device: None,
             disk_size: None,
+            bench_role: None,
+            provision: vec![],
         }
     }
 }
 
-impl Default for L2NoNestedVagrantConfig {
-    fn default() -> Self {
-        Self {
-            host_name: "l2-vagrant".to_string(),
-            cpus: 2,
-            memory: 2048,
-            cpu_mode: CpuMode::Custom,
-            network_interface: None,
-            disk_size: None,
+impl LayeredConfig for L1VagrantConfig {
+    fn schema() -> Schema {
+        let mut properties = vm_properties();
+        properties.extend(CpuOptions::properties());
+        properties.extend(MemoryOptions::properties());
+        properties.extend(StorageOptions::properties());
+        properties.extend([
+            Property::new(
+                "network_interface",
+                Schema::String.nullable(),
+                "Host interface bridged to the public network of the VM",
+            ),
+            Property::new(
+                "kvm_options",
+                Schema::map(Schema::String),
+                "Parameters of the kvm module inside the VM such as nested: Y",
+            ),
+            disk_size_property(),
+            Property::new(
+                "custom_qemu",
+                Schema::String.nullable(),
+                "Local QEMU install prefix or .deb package used as the emulator of L2 VMs",
+            ),
+            provision::properties(),
+        ]);
+        Schema::object(properties)
+    }
+}
+
+impl LayeredConfig for L2VagrantConfig {
+    fn schema() -> Schema {
+        let mut properties = vm_properties();
+        properties.extend(CpuOptions::properties());
+        properties.extend(MemoryOptions::properties());
+        properties.extend(StorageOptions::properties());
+        properties.extend(NetworkOptions::properties());
+        properties.extend([
+            Property::new(
+                "enable_network_bridge",
+                Schema::Boolean,
+                "Bridge the public network of the VM to the default interface of L1 VM, or the host with --no-nested",
+            ),
+            Property::new(
+                "l1_bridge_device",
+                Schema::String.nullable(),
+                "Device of L1 VM bridged to the public network of the VM, instead of eth0",
+            ),
+            Property::new(
+                "host_bridge_device",
+                Schema::String.nullable(),
+                "Host device bridged to the public network of the VM with --no-nested, instead of the default interface",
+            ),
+            Property::deprecated("bridge_device", "l1_bridge_device", Schema::String.nullable()),
+            Property::deprecated("network_interface", "host_bridge_device", Schema::String.nullable()),
+            disk_size_property(),
+            bench_role_property(),
+            provision::properties(),
+        ]);
+        Schema::object(properties)
+    }
+}
+
+impl L1VagrantConfig {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let check = || -> Result<(), anyhow::Error> {
+            self.cpu_options
+                .validate(&self.cpu_mode, self.cpus, self.memory)?;
+            if let Some(cpu_pinning) = &self.cpu_options.cpu_pinning {
+                validate_host_cpu_pinning(cpu_pinning, self.cpus)
+                    .map_err(LauncherError::preflight)?;
+            }
+            self.memory_options.validate(self.memory)?;
+            self.storage_options.validate(self.disk_size)?;
+            provision::validate(&self.provision)?;
+            if let Some(hugepage_size) = self.memory_options.hugepage_size {
+                check_host_hugepages(hugepage_size, self.memory)
+                    .map_err(LauncherError::preflight)?;
+            }
+            Ok(())
+        };
+        check().map_err(|err| LauncherError::map(err, |err| anyhow!("invalid L1 config: {err}")))
+    }
+}
+
+impl L2VagrantConfig {
+    // Validate the VM running inside L1 VM, or on the host if l1_config is None. The management
+    // network must not collide with `networks` besides the libvirt networks of the host.
+    fn validate(
+        &self,
+        l1_config: Option<&L1VagrantConfig>,
+        networks: &[(String, Ipv4Subnet)],
+    ) -> Result<(), anyhow::Error> {
+        let check = || -> Result<(), anyhow::Error> {
+            self.cpu_options
+                .validate(&self.cpu_mode, self.cpus, self.memory)?;
+            if let Some(l1_config) = l1_config {
+                if self.cpus > l1_config.cpus {
+                    return Err(anyhow!(
+                        "cpus {} exceeds cpus {} of L1 VM",
+                        self.cpus,
+                        l1_config.cpus
+                    ));
+                }
+            }
+            if let Some(cpu_pinning) = &self.cpu_options.cpu_pinning {
+                match l1_config {
+                    Some(l1_config) => {
+                        cpu_pinning.validate(self.cpus, &(0..l1_config.cpus).collect())?
+                    }
+                    None => validate_host_cpu_pinning(cpu_pinning, self.cpus)
+                        .map_err(LauncherError::preflight)?,
+                }
+            }
+            self.memory_options.validate(self.memory)?;
+            self.storage_options.validate(self.disk_size)?;
+            self.network_options.validate()?;
+            provision::validate(&self.provision)?;
+            // L2 VM must not collide with the networks of L1 VM and the host
+            let mut networks = networks.to_vec();
+            networks.extend(host_libvirt_networks());
+            self.network_options
+                .check_subnet_collision(&networks)
+                .map_err(LauncherError::preflight)?;
+            if l1_config.is_none() {
+                self.bridge_interface(false)
+                    .map_err(LauncherError::preflight)?;
+            }
+            Ok(())
+        };
+        check().map_err(|err| {
+            LauncherError::map(err, |err| anyhow!("invalid L2 config {}: {err}", self.host_name))
+        })
+    }
+
+    // Device bridged to the public network of the VM, inside L1 VM if nested or on the host
+    fn bridge_interface(&self, nested: bool) -> Result<Option<String>, anyhow::Error> {
+        let bridge_device = if nested {
+            &self.l1_bridge_device
+        } else {
+            &self.host_bridge_device
+        };
+        if let Some(bridge_device) = bridge_device {
+            return Ok(Some(bridge_device.clone()));
+        }
+        if !self.enable_network_bridge {
+            return Ok(None);
+        }
+        if nested {
+            return Ok(Some("eth0".to_string()));
+        }
+        host_default_interface().map(Some).ok_or_else(|| {
+            anyhow!("enable_network_bridge is set but the host has no default route, set host_bridge_device")
+        })
+    }
+}
+
+// Checks shared by L2 VMs defined in the same Vagrantfile. Returns the hugepage size and the
+// total memory backed by hugepages.
+fn validate_l2_vm_set(
+    l2_configs: &[L2VagrantConfig],
+) -> Result<Option<(HugepageSize, u64)>, anyhow::Error> {
+    if l2_configs.is_empty() {
+        return Err(anyhow!("invalid L2 config: no L2 VM is defined"));
+    }
+
+    let mut host_names = BTreeSet::new();
+    if let Some(l2_config) = l2_configs
+        .iter()
+        .find(|l2_config| !host_names.insert(&l2_config.host_name))
+    {
+        return Err(anyhow!(
+            "invalid L2 config: host_name {} is duplicated",
+            l2_config.host_name
+        ));
+    }
+    if l2_configs
+        .iter()
+        .all(|l2_config| l2_config.bench_role == Some(BenchRole::Aggressor))
+    {
+        return Err(anyhow!("invalid L2 config: all L2 VMs are aggressors"));
+    }
+
+    // L2 VMs are attached to the same management network
+    let management_networks = l2_configs
+        .iter()
+        .map(|l2_config| {
+            let network_options = &l2_config.network_options;
+            Ok((
+                network_options.management_network_name(),
+                network_options.management_subnet()?,
+            ))
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    if management_networks.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err(anyhow!(
+            "invalid L2 config: all L2 VMs must share the same management network"
+        ));
+    }
+
+    let hugepage_configs: Vec<_> = l2_configs
+        .iter()
+        .filter(|l2_config| l2_config.memory_options.hugepage_size.is_some())
+        .collect();
+    let Some(hugepage_size) = hugepage_configs
+        .first()
+        .and_then(|first| first.memory_options.hugepage_size)
+    else {
+        return Ok(None);
+    };
+    if hugepage_configs
+        .iter()
+        .any(|l2_config| l2_config.memory_options.hugepage_size != Some(hugepage_size))
+    {
+        return Err(anyhow!(
+            "invalid L2 config: all L2 VMs must use the same hugepage_size"
+        ));
+    }
+    let hugepage_memory = hugepage_configs.iter().map(|l2_config| l2_config.memory).sum();
+    Ok(Some((hugepage_size, hugepage_memory)))
+}
+
+// Validate L2 VMs sharing the same L1 VM
+fn validate_l2_configs(
+    l2_configs: &[L2VagrantConfig],
+    l1_config: &L1VagrantConfig,
+    l1_vagrant_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    let l1_networks = l1_libvirt_networks(l1_vagrant_dir)?;
+    for l2_config in l2_configs {
+        l2_config.validate(Some(l1_config), &l1_networks)?;
+    }
+
+    validate_l2_vm_set(l2_configs)?;
+
+    // L1 VM needs memory for itself besides L2 VMs, including hugepages reserved for them on
+    // provisioning
+    let l2_memory: u64 = l2_configs.iter().map(|l2_config| l2_config.memory).sum();
+    if l2_memory >= l1_config.memory {
+        return Err(anyhow!(
+            "invalid L2 config: L2 VMs have {l2_memory} MB memory in total, which does not fit in L1 memory {} MB",
+            l1_config.memory
+        ));
+    }
+    // disk images of L2 VMs are stored in the root disk of L1 VM
+    if let Some(l1_disk_size) = l1_config.disk_size {
+        let l2_disk_size: u64 = l2_configs
+            .iter()
+            .map(|l2_config| {
+                l2_config.disk_size.unwrap_or(0)
+                    + l2_config
+                        .storage_options
+                        .data_disks
+                        .iter()
+                        .map(|disk| disk.size)
+                        .sum::<u64>()
+            })
+            .sum();
+        if l2_disk_size >= l1_disk_size {
+            return Err(anyhow!(
+                "invalid L2 config: L2 VMs have {l2_disk_size} GB disks in total, which does not fit in L1 disk_size {l1_disk_size} GB"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Validate no-nested L2 VMs running on the host
+fn validate_l2_no_nested_configs(l2_configs: &[L2VagrantConfig]) -> Result<(), anyhow::Error> {
+    for l2_config in l2_configs {
+        l2_config.validate(None, &[])?;
+    }
+
+    if let Some((hugepage_size, hugepage_memory)) = validate_l2_vm_set(l2_configs)? {
+        check_host_hugepages(hugepage_size, hugepage_memory)
+            .map_err(|err| LauncherError::preflight(anyhow!("invalid L2 config: {err}")))?;
+    }
+
+    Ok(())
+}
+
+// Host resources reserved by L1 VM, L2 VMs run inside it
+fn l1_project_entry(
+    project_dir: &Path,
+    l1_config: &L1VagrantConfig,
+    domain_prefix: Option<String>,
+) -> Result<ProjectEntry, anyhow::Error> {
+    Ok(ProjectEntry {
+        project_dir: project_dir.to_path_buf(),
+        nested: true,
+        pid: process::id(),
+        cpus: l1_config.cpus,
+        memory: l1_config.memory,
+        pinned_cpus: match &l1_config.cpu_options.cpu_pinning {
+            Some(cpu_pinning) => cpu_pinning.pinned_cpus()?,
+            None => BTreeSet::new(),
+        },
+        // L1 VM is attached to the network shared by every vagrant-libvirt VM
+        networks: vec![],
+        domain_prefix,
+    })
+}
+
+fn no_nested_project_entry<'a>(
+    project_dir: &Path,
+    l2_configs: impl IntoIterator<Item = &'a L2VagrantConfig>,
+    domain_prefix: Option<String>,
+) -> Result<ProjectEntry, anyhow::Error> {
+    let mut cpus = 0;
+    let mut memory = 0;
+    let mut pinned_cpus = BTreeSet::new();
+    let mut networks = vec![];
+    for l2_config in l2_configs {
+        cpus += l2_config.cpus;
+        memory += l2_config.memory;
+        if let Some(cpu_pinning) = &l2_config.cpu_options.cpu_pinning {
+            pinned_cpus.extend(cpu_pinning.pinned_cpus()?);
+        }
+        let network = RegisteredNetwork {
+            name: l2_config.network_options.management_network_name().to_string(),
+            address: l2_config.network_options.management_subnet()?.to_string(),
+        };
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    Ok(ProjectEntry {
+        project_dir: project_dir.to_path_buf(),
+        nested: false,
+        pid: process::id(),
+        cpus,
+        memory,
+        pinned_cpus,
+        networks,
+        domain_prefix,
+    })
+}
+
+// Attach no-nested VMs without an explicit management network to the network of the project
+fn assign_management_network(
+    l2_configs: &mut [L2VagrantConfig],
+    name: &str,
+    subnet: Ipv4Subnet,
+) {
+    if l2_configs.iter().any(|l2_config| {
+        let network_options = &l2_config.network_options;
+        network_options.management_network_name.is_some()
+            || network_options.management_network_address.is_some()
+    }) {
+        return;
+    }
+    for l2_config in l2_configs {
+        l2_config.network_options.management_network_name = Some(name.to_string());
+        l2_config.network_options.management_network_address = Some(subnet.to_string());
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct L2VmMetadata {
+    host_name: String,
+    bench_role: Option<BenchRole>,
+    storage: StorageOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RunMetadata {
+    version: u32,
+    nested: bool,
+    emulator_version: Option<String>,
+    l1_storage: Option<StorageOptions>,
+    l2_vms: Vec<L2VmMetadata>,
+    // options of the bench script, none for net-bench
+    bench: Option<BenchOptions>,
+}
+
+const CUSTOM_QEMU_INSTALL_DIR: &str = "/opt/custom-qemu";
+
+// Copy custom QEMU into l1-vagrant/custom-qemu so that it is uploaded to L1 VM on provisioning
+fn stage_custom_qemu(
+    executor: &mut Executor,
+    l1_vagrant_dir: &Path,
+    custom_qemu: Option<&PathBuf>,
+) -> Result<(), anyhow::Error> {
+    let custom_qemu_dest = l1_vagrant_dir.join("custom-qemu");
+    executor.remove_dir(&custom_qemu_dest)?;
+    let Some(custom_qemu) = custom_qemu else {
+        return Ok(());
+    };
+
+    if custom_qemu.is_dir() {
+        executor.copy_dir(custom_qemu, &custom_qemu_dest)
+    } else if custom_qemu.extension().is_some_and(|ext| ext == "deb") {
+        let file_name = custom_qemu
+            .file_name()
+            .ok_or_else(|| anyhow!("invalid custom_qemu path: {}", custom_qemu.display()))?;
+        executor.copy_file(custom_qemu, &custom_qemu_dest.join(file_name))
+    } else {
+        Err(anyhow!(
+            "custom_qemu must be a QEMU install directory or a .deb package: {}",
+            custom_qemu.display()
+        ))
+    }
+}
+
+// Emulator path inside L1 VM to be used for L2 VM
+fn custom_qemu_emulator_path(custom_qemu: &Path) -> Option<PathBuf> {
+    if custom_qemu.extension().is_some_and(|ext| ext == "deb") {
+        // deb package replaces the system QEMU, so libvirt uses it by default
+        None
+    } else {
+        Some(PathBuf::from(CUSTOM_QEMU_INSTALL_DIR).join("bin/qemu-system-x86_64"))
+    }
+}
+
+// Create the vagrant directory from the template if it does not exist or overwrite is true
+fn create_from_template(
+    executor: &mut Executor,
+    resource_path: &Path,
+    template: &str,
+    dest: &Path,
+    overwrite: bool,
+) -> Result<(), anyhow::Error> {
+    if dest.exists() {
+        let dir_name = dest.file_name().unwrap_or_default().to_string_lossy();
+        info!("{dir_name} directory already exists");
+        if !overwrite {
+            return Err(anyhow!("{dir_name} directory already exists"));
         }
+        executor.remove_dir(dest)?;
     }
+    provision::copy_template(executor, resource_path, template, dest)
 }
 
-static ANSI_ESCAPE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\x1B\[([0-9;]+)?[A-Za-z]").unwrap());
-
 fn create_l1_vagrant_directory(
-    l1_vagrant_dest: &PathBuf,
+    executor: &mut Executor,
+    l1_vagrant_dest: &Path,
     arch: Arch,
-    resource_path: &PathBuf,
-    l1_vagrant_config: L1VagrantConfig,
+    resource_path: &Path,
+    mut l1_vagrant_config: L1VagrantConfig,
+    domain_prefix: Option<String>,
     overwrite: bool,
-) -> Result<(), anyhow::Error> {
-    let l1_vagrant_template_path = resource_path.join("l1-vagrant-template");
-
-    // Create l1-vagrant directory from template if it does not exist or overwrite is true
-    if l1_vagrant_dest.exists() {
-        println!("l1-vagrant directory already exists");
-        if overwrite {
-            fs_extra::dir::remove(l1_vagrant_dest.as_path())?;
-        } else {
-            return Err(anyhow!("l1-vagrant directory already exists"));
-        }
-    }
-    fs_extra::dir::create_all(l1_vagrant_dest.as_path(), false)?;
-    fs_extra::dir::copy(
-        l1_vagrant_template_path.as_path(),
-        l1_vagrant_dest.as_path(),
-        &fs_extra::dir::CopyOptions::new().content_only(true),
-    )?;
+) -> Result<GeneratedL1VagrantConfig, anyhow::Error> {
+    step_in("template-copy", l1_vagrant_dest).in_scope(|| -> Result<(), anyhow::Error> {
+        create_from_template(
+            executor,
+            resource_path,
+            "l1-vagrant-template",
+            l1_vagrant_dest,
+            overwrite,
+        )?;
+        stage_custom_qemu(executor, l1_vagrant_dest, l1_vagrant_config.custom_qemu.as_ref())?;
+        let files = provision::staged_files(
+            l1_vagrant_dest,
+            &l1_vagrant_config.host_name,
+            &mut l1_vagrant_config.provision,
+        )?;
+        provision::stage(executor, l1_vagrant_dest, &files)
+    })?;
 
     // Write l1-vagrant config
+    let _step = step_in("config-write", l1_vagrant_dest).entered();
     let generated_l1_config = GeneratedL1VagrantConfig {
+        version: PROJECT_VERSION,
         l1_vagrant_config,
         arch,
         l2_vagrant_dir: PathBuf::from("../l2-vagrant"),
+        domain_prefix,
     };
-    let l1_vagrant_config_file = std::fs::File::create(l1_vagrant_dest.join("config.yaml"))?;
-    serde_yaml::to_writer(l1_vagrant_config_file, &generated_l1_config)?;
+    executor.write_config(&l1_vagrant_dest.join("config.yaml"), &generated_l1_config)?;
 
-    Ok(())
+    Ok(generated_l1_config)
 }
 
-fn create_l2_no_nested_vagrant_directory(
-    l2_vagrant_dest: &PathBuf,
-    resource_path: &PathBuf,
-    l2_vagrant_config: L2NoNestedVagrantConfig,
+#[allow(clippy::too_many_arguments)]
+fn create_l2_vagrant_directory(
+    executor: &mut Executor,
+    l2_vagrant_dest: &Path,
+    resource_path: &Path,
+    l2_vagrant_configs: Vec<L2VagrantConfig>,
     bench_script_path: Option<&PathBuf>,
     l2_provision_script_path: Option<&PathBuf>,
+    placement: &L2Placement,
     overwrite: bool,
-) -> Result<(), anyhow::Error> {
-    let l2_vagrant_template_path = resource_path.join("l2-vagrant-template");
-
-    // Create l2-vagrant directory from template if it does not exist or overwrite is true
-    if l2_vagrant_dest.exists() {
-        println!("l2-vagrant directory already exists");
-        if overwrite {
-            fs_extra::dir::remove(l2_vagrant_dest.as_path())?;
-        } else {
-            return Err(anyhow!("l2-vagrant directory already exists"));
-        }
-    }
-    fs_extra::dir::create_all(l2_vagrant_dest.as_path(), false)?;
-    fs_extra::dir::copy(
-        l2_vagrant_template_path.as_path(),
-        l2_vagrant_dest.as_path(),
-        &fs_extra::dir::CopyOptions::new().content_only(true),
-    )?;
+) -> Result<GeneratedL2VagrantConfig, anyhow::Error> {
+    step_in("template-copy", l2_vagrant_dest).in_scope(|| {
+        create_from_template(
+            executor,
+            resource_path,
+            "l2-vagrant-template",
+            l2_vagrant_dest,
+            overwrite,
+        )
+    })?;
+
+    update_l2_config(
+        executor,
+        l2_vagrant_dest,
+        l2_vagrant_configs,
+        bench_script_path,
+        l2_provision_script_path,
+        placement,
+    )
+}
 
-    let mut l2_vagrant_config = GeneratedL2NoNestedVagrantConfig {
-        l2_vagrant_config,
-        bench_script_path: None,
-        enable_provision_script: false,
-    };
+async fn launch_vm(
+    executor: &mut Executor,
+    vagrant_dir: &Path,
+    phase_options: &PhaseOptions,
+) -> Result<(), anyhow::Error> {
+    // TODO: check if L1 VM already exists
+    let vagrant = VagrantCommand::new(vagrant_dir, &["up"]);
+    executor
+        .vagrant(Phase::Boot, phase_options, &vagrant, step_in("vagrant-up", vagrant_dir))
+        .await
+}
 
-    // Copy bench script if specified
-    if let Some(bench_script_path) = &bench_script_path {
-        let bench_script_dest = l2_vagrant_dest.join("run-bench.sh");
-        fs_extra::file::copy(
-            bench_script_path,
-            &bench_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )
-        .unwrap();
-        l2_vagrant_config.bench_script_path = Some(fs::canonicalize(&bench_script_dest)?);
-    }
+async fn provision_vm(
+    executor: &mut Executor,
+    vagrant_dir: &Path,
+    phase_options: &PhaseOptions,
+) -> Result<(), anyhow::Error> {
+    let vagrant = VagrantCommand::new(vagrant_dir, &["reload", "--provision"]);
+    executor
+        .vagrant(Phase::Provision, phase_options, &vagrant, step_in("provision", vagrant_dir))
+        .await
+}
 
-    // copy provision script
-    if let Some(provision_script_path) = &l2_provision_script_path {
-        let provision_script_dest = l2_vagrant_dest.join("init.sh");
-        fs_extra::file::copy(
-            provision_script_path,
-            provision_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
-        l2_vagrant_config.enable_provision_script = true;
-    }
+fn nested_bench_targets(l1_vagrant_dir: &Path, vms: &[GeneratedL2Vm]) -> Vec<BenchTarget> {
+    vms.iter()
+        .map(|vm| {
+            BenchTarget::nested(
+                l1_vagrant_dir,
+                &vm.l2_vagrant_config.host_name,
+                vm.l2_vagrant_config.bench_role,
+            )
+        })
+        .collect()
+}
 
-    // Write l2-vagrant config
-    let l2_vagrant_config_file = std::fs::File::create(l2_vagrant_dest.join("config.yaml"))?;
-    serde_yaml::to_writer(l2_vagrant_config_file, &l2_vagrant_config)?;
+fn no_nested_bench_targets(l2_vagrant_dir: &Path, vms: &[GeneratedL2Vm]) -> Vec<BenchTarget> {
+    vms.iter()
+        .map(|vm| {
+            BenchTarget::no_nested(
+                l2_vagrant_dir,
+                &vm.l2_vagrant_config.host_name,
+                vm.l2_vagrant_config.bench_role,
+            )
+        })
+        .collect()
+}
 
-    Ok(())
+// Upload run-l2-bench.sh of this launcher to L1 VM of nested targets, since the one uploaded when
+// L1 VM was provisioned may be older and ignore some of the bench options
+async fn upload_l2_bench_script(
+    executor: &mut Executor,
+    resource_path: &Path,
+    targets: &[BenchTarget],
+    phase_options: &PhaseOptions,
+) -> Result<(), anyhow::Error> {
+    let Some(l1_vagrant_dir) = targets.first().and_then(BenchTarget::l1_vagrant_dir) else {
+        return Ok(());
+    };
+    let script = resource_path.join("l1-vagrant-template").join(L2_BENCH_SCRIPT);
+    let script = script.to_string_lossy();
+    let destination = format!("/home/vagrant/{L2_BENCH_SCRIPT}");
+    let vagrant = VagrantCommand::new(l1_vagrant_dir, &["upload", &script, &destination]);
+    let upload_step = step_in("bench-upload", l1_vagrant_dir);
+    executor
+        .vagrant(Phase::Provision, phase_options, &vagrant, upload_step)
+        .await
 }
 
-fn create_l2_vagrant_directory(
-    l2_vagrant_dest: &PathBuf,
-    resource_path: &PathBuf,
-    l2_vagrant_config: L2VagrantConfig,
-    bench_script_path: Option<&PathBuf>,
-    l2_provision_script_path: Option<&PathBuf>,
-    overwrite: bool,
+async fn run_l2_bench(
+    executor: &mut Executor,
+    resource_path: &Path,
+    targets: &[BenchTarget],
+    bench_options: &BenchOptions,
+    output_path: Option<&PathBuf>,
+    phase_options: &PhaseOptions,
 ) -> Result<(), anyhow::Error> {
-    let l2_vagrant_template_path = resource_path.join("l2-vagrant-template");
+    upload_l2_bench_script(executor, resource_path, targets, phase_options).await?;
+    executor
+        .run_bench(
+            targets,
+            BENCH_SCRIPT,
+            bench_options,
+            output_path,
+            phase_options.timeout(Phase::Bench),
+        )
+        .instrument(step("bench"))
+        .await
+}
 
-    // Create l2-vagrant directory from template if it does not exist or overwrite is true
-    if l2_vagrant_dest.exists() {
-        println!("l2-vagrant directory already exists");
-        if overwrite {
-            fs_extra::dir::remove(l2_vagrant_dest.as_path())?;
-        } else {
-            return Err(anyhow!("l2-vagrant directory already exists"));
-        }
+// Get the first line of `--version` output of the emulator
+fn emulator_version(command: &mut process::Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
     }
-    fs_extra::dir::create_all(l2_vagrant_dest.as_path(), false)?;
-    fs_extra::dir::copy(
-        l2_vagrant_template_path.as_path(),
-        l2_vagrant_dest.as_path(),
-        &fs_extra::dir::CopyOptions::new().content_only(true),
-    )?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+}
 
-    let network_interface = if l2_vagrant_config.enable_network_bridge {
-        Some("eth0".to_string())
+// Write the environment of the VMs of the project to metadata_path
+fn record_run_metadata(
+    project_dir: &Path,
+    no_nested: bool,
+    bench_options: Option<&BenchOptions>,
+    metadata_path: &Path,
+) -> Result<(), anyhow::Error> {
+    let (l2_vagrant_dir, generated_config) = if no_nested {
+        (project_dir.join("l2-vagrant-no-nested"), GeneratedConfig::L2NoNested)
     } else {
-        None
+        (project_dir.join("l2-vagrant"), GeneratedConfig::L2)
     };
-    let mut l2_vagrant_config = GeneratedL2VagrantConfig {
-        l2_vagrant_config,
-        bench_script_path: None,
-        network_interface,
-        enable_provision_script: l2_provision_script_path.is_some(),
+    let l2_config: GeneratedL2VagrantConfig =
+        migrate::read_generated(&l2_vagrant_dir, generated_config)?;
+    let (emulator_version, l1_storage) = if no_nested {
+        let emulator_version =
+            emulator_version(process::Command::new("qemu-system-x86_64").arg("--version"));
+        (emulator_version, None)
+    } else {
+        let l1_config: GeneratedL1VagrantConfig =
+            migrate::read_generated(&project_dir.join("l1-vagrant"), GeneratedConfig::L1)?;
+        let emulator = l2_config
+            .emulator_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("qemu-system-x86_64"));
+        let emulator_version = emulator_version(
+            process::Command::new("vagrant")
+                .current_dir(project_dir.join("l1-vagrant"))
+                .arg("ssh")
+                .arg("-c")
+                .arg(format!("{} --version", emulator.display())),
+        );
+        (emulator_version, Some(l1_config.l1_vagrant_config.storage_options))
     };
-    // Copy bench script if specified
-    if let Some(bench_script_path) = &bench_script_path {
-        let bench_script_dest = l2_vagrant_dest.join("run-bench.sh");
-        fs_extra::file::copy(
-            bench_script_path,
-            &bench_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )
-        .unwrap();
-        l2_vagrant_config.bench_script_path =
-            Some(PathBuf::from("/home/vagrant/l2-vagrant/run-bench.sh"));
-    }
-
-    // copy provision script
-    if let Some(provision_script_path) = &l2_provision_script_path {
-        let provision_script_dest = l2_vagrant_dest.join("init.sh");
-        fs_extra::file::copy(
-            provision_script_path,
-            provision_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
-        l2_vagrant_config.enable_provision_script = true;
+    let metadata = RunMetadata {
+        version: PROJECT_VERSION,
+        nested: !no_nested,
+        emulator_version,
+        l1_storage,
+        l2_vms: l2_config
+            .into_l2_configs()
+            .into_iter()
+            .map(|vm| L2VmMetadata {
+                host_name: vm.host_name,
+                bench_role: vm.bench_role,
+                storage: vm.storage_options,
+            })
+            .collect(),
+        bench: bench_options.cloned(),
+    };
+    if metadata.emulator_version.is_none() {
+        warn!("failed to get emulator version");
     }
 
-    // Write l2-vagrant config
-    let l2_vagrant_config_file = std::fs::File::create(l2_vagrant_dest.join("config.yaml"))?;
-    serde_yaml::to_writer(l2_vagrant_config_file, &l2_vagrant_config)?;
+    serde_yaml::to_writer(std::fs::File::create(metadata_path)?, &metadata)?;
+    info!("run metadata written to {}", metadata_path.display());
 
     Ok(())
 }
 
-fn launch_vm(vagrant_dir: &PathBuf) -> Result<(), anyhow::Error> {
-    // TODO: check if L1 VM already exists
-    let status = process::Command::new("vagrant")
+// Command pinning CPUs of the VM running on the host, whose libvirt domain is read from the
+// vagrant directory when the command runs
+fn host_cpu_pinning_command(
+    vagrant_dir: &Path,
+    cpu_pinning: &CpuPinning,
+    host_name: &str,
+) -> process::Command {
+    let domain = format!("$(cat .vagrant/machines/{host_name}/libvirt/id)");
+    let mut command = process::Command::new("sh");
+    command
         .current_dir(vagrant_dir)
-        .arg("up")
-        .status()?;
-    if !status.success() {
-        return Err(anyhow!(format!("vagrant up failed with status: {status}")));
-    }
-
-    Ok(())
+        .arg("-c")
+        .arg(cpu_pinning.virsh_commands(&domain).join(" && "));
+    command
 }
 
-fn provision_vm(vagrant_dir: &PathBuf) -> Result<(), anyhow::Error> {
-    let status = process::Command::new("vagrant")
-        .current_dir(vagrant_dir)
-        .arg("reload")
-        .arg("--provision")
-        .status()?;
-    if !status.success() {
-        return Err(anyhow!(format!(
-            "vagrant reload failed with status: {status}"
-        )));
-    }
-
-    Ok(())
+// Command pinning CPUs of L2 VM through L1 VM
+fn l2_cpu_pinning_command(
+    l1_vagrant_dir: &Path,
+    cpu_pinning: &CpuPinning,
+    host_name: &str,
+) -> process::Command {
+    let domain = format!("$(cat /home/vagrant/l2-vagrant/.vagrant/machines/{host_name}/libvirt/id)");
+    let mut command = process::Command::new("vagrant");
+    command
+        .current_dir(l1_vagrant_dir)
+        .arg("ssh")
+        .arg("-c")
+        .arg(cpu_pinning.virsh_commands(&domain).join(" && "));
+    command
 }
 
-fn run_l2_bench(
-    l1_vagrant_dir: &PathBuf,
-    output_path: Option<&PathBuf>,
+// Apply cpu_pinning of generated configs to running VMs. VMs on the host run in vagrant_dir, and
+// L2 VMs are pinned through L1 VM if l1_config is given.
+fn apply_cpu_pinning(
+    executor: &mut Executor,
+    vagrant_dir: &Path,
+    l1_config: Option<&L1VagrantConfig>,
+    l2_vms: &[GeneratedL2Vm],
 ) -> Result<(), anyhow::Error> {
-    // run l2 bench
-    let status = process::Command::new("vagrant")
-        .current_dir(&l1_vagrant_dir)
-        .arg("ssh")
-        .arg("-c")
-        .arg("./run-l2-bench.sh")
-        .stdout(Stdio::inherit())
-        .status()?;
-    if !status.success() {
-        return Err(anyhow!(format!(
-            "running bench script failed with status: {status}"
-        )));
+    let mut commands = vec![];
+    if let Some(l1_config) = l1_config {
+        if let Some(cpu_pinning) = &l1_config.cpu_options.cpu_pinning {
+            let command = host_cpu_pinning_command(vagrant_dir, cpu_pinning, &l1_config.host_name);
+            commands.push((&l1_config.host_name, command));
+        }
     }
-
-    let output = process::Command::new("vagrant")
-        .current_dir(&l1_vagrant_dir)
-        .arg("ssh")
-        .arg("-c")
-        .arg("cat ./bench-results.txt")
-        .output()?;
-    if !output.status.success() {
-        println!("{}", String::from_utf8(output.stderr).unwrap());
-        return Err(anyhow!(format!(
-            "reading bench-results.txt failed with status: {status}"
-        )));
+    for vm in l2_vms {
+        let vm = &vm.l2_vagrant_config;
+        let Some(cpu_pinning) = &vm.cpu_options.cpu_pinning else {
+            continue;
+        };
+        let command = match l1_config {
+            Some(_) => l2_cpu_pinning_command(vagrant_dir, cpu_pinning, &vm.host_name),
+            None => host_cpu_pinning_command(vagrant_dir, cpu_pinning, &vm.host_name),
+        };
+        commands.push((&vm.host_name, command));
     }
 
-    if let Some(output_path) = &output_path {
-        let mut output_file = std::fs::File::create(output_path)?;
-        output_file.write_all(&output.stdout)?;
-        println!(
-            "Bench results written to {}",
-            output_path.to_str().unwrap_or("file")
-        );
+    for (host_name, mut command) in commands {
+        let status = executor.run(&mut command)?;
+        if !status.success() {
+            return Err(anyhow!(
+                "cpu pinning of {host_name} failed with status: {status}"
+            ));
+        }
     }
 
     Ok(())
 }
 
-async fn run_no_nested_l2_bench(
-    l2_vagrant_dir: &PathBuf,
-    output_path: Option<&PathBuf>,
-) -> Result<(), anyhow::Error> {
-    // run l2 bench
-    let mut child = tokio::process::Command::new("vagrant")
-        .current_dir(&l2_vagrant_dir)
-        .arg("ssh")
-        .arg("-c")
-        .arg("./run-bench.sh")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()?;
-    let mut stdout_outputs = String::new();
-    let mut stderr_outputs = String::new();
-    let mut child_stdout = BufReader::new(child.stdout.take().unwrap()).lines();
-    let mut child_stderr = BufReader::new(child.stderr.take().unwrap()).lines();
-
-    let res: tokio::io::Result<ExitStatus> = loop {
-        tokio::select! {
-            res = child_stdout.next_line() => {
-                if let Some(line) = res? {
-                    print!("{}\r\n", line);
-                    let mut line = ANSI_ESCAPE_PATTERN.replace_all(&line, "");
-                    line += "\n";
-                    stdout_outputs.push_str(&line);
-                }
-            }
-            res = child_stderr.next_line() => {
-                if let Some(line) = res? {
-                    eprint!("{}\r\n", line);
-                    let mut line = ANSI_ESCAPE_PATTERN.replace_all(&line, "");
-                    line += "\n";
-                    stderr_outputs.push_str(&line);
-                }
-            }
-            res = child.wait() => {
-                break res;
-            }
-        }
+fn run_delete(args: DeleteArgs) -> Result<(), anyhow::Error> {
+    let project_dir = args
+        .project_dir
+        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    let project_dir = fs::canonicalize(project_dir)?;
+    let mut executor = Executor::new("delete", &project_dir, args.dry_run);
+    executor.open_log_file()?;
+    let vagrant_dirs = if args.no_nested {
+        vec![project_dir.join("l2-vagrant-no-nested")]
+    } else {
+        vec![project_dir.join("l1-vagrant"), project_dir.join("l2-vagrant")]
     };
 
-    if !res.is_ok() || !res.as_ref().unwrap().success() {
-        return Err(anyhow!(format!(
-            "running bench script failed with status: {}",
-            res.map(|status| status.code().unwrap_or(-1)).unwrap_or(-1)
-        )));
+    let mut command = process::Command::new("vagrant");
+    command.current_dir(&vagrant_dirs[0]).arg("destroy");
+    if args.force {
+        command.arg("-f");
     }
+    let status = executor.run(&mut command)?;
+    if !status.success() {
+        warn!("vagrant destroy failed with status: {status}, continue to clean up directory");
+    }
+    for vagrant_dir in &vagrant_dirs {
+        executor.remove_dir(vagrant_dir)?;
+    }
+    executor.unregister(&mut executor.registry()?)?;
+
+    executor.finish()
+}
 
-    if let Some(output_path) = &output_path {
-        let mut output_file = std::fs::File::create(output_path)?;
-        output_file.write_all(stdout_outputs.as_bytes())?;
+fn run_projects() -> Result<(), anyhow::Error> {
+    let registry = Registry::lock()?;
+    if registry.projects().is_empty() {
+        println!("No project is registered");
+    }
+    for project in registry.projects() {
         println!(
-            "Bench results written to {}",
-            output_path.to_str().unwrap_or("file")
+            "{} ({}): cpus {}, memory {} MB",
+            project.project_dir.display(),
+            if project.nested { "nested" } else { "no-nested" },
+            project.cpus,
+            project.memory
         );
+        if !project.pinned_cpus.is_empty() {
+            let pinned_cpus: Vec<String> = project
+                .pinned_cpus
+                .iter()
+                .map(|cpu| cpu.to_string())
+                .collect();
+            println!("  pinned cpus: {}", pinned_cpus.join(","));
+        }
+        for network in &project.networks {
+            println!("  network: {} {}", network.name, network.address);
+        }
     }
 
     Ok(())
 }
 
-fn run_delete(args: DeleteArgs) -> Result<(), anyhow::Error> {
+fn run_config_show(args: ConfigShowArgs) -> Result<(), anyhow::Error> {
     let project_dir = args
         .project_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap());
-    if !args.no_nested {
-        let l1_vagrant_dir = project_dir.join("l1-vagrant");
-        let mut command = process::Command::new("vagrant");
-        command.current_dir(&l1_vagrant_dir)
-                .arg("destroy");
-        if args.force {
-            command.arg("-f");
-        }
-        let status = command.status()?;
-        if !status.success() {
-            println!("Warning: vagrant destroy failed with status: {}", status);
-            println!("continue to clean up directory");
+    let config_loader = ConfigLoader::new(Some(&project_dir), &args.config_overrides)
+        .map_err(LauncherError::config)?;
+    let show = || -> Result<(), anyhow::Error> {
+        // values are checked as create checks them before being printed
+        if !args.no_nested {
+            load_l1_config(&config_loader, args.l1_config.as_deref())?;
+            let l1 = config_loader.l1::<L1VagrantConfig>(args.l1_config.as_deref())?;
+            config::print_layered("l1", &l1);
         }
-        fs_extra::dir::remove(project_dir.join("l1-vagrant"))?;
-        fs_extra::dir::remove(project_dir.join("l2-vagrant"))?;
-
-    } else {
-        let l2_vagrant_dir = project_dir.join("l2-vagrant-no-nested");
-        let mut command = process::Command::new("vagrant");
-        command.current_dir(&l2_vagrant_dir)
-            .arg("destroy");
-        if args.force {
-            command.arg("-f");
-        }
-        let status = command.status()?;
-        if !status.success() {
-            println!("Warning: vagrant destroy failed with status: {}", status);
-            println!("continue to clean up directory");
+        let l2_vms = config_loader.l2::<L2VagrantConfig>(args.l2_config.as_deref())?;
+        for vm in &l2_vms {
+            vm.deserialize::<L2VagrantConfig>().map_err(|err| {
+                anyhow!("invalid L2 config {}: {err}", vm.host_name().unwrap_or_default())
+            })?;
         }
-        fs_extra::dir::remove(project_dir.join("l2-vagrant-no-nested"))?;
-    }
+        for vm in &l2_vms {
+            config::print_layered(&format!("l2 {}", vm.host_name().unwrap_or_default()), vm);
+        }
+        Ok(())
+    };
+    show().map_err(LauncherError::config)
+}
 
+fn run_config_schema(args: ConfigSchemaArgs) -> Result<(), anyhow::Error> {
+    let (title, schema) = match args.kind {
+        ConfigKind::L1 => ("L1 VM config", config::file_schema::<L1VagrantConfig>(Level::L1)),
+        ConfigKind::L2 => ("L2 VM config", config::file_schema::<L2VagrantConfig>(Level::L2)),
+        ConfigKind::Suite => ("Bench suite", suite::schema()),
+    };
+    let mut json_schema = schema.to_json_schema();
+    json_schema["$schema"] = serde_json::json!("http://json-schema.org/draft-07/schema#");
+    json_schema["title"] = serde_json::json!(title);
+    println!("{}", serde_json::to_string_pretty(&json_schema)?);
     Ok(())
 }
 
-async fn run_create(args: CreateArgs, arch: Arch, resource_path: &PathBuf) -> Result<(), anyhow::Error> {
-    let project_dir = args
+fn run_migrate(args: MigrateArgs, resource_path: &Path) -> Result<(), anyhow::Error> {
+    let project_path = args
         .project_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap());
+    let project_path = fs::canonicalize(project_path)?;
+    logging::open_log_file(&project_path, "migrate")?;
+    let migrated =
+        migrate::migrate_project(&project_path, resource_path).map_err(LauncherError::config)?;
+    if !migrated {
+        info!("project directory is already of version {PROJECT_VERSION}");
+    }
+    Ok(())
+}
+
+async fn run_create(args: CreateArgs, arch: Arch, resource_path: &Path) -> Result<(), anyhow::Error> {
+    let project_dir = plan::project_dir(args.project_dir.as_ref())?;
+    let mut executor = Executor::new("create", &project_dir, args.dry_run);
+    executor.create_dir_all(&project_dir)?;
+    // canonical once it exists
+    let project_dir = plan::project_dir(Some(&project_dir))?;
+    executor.open_log_file()?;
+    // domain names are unique per project so that projects can run on the same host
+    let domain_prefix = registry::domain_prefix(&project_dir);
+    let config_loader = ConfigLoader::new(Some(&project_dir), &args.config_overrides)
+        .map_err(LauncherError::config)?;
+    executor.start_run_log()?;
+    let mut registry = executor.registry()?;
     // TODO: clean up created directories if error occurs
     if args.no_nested {
-        let l2_config = if let Some(l2_config_path) = args.l2_config {
-            serde_yaml::from_reader(std::fs::File::open(l2_config_path)?)?
-        } else {
-            L2NoNestedVagrantConfig::default()
-        };
+        let mut l2_configs = load_l2_configs(&config_loader, args.l2_config.as_deref())
+            .map_err(LauncherError::config)?;
+        let host_networks = host_libvirt_networks();
+        assign_management_network(
+            &mut l2_configs,
+            &format!("{domain_prefix}net"),
+            registry
+                .allocate_subnet(&project_dir, &host_networks)
+                .map_err(LauncherError::preflight)?,
+        );
+        validate_l2_no_nested_configs(&l2_configs).map_err(LauncherError::config)?;
+        // registered after the directory is created not to leave an entry of a project which
+        // failed to be created
+        let entry = no_nested_project_entry(&project_dir, &l2_configs, Some(domain_prefix.clone()))?;
+        registry.check(&entry).map_err(LauncherError::preflight)?;
         let l2_vagrant_dest = project_dir.join("l2-vagrant-no-nested");
 
-        create_l2_no_nested_vagrant_directory(
+        let l2_config = create_l2_vagrant_directory(
+            &mut executor,
             &l2_vagrant_dest,
             resource_path,
-            l2_config,
+            l2_configs,
             args.bench_script.as_ref(),
             args.l2_provision_script.as_ref(),
+            &L2Placement::NoNested {
+                domain_prefix: Some(domain_prefix),
+            },
             args.overwrite,
         )?;
-        launch_vm(&l2_vagrant_dest)?;
+        executor.register(&mut registry, entry)?;
+        drop(registry);
+        launch_vm(&mut executor, &l2_vagrant_dest, &args.phase_options).await?;
+        let targets = no_nested_bench_targets(&l2_vagrant_dest, &l2_config.vms);
+        executor.wait_ready(Phase::Boot, &args.readiness, &targets).await?;
+        apply_cpu_pinning(&mut executor, &l2_vagrant_dest, None, &l2_config.vms)
+            .map_err(LauncherError::backend)?;
 
         if args.bench_script.is_some() {
-            run_no_nested_l2_bench(&l2_vagrant_dest, args.output.as_ref()).await?;
+            run_l2_bench(
+                &mut executor,
+                resource_path,
+                &targets,
+                &args.bench_options,
+                args.output.as_ref(),
+                &args.phase_options,
+            )
+            .await?;
+            executor.record_run_metadata(true, Some(&args.bench_options))?;
         }
     } else {
-        let l1_config = if let Some(l1_config_path) = args.l1_config {
-            serde_yaml::from_reader(std::fs::File::open(l1_config_path)?)?
-        } else {
-            L1VagrantConfig::default()
-        };
-        let l2_config = if let Some(l2_config_path) = args.l2_config {
-            serde_yaml::from_reader(std::fs::File::open(l2_config_path)?)?
-        } else {
-            L2VagrantConfig::default()
-        };
+        let l1_config = load_l1_config(&config_loader, args.l1_config.as_deref())
+            .map_err(LauncherError::config)?;
+        let l2_configs = load_l2_configs(&config_loader, args.l2_config.as_deref())
+            .map_err(LauncherError::config)?;
+        l1_config.validate().map_err(LauncherError::config)?;
+        validate_l2_configs(&l2_configs, &l1_config, &project_dir.join("l1-vagrant"))
+            .map_err(LauncherError::config)?;
+        let entry = l1_project_entry(&project_dir, &l1_config, Some(domain_prefix.clone()))?;
+        registry.check(&entry).map_err(LauncherError::preflight)?;
 
         let l1_vagrant_dest = project_dir.join("l1-vagrant");
         let l2_vagrant_dest = project_dir.join("l2-vagrant");
-        create_l1_vagrant_directory(
+        let emulator_path = l1_config
+            .custom_qemu
+            .as_deref()
+            .and_then(custom_qemu_emulator_path);
+        let l1_config = create_l1_vagrant_directory(
+            &mut executor,
             &l1_vagrant_dest,
             arch,
             resource_path,
             l1_config,
+            Some(domain_prefix),
             args.overwrite,
         )?;
-        create_l2_vagrant_directory(
+        let l2_config = create_l2_vagrant_directory(
+            &mut executor,
             &l2_vagrant_dest,
             resource_path,
-            l2_config,
+            l2_configs,
             args.bench_script.as_ref(),
             args.l2_provision_script.as_ref(),
+            &L2Placement::Nested { emulator_path },
             args.overwrite,
         )?;
-
-        launch_vm(&l1_vagrant_dest)?;
+        executor.register(&mut registry, entry)?;
+        drop(registry);
+
+        launch_vm(&mut executor, &l1_vagrant_dest, &args.phase_options).await?;
+        let targets = nested_bench_targets(&l1_vagrant_dest, &l2_config.vms);
+        executor.wait_ready(Phase::Boot, &args.readiness, &targets).await?;
+        apply_cpu_pinning(
+            &mut executor,
+            &l1_vagrant_dest,
+            Some(&l1_config.l1_vagrant_config),
+            &l2_config.vms,
+        )
+        .map_err(LauncherError::backend)?;
 
         if args.bench_script.is_some() {
-            run_l2_bench(&l1_vagrant_dest, args.output.as_ref())?;
+            run_l2_bench(
+                &mut executor,
+                resource_path,
+                &targets,
+                &args.bench_options,
+                args.output.as_ref(),
+                &args.phase_options,
+            )
+            .await?;
+            executor.record_run_metadata(false, Some(&args.bench_options))?;
         }
     }
 
-    Ok(())
+    executor.finish()
 }
 
 fn update_l1_config(
-    l1_vagrant_dir: &PathBuf,
-    l1_config: L1VagrantConfig,
+    executor: &mut Executor,
+    l1_vagrant_dir: &Path,
+    mut l1_config: L1VagrantConfig,
     arch: Arch,
-    l2_vagrant_dir: &PathBuf,
-) -> Result<(), anyhow::Error> {
+    l2_vagrant_dir: &Path,
+    domain_prefix: Option<String>,
+) -> Result<GeneratedL1VagrantConfig, anyhow::Error> {
+    let _step = step_in("config-write", l1_vagrant_dir).entered();
+    stage_custom_qemu(executor, l1_vagrant_dir, l1_config.custom_qemu.as_ref())?;
+    let files =
+        provision::staged_files(l1_vagrant_dir, &l1_config.host_name, &mut l1_config.provision)?;
+    provision::stage(executor, l1_vagrant_dir, &files)?;
     let l1_config = GeneratedL1VagrantConfig {
+        version: PROJECT_VERSION,
         l1_vagrant_config: l1_config,
         arch,
-        l2_vagrant_dir: std::fs::canonicalize(&l2_vagrant_dir)?,
+        l2_vagrant_dir: std::fs::canonicalize(l2_vagrant_dir)?,
+        domain_prefix,
     };
+    executor.write_config(&l1_vagrant_dir.join("config.yaml"), &l1_config)?;
 
-    serde_yaml::to_writer(
-        std::fs::File::create(l1_vagrant_dir.join("config.yaml"))?,
-        &l1_config,
-    )?;
-
-    Ok(())
+    Ok(l1_config)
 }
 
+// Write the config of L2 VMs in the placement with the scripts copied into the directory
 fn update_l2_config(
-    l2_vagrant_dir: &PathBuf,
-    l2_config: L2VagrantConfig,
+    executor: &mut Executor,
+    l2_vagrant_dir: &Path,
+    mut l2_configs: Vec<L2VagrantConfig>,
     bench_script: Option<&PathBuf>,
     provision_script_path: Option<&PathBuf>,
-    no_nested: bool,
-) -> Result<(), anyhow::Error> {
-    let network_interface = if l2_config.enable_network_bridge {
-        Some("eth0".to_string())
-    } else {
-        None
-    };
-    let mut l2_config = GeneratedL2VagrantConfig {
-        l2_vagrant_config: l2_config,
-        bench_script_path: None,
-        network_interface,
-        enable_provision_script: provision_script_path.is_some(),
-    };
+    placement: &L2Placement,
+) -> Result<GeneratedL2VagrantConfig, anyhow::Error> {
+    let _step = step_in("config-write", l2_vagrant_dir).entered();
+    let mut files = vec![];
+    for l2_config in &mut l2_configs {
+        files.extend(provision::staged_files(
+            l2_vagrant_dir,
+            &l2_config.host_name,
+            &mut l2_config.provision,
+        )?);
+    }
+    provision::stage(executor, l2_vagrant_dir, &files)?;
+    let l2_config = GeneratedL2VagrantConfig::new(
+        l2_vagrant_dir,
+        l2_configs,
+        bench_script.is_some(),
+        provision_script_path.is_some(),
+        placement,
+    )?;
     if let Some(bench_script_path) = bench_script {
-        let bench_script_dest = l2_vagrant_dir.join("run-bench.sh");
-        fs_extra::file::copy(
-            bench_script_path,
-            &bench_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
-        if !no_nested {
-            l2_config.bench_script_path = Some(PathBuf::from("/home/vagrant/l2-vagrant/run-bench.sh"));
-        } else {
-            l2_config.bench_script_path = Some(fs::canonicalize(bench_script_dest)?);
-        }
+        executor.copy_file(bench_script_path, &l2_vagrant_dir.join("run-bench.sh"))?;
     }
-
     if let Some(provision_script_path) = provision_script_path {
-        let provision_script_dest = l2_vagrant_dir.join("init.sh");
-        fs_extra::file::copy(
-            provision_script_path,
-            provision_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
+        executor.copy_file(provision_script_path, &l2_vagrant_dir.join("init.sh"))?;
     }
+    executor.write_config(&l2_vagrant_dir.join("config.yaml"), &l2_config)?;
 
-    serde_yaml::to_writer(
-        std::fs::File::create(l2_vagrant_dir.join("config.yaml"))?,
-        &l2_config,
-    )?;
-
-    Ok(())
+    Ok(l2_config)
 }
 
 async fn run_provision(
     args: ProvisionArgs,
-    resource_path: &PathBuf,
+    resource_path: &Path,
     arch: Arch,
 ) -> Result<(), anyhow::Error> {
     let project_path = args
         .project_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap());
+    let project_path = fs::canonicalize(project_path)?;
+    let mut executor = Executor::new("provision", &project_path, args.dry_run);
+    executor.open_log_file()?;
+    // projects created by an older launcher are upgraded before vagrant reads their configs
+    executor.migrate(resource_path)?;
+    let config_loader = ConfigLoader::new(Some(&project_path), &args.config_overrides)
+        .map_err(LauncherError::config)?;
     let l1_vagrant_dir = project_path.join("l1-vagrant");
     let l2_vagrant_dir = project_path.join("l2-vagrant");
     let no_nested_l2_vagrant_dir = project_path.join("l2-vagrant-no-nested");
+    executor.start_run_log()?;
     if args.sync {
+        let _step = step("template-copy").entered();
         if !args.no_nested {
-            println!("copy template files to project directory");
-            let l1_vagrant_template_path = resource_path.join("l1-vagrant-template");
-            fs_extra::dir::copy(
-                l1_vagrant_template_path.as_path(),
-                l1_vagrant_dir.as_path(),
-                &fs_extra::dir::CopyOptions::new()
-                    .overwrite(true)
-                    .content_only(true),
+            info!("copy template files to project directory");
+            provision::copy_template(
+                &mut executor,
+                resource_path,
+                "l1-vagrant-template",
+                &l1_vagrant_dir,
             )?;
-            let l2_vagrant_template_path = resource_path.join("l2-vagrant-template");
-            fs_extra::dir::copy(
-                l2_vagrant_template_path.as_path(),
-                l2_vagrant_dir.as_path(),
-                &fs_extra::dir::CopyOptions::new()
-                    .overwrite(true)
-                    .content_only(true),
+            provision::copy_template(
+                &mut executor,
+                resource_path,
+                "l2-vagrant-template",
+                &l2_vagrant_dir,
             )?;
         } else {
-            let l2_vagrant_template_path = resource_path.join("l2-vagrant-template");
-            fs_extra::dir::copy(
-                l2_vagrant_template_path.as_path(),
-                no_nested_l2_vagrant_dir.as_path(),
-                &fs_extra::dir::CopyOptions::new()
-                    .overwrite(true)
-                    .content_only(true),
+            provision::copy_template(
+                &mut executor,
+                resource_path,
+                "l2-vagrant-template",
+                &no_nested_l2_vagrant_dir,
             )?;
         }
     }
 
     if !args.no_nested {
-        if let Some(l1_config_path) = args.l1_config {
-            let l1_config = serde_yaml::from_reader(std::fs::File::open(l1_config_path)?)?;
-            update_l1_config(&l1_vagrant_dir, l1_config, arch, &l2_vagrant_dir)?;
+        let current_l1_config: GeneratedL1VagrantConfig =
+            migrate::read_generated(&l1_vagrant_dir, GeneratedConfig::L1)?;
+        // projects created without a domain prefix keep the default domain names
+        let domain_prefix = current_l1_config.domain_prefix.clone();
+        let new_l1_config = provision_l1_config(
+            &config_loader,
+            args.l1_config.as_deref(),
+            &current_l1_config.l1_vagrant_config,
+        )
+        .and_then(|l1_config| {
+            if let Some(l1_config) = &l1_config {
+                l1_config.validate()?;
+            }
+            Ok(l1_config)
+        })
+        .map_err(LauncherError::config)?;
+        let l1_config = new_l1_config
+            .as_ref()
+            .unwrap_or(&current_l1_config.l1_vagrant_config);
+        executor.register(
+            &mut executor.registry()?,
+            l1_project_entry(&project_path, l1_config, domain_prefix.clone())?,
+        )?;
+
+        let mut l2_config: GeneratedL2VagrantConfig =
+            migrate::read_generated(&l2_vagrant_dir, GeneratedConfig::L2)?;
+        let new_l2_configs = provision_l2_configs(
+            &config_loader,
+            args.l2_config.as_deref(),
+            l2_config.vms.iter().map(|vm| &vm.l2_vagrant_config),
+        )
+        .map_err(LauncherError::config)?;
+        if let Some(l2_configs) = &new_l2_configs {
+            validate_l2_configs(l2_configs, l1_config, &l1_vagrant_dir)
+                .map_err(LauncherError::config)?;
         }
-        if let Some(l2_config_path) = args.l2_config {
-            let l2_config = serde_yaml::from_reader(std::fs::File::open(l2_config_path)?)?;
-            update_l2_config(
-                &l2_vagrant_dir,
-                l2_config,
-                args.bench_script.as_ref(),
-                args.l2_provision_script.as_ref(),
-                false
-            )?;
-        } else if args.l2_provision_script.is_some() {
-            let original_config_path = l2_vagrant_dir.join("config.yaml");
-            let l2_config = serde_yaml::from_reader(std::fs::File::open(&original_config_path)?)?;
-            update_l2_config(
+
+        // L2 VM uses the emulator installed by the custom_qemu option of L1 VM
+        let mut emulator_changed = false;
+        let l1_config = match new_l1_config {
+            Some(l1_config) => {
+                l2_config.emulator_path = l1_config
+                    .custom_qemu
+                    .as_deref()
+                    .and_then(custom_qemu_emulator_path);
+                emulator_changed = true;
+                update_l1_config(
+                    &mut executor,
+                    &l1_vagrant_dir,
+                    l1_config,
+                    arch,
+                    &l2_vagrant_dir,
+                    domain_prefix,
+                )?
+                .l1_vagrant_config
+            }
+            None => current_l1_config.l1_vagrant_config,
+        };
+        let placement = L2Placement::Nested {
+            emulator_path: l2_config.emulator_path.clone(),
+        };
+        let l2_vms = match new_l2_configs {
+            Some(l2_configs) => Some(l2_configs),
+            None if args.l2_provision_script.is_some() => Some(
+                l2_config
+                    .vms
+                    .drain(..)
+                    .map(|vm| vm.l2_vagrant_config)
+                    .collect(),
+            ),
+            None => None,
+        };
+        if let Some(l2_vms) = l2_vms {
+            l2_config = update_l2_config(
+                &mut executor,
                 &l2_vagrant_dir,
-                l2_config,
+                l2_vms,
                 args.bench_script.as_ref(),
                 args.l2_provision_script.as_ref(),
-                false
+                &placement,
             )?;
+        } else if emulator_changed {
+            // keep the emulator of current L2 config in sync with L1 config
+            executor.write_config(&l2_vagrant_dir.join("config.yaml"), &l2_config)?;
         }
 
-        provision_vm(&l1_vagrant_dir)?;
+        provision_vm(&mut executor, &l1_vagrant_dir, &args.phase_options).await?;
+        let targets = nested_bench_targets(&l1_vagrant_dir, &l2_config.vms);
+        executor.wait_ready(Phase::Provision, &args.readiness, &targets).await?;
+        apply_cpu_pinning(&mut executor, &l1_vagrant_dir, Some(&l1_config), &l2_config.vms)
+            .map_err(LauncherError::backend)?;
 
         if args.bench_script.is_some() {
-            run_l2_bench(&l1_vagrant_dir, args.output.as_ref())?;
+            run_l2_bench(
+                &mut executor,
+                resource_path,
+                &targets,
+                &args.bench_options,
+                args.output.as_ref(),
+                &args.phase_options,
+            )
+            .await?;
+            executor.record_run_metadata(false, Some(&args.bench_options))?;
         }
     } else {
         // no nested version
-        if let Some(l2_config_path) = args.l2_config {
-            let l2_config = serde_yaml::from_reader(std::fs::File::open(l2_config_path)?)?;
-            update_l2_config(
+        let mut l2_config: GeneratedL2VagrantConfig =
+            migrate::read_generated(&no_nested_l2_vagrant_dir, GeneratedConfig::L2NoNested)?;
+        let domain_prefix = l2_config.domain_prefix.clone();
+        let mut registry = executor.registry()?;
+        let mut new_l2_configs = provision_l2_configs(
+            &config_loader,
+            args.l2_config.as_deref(),
+            l2_config.vms.iter().map(|vm| &vm.l2_vagrant_config),
+        )
+        .map_err(LauncherError::config)?;
+        if let Some(l2_configs) = &mut new_l2_configs {
+            // VMs stay on the management network of the project unless it is specified
+            if let Some(current_vm) = l2_config.vms.first() {
+                let network_options = &current_vm.l2_vagrant_config.network_options;
+                assign_management_network(
+                    l2_configs,
+                    network_options.management_network_name(),
+                    network_options.management_subnet()?,
+                );
+            }
+            validate_l2_no_nested_configs(l2_configs).map_err(LauncherError::config)?;
+        }
+        let entry = match &new_l2_configs {
+            Some(l2_configs) => {
+                no_nested_project_entry(&project_path, l2_configs, domain_prefix.clone())?
+            }
+            None => no_nested_project_entry(
+                &project_path,
+                l2_config.vms.iter().map(|vm| &vm.l2_vagrant_config),
+                domain_prefix.clone(),
+            )?,
+        };
+        executor.register(&mut registry, entry)?;
+        drop(registry);
+        let l2_vms = match new_l2_configs {
+            Some(l2_configs) => Some(l2_configs),
+            None if args.l2_provision_script.is_some() => Some(
+                l2_config
+                    .vms
+                    .drain(..)
+                    .map(|vm| vm.l2_vagrant_config)
+                    .collect(),
+            ),
+            None => None,
+        };
+        if let Some(l2_vms) = l2_vms {
+            l2_config = update_l2_config(
+                &mut executor,
                 &no_nested_l2_vagrant_dir,
-                l2_config,
-                args.bench_script.as_ref(),
-                args.l2_provision_script.as_ref(),
-                true
-            )?;
-        } else if args.l2_provision_script.is_some() {
-            let original_config_path = l2_vagrant_dir.join("config.yaml");
-            let l2_config = serde_yaml::from_reader(std::fs::File::open(&original_config_path)?)?;
-            update_l2_config(
-                &l2_vagrant_dir,
-                l2_config,
+                l2_vms,
                 args.bench_script.as_ref(),
                 args.l2_provision_script.as_ref(),
-                true
+                &L2Placement::NoNested { domain_prefix },
             )?;
         }
 
-        provision_vm(&no_nested_l2_vagrant_dir)?;
+        provision_vm(&mut executor, &no_nested_l2_vagrant_dir, &args.phase_options).await?;
+        let targets = no_nested_bench_targets(&no_nested_l2_vagrant_dir, &l2_config.vms);
+        executor.wait_ready(Phase::Provision, &args.readiness, &targets).await?;
+        apply_cpu_pinning(&mut executor, &no_nested_l2_vagrant_dir, None, &l2_config.vms)
+            .map_err(LauncherError::backend)?;
 
         if args.bench_script.is_some() {
-            run_no_nested_l2_bench(&no_nested_l2_vagrant_dir, args.output.as_ref()).await?;
+            run_l2_bench(
+                &mut executor,
+                resource_path,
+                &targets,
+                &args.bench_options,
+                args.output.as_ref(),
+                &args.phase_options,
+            )
+            .await?;
+            executor.record_run_metadata(true, Some(&args.bench_options))?;
         }
     }
 
-    Ok(())
+    executor.finish()
 }
 
-async fn run_bench(args: RunBenchArgs) -> Result<(), anyhow::Error> {
+async fn run_bench(args: RunBenchArgs, resource_path: &Path) -> Result<(), anyhow::Error> {
     let project_path = args
         .project_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap());
-    if !args.no_nested {
+    let project_path = fs::canonicalize(project_path)?;
+    let mut executor = Executor::new("run-bench", &project_path, args.dry_run);
+    executor.open_log_file()?;
+    let suite = args
+        .bench_suite
+        .as_deref()
+        .map(|path| Suite::load(path, &args.only, &args.skip))
+        .transpose()
+        .map_err(LauncherError::config)?;
+    executor.migrate(resource_path)?;
+    executor.start_run_log()?;
+    let targets = if !args.no_nested {
         let l1_vagrant_dir = project_path.join("l1-vagrant");
         let l2_vagrant_dir = project_path.join("l2-vagrant");
 
-        // Copy bench script
-        let bench_script_dest = l2_vagrant_dir.join("run-bench.sh");
-        fs_extra::file::copy(
-            args.bench_script,
-            bench_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
+        let l1_config: GeneratedL1VagrantConfig =
+            migrate::read_generated(&l1_vagrant_dir, GeneratedConfig::L1)?;
+        let mut config: GeneratedL2VagrantConfig =
+            migrate::read_generated(&l2_vagrant_dir, GeneratedConfig::L2)?;
+        if let Some(suite) = &suite {
+            // Copy scripts of the suite
+            suite.stage(&mut executor, &l2_vagrant_dir)?;
+            config.bench_suite_path = Some(PathBuf::from(format!("./{}", suite::STAGING_DIR)));
+        } else if let Some(bench_script) = &args.bench_script {
+            // Copy bench script
+            executor.copy_file(bench_script, &l2_vagrant_dir.join("run-bench.sh"))?;
+            config.bench_script_path = Some(PathBuf::from("./run-bench.sh"));
+        }
         // Update l2-vagrant config
-        let config_path = l2_vagrant_dir.join("config.yaml");
-        let mut config: GeneratedL2VagrantConfig = serde_yaml::from_reader(std::fs::File::open(&config_path)?)?;
-        config.bench_script_path = Some(PathBuf::from("./run-bench.sh"));
-        serde_yaml::to_writer(std::fs::File::create(&config_path)?, &config)?;
+        executor.write_config(&l2_vagrant_dir.join("config.yaml"), &config)?;
         // Sync l2-vagrant directory
-        process::Command::new("vagrant")
-            .current_dir(&l1_vagrant_dir)
-            .arg("reload")
-            .status()?;
+        let vagrant = VagrantCommand::new(&l1_vagrant_dir, &["reload"]);
+        let reload_step = step_in("reload", &l1_vagrant_dir);
+        executor
+            .vagrant(Phase::Provision, &args.phase_options, &vagrant, reload_step)
+            .await?;
+        executor
+            .wait_l1_ready(Phase::Provision, &args.readiness, &l1_vagrant_dir)
+            .await?;
 
         // Boot L2 VM
-        process::Command::new("vagrant")
-            .current_dir(&l1_vagrant_dir)
-            .arg("ssh")
-            .arg("-c")
-            .arg("cd /home/vagrant/l2-vagrant && vagrant up --provision")
-            .status()?;
-
-        run_l2_bench(&l1_vagrant_dir, args.output.as_ref())?;
+        let vagrant = VagrantCommand::in_l1(
+            &l1_vagrant_dir,
+            &l2_vagrant_dir,
+            "/home/vagrant/l2-vagrant",
+            &["up", "--provision"],
+        );
+        executor
+            .vagrant(Phase::Provision, &args.phase_options, &vagrant, step("l2-up"))
+            .await?;
+        let targets = nested_bench_targets(&l1_vagrant_dir, &config.vms);
+        executor.wait_ready(Phase::Provision, &args.readiness, &targets).await?;
+        apply_cpu_pinning(
+            &mut executor,
+            &l1_vagrant_dir,
+            Some(&l1_config.l1_vagrant_config),
+            &config.vms,
+        )
+        .map_err(LauncherError::backend)?;
+        targets
     } else {
         let l2_vagrant_dir = project_path.join("l2-vagrant-no-nested");
 
-        // Copy bench script
-        let bench_script_dest = l2_vagrant_dir.join("run-bench.sh");
-        fs_extra::file::copy(
-            args.bench_script,
-            bench_script_dest,
-            &fs_extra::file::CopyOptions::new().overwrite(true),
-        )?;
-        // Update l2-vagrant config
         let config_path = l2_vagrant_dir.join("config.yaml");
-        let mut config: GeneratedL2VagrantConfig = serde_yaml::from_reader(std::fs::File::open(&config_path)?)?;
-        if config.bench_script_path.is_none() {
-            config.bench_script_path = Some(PathBuf::from("./run-bench.sh"));
-            serde_yaml::to_writer(std::fs::File::create(&config_path)?, &config)?;
+        let mut config: GeneratedL2VagrantConfig =
+            migrate::read_generated(&l2_vagrant_dir, GeneratedConfig::L2NoNested)?;
+        if let Some(suite) = &suite {
+            // Copy scripts of the suite
+            suite.stage(&mut executor, &l2_vagrant_dir)?;
+            if config.bench_suite_path.is_none() {
+                config.bench_suite_path =
+                    Some(PathBuf::from(format!("./{}", suite::STAGING_DIR)));
+                executor.write_config(&config_path, &config)?;
+            }
+        } else if let Some(bench_script) = &args.bench_script {
+            // Copy bench script
+            executor.copy_file(bench_script, &l2_vagrant_dir.join("run-bench.sh"))?;
+            // Update l2-vagrant config
+            if config.bench_script_path.is_none() {
+                config.bench_script_path = Some(PathBuf::from("./run-bench.sh"));
+                executor.write_config(&config_path, &config)?;
+            }
         }
 
         // Sync l2-vagrant directory
-        process::Command::new("vagrant")
-            .current_dir(&l2_vagrant_dir)
-            .arg("reload")
-            .arg("--provision")
-            .status()?;
+        provision_vm(&mut executor, &l2_vagrant_dir, &args.phase_options).await?;
+        let targets = no_nested_bench_targets(&l2_vagrant_dir, &config.vms);
+        executor.wait_ready(Phase::Provision, &args.readiness, &targets).await?;
+        apply_cpu_pinning(&mut executor, &l2_vagrant_dir, None, &config.vms)
+            .map_err(LauncherError::backend)?;
+        targets
+    };
 
-        run_no_nested_l2_bench(&l2_vagrant_dir, args.output.as_ref()).await?;
+    if let Some(suite) = &suite {
+        upload_l2_bench_script(&mut executor, resource_path, &targets, &args.phase_options).await?;
+        let output_path = args
+            .output
+            .clone()
+            .unwrap_or_else(|| project_path.join(suite::DEFAULT_OUTPUT));
+        let results_path = args
+            .suite_results
+            .clone()
+            .unwrap_or_else(|| suite::default_results_path(&project_path));
+        suite::run_suite(
+            &mut executor,
+            suite,
+            &targets,
+            &args.bench_options,
+            &output_path,
+            &results_path,
+            args.phase_options.timeout(Phase::Bench),
+        )
+        .instrument(step("bench"))
+        .await?;
+    } else {
+        run_l2_bench(
+            &mut executor,
+            resource_path,
+            &targets,
+            &args.bench_options,
+            args.output.as_ref(),
+            &args.phase_options,
+        )
+        .await?;
     }
-    Ok(())
+    executor.record_run_metadata(args.no_nested, Some(&args.bench_options))?;
+
+    executor.finish()
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
-    let manifest_path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
-    let resource_path = manifest_path.join("resources");
-    let arch = if PathBuf::from("/sys/module/kvm_intel").exists() {
-        Arch::Intel
+// Architecture of the host, telling which kvm module is loaded
+fn host_arch() -> Result<Arch, anyhow::Error> {
+    if PathBuf::from("/sys/module/kvm_intel").exists() {
+        Ok(Arch::Intel)
     } else if PathBuf::from("/sys/module/kvm_amd").exists() {
-        Arch::Amd
+        Ok(Arch::Amd)
     } else {
-        panic!("kvm_intel or kvm_amd module is not loaded");
-    };
+        Err(LauncherError::preflight(anyhow!(
+            "kvm_intel or kvm_amd module is not loaded"
+        )))
+    }
+}
+
+// Directory of the vagrant templates in the crate, which launcher.sh runs with cargo. The crate
+// directory of the build is used if the launcher is run without cargo.
+fn resource_path() -> Result<PathBuf, anyhow::Error> {
+    let manifest_path = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+    let resource_path = manifest_path.join("resources");
+    if !resource_path.is_dir() {
+        return Err(LauncherError::config(anyhow!(
+            "resources of the launcher are not found in {}, run the launcher with launcher.sh",
+            manifest_path.display()
+        )));
+    }
+    Ok(resource_path)
+}
 
-    let result = match args.command {
+async fn run(command: Command) -> Result<(), anyhow::Error> {
+    phase::listen_signals()?;
+
+    match command {
         Command::Delete(args) => run_delete(args),
-        Command::Create(args) => run_create(args, arch, &resource_path).await,
-        Command::Provision(args) => run_provision(args, &resource_path, arch).await,
-        Command::RunBench(args) => run_bench(args).await,
-    };
+        Command::Create(args) => run_create(args, host_arch()?, &resource_path()?).await,
+        Command::Provision(args) => run_provision(args, &resource_path()?, host_arch()?).await,
+        Command::RunBench(args) => run_bench(args, &resource_path()?).await,
+        Command::NetBench(args) => net_bench::run_net_bench(args).await,
+        Command::Projects => run_projects(),
+        Command::Daemon(args) => daemon::run_daemon(args).await,
+        Command::Submit(args) => daemon::run_submit(args).await,
+        Command::Queue(args) => daemon::run_queue(args).await,
+        Command::Cancel(args) => daemon::run_cancel(args).await,
+        Command::Config(args) => match args.command {
+            ConfigCommand::Show(args) => run_config_show(args),
+            ConfigCommand::Schema(args) => run_config_schema(args),
+        },
+        Command::Migrate(args) => run_migrate(args, &resource_path()?),
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    // anyhow captures backtraces only if this is set before the errors are created
+    if args.verbose {
+        std::env::set_var("RUST_LIB_BACKTRACE", "1");
+    }
+
+    // the plan of --dry-run is printed to stdout
+    let dry_run = args.command.dry_run().format.is_some();
+    logging::init(args.log_format, args.verbose, dry_run);
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|runtime| runtime.block_on(run(args.command)));
+    if !dry_run {
+        logging::log_timing_summary(args.log_format);
+    }
     if let Err(err) = result {
-        println!("{:?}", err);
-        return Err(err);
+        let err = LauncherError::from(err);
+        if args.verbose {
+            tracing::error!("{err:?}");
+        } else {
+            tracing::error!("{err}");
+        }
+        std::process::exit(err.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_topology_overflow_is_rejected() {
+        let options = CpuOptions {
+            cpu_topology: Some(CpuTopology {
+                sockets: u32::MAX,
+                cores: 2,
+                threads: 1,
+            }),
+            ..Default::default()
+        };
+        let err = options.validate(&CpuMode::HostModel, 2, 1024).unwrap_err();
+        assert!(err.to_string().contains("too many vCPUs"), "{err}");
+    }
+
+    #[test]
+    fn numa_node_cpus_must_be_within_cpus() {
+        let options = |cpus: &str| CpuOptions {
+            numa_nodes: vec![
+                NumaNode { cpus: "0-1".to_string(), memory: 512 },
+                NumaNode { cpus: cpus.to_string(), memory: 512 },
+            ],
+            ..Default::default()
+        };
+        assert!(options("2-3").validate(&CpuMode::HostModel, 4, 1024).is_ok());
+        let err = options("2-4").validate(&CpuMode::HostModel, 4, 1024).unwrap_err();
+        assert!(err.to_string().contains("cpu 4 in numa_nodes"), "{err}");
+    }
+
+    // Config with every optional value set, so that nested fields are serialized as well
+    const FULL_CONFIG: &str = r#"
+cpu_model: Skylake-Server
+cpu_features: { enable: [vmx], disable: [avx512f] }
+cpu_topology: { sockets: 1, cores: 2, threads: 1 }
+numa_nodes: [{ cpus: "0-1", memory: 1024 }]
+cpu_pinning: { vcpus: { 0: "2" }, emulator: "3", iothreads: ["3"] }
+hugepage_size: 2M
+memballoon: false
+disk_bus: virtio-scsi
+disk_cache: none
+disk_io: native
+disk_format: raw
+data_disks: [{ size: 10 }]
+disk_size: 20
+provision:
+  - { name: step, shell: "true", reboot: true, privileged: false }
+"#;
+
+    // The schema must accept the serialized configs and have no property missing from them
+    fn assert_schema_matches<T: LayeredConfig>(full: &str) {
+        let schema = T::schema();
+        let json_schema = schema.to_json_schema();
+        let mut defaults = serde_yaml::to_value(T::default()).unwrap();
+        schema::validate_value(&defaults, &schema).unwrap();
+        for (name, property) in json_schema["properties"].as_object().unwrap() {
+            if property.get("deprecated").is_none() {
+                assert!(defaults.get(name).is_some(), "{name} is not a field");
+            }
+        }
+
+        let full: serde_yaml::Value = serde_yaml::from_str(full).unwrap();
+        if let (Some(defaults), Some(full)) = (defaults.as_mapping_mut(), full.as_mapping()) {
+            defaults.extend(full.clone());
+        }
+        let config: T = serde_yaml::from_value(defaults).unwrap();
+        schema::validate_value(&serde_yaml::to_value(config).unwrap(), &schema).unwrap();
+    }
+
+    #[test]
+    fn l1_schema_matches_config() {
+        let full = format!(
+            "{FULL_CONFIG}network_interface: eth0\nkvm_options: {{ nested: Y }}\ncustom_qemu: /opt/qemu\n"
+        );
+        assert_schema_matches::<L1VagrantConfig>(&full);
+    }
+
+    #[test]
+    fn l2_schema_matches_config() {
+        let full = format!(
+            "{FULL_CONFIG}management_network_name: net\nmanagement_network_address: 192.168.140.0/24\nnic_model: virtio\nnic_queues: 2\nmtu: 9000\nl1_bridge_device: eth1\nhost_bridge_device: eth0\nbench_role: victim\n"
+        );
+        assert_schema_matches::<L2VagrantConfig>(&full);
     }
-    Ok(())
 }
@@ -0,0 +1,297 @@
+use crate::bench::timestamp;
+use crate::provision::STEPS_HELPER;
+use crate::{GeneratedL1VagrantConfig, GeneratedL2VagrantConfig};
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+// Version of the files the launcher writes to project directories. Files without `version` are
+// version 1, the layout before multiple L2 VMs were supported.
+pub const PROJECT_VERSION: u32 = 5;
+
+// Migration of each version to the next, starting from version 1
+const MIGRATIONS: [fn(&mut Mapping, GeneratedConfig); (PROJECT_VERSION - 1) as usize] =
+    [multiple_l2_vms, unified_l2_config, bench_suites, placement_bridge_devices];
+
+// Config generated in a vagrant directory of the project
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneratedConfig {
+    L1,
+    L2,
+    L2NoNested,
+}
+
+impl GeneratedConfig {
+    const ALL: [GeneratedConfig; 3] = [
+        GeneratedConfig::L1,
+        GeneratedConfig::L2,
+        GeneratedConfig::L2NoNested,
+    ];
+
+    fn vagrant_dir(self, project_dir: &Path) -> PathBuf {
+        project_dir.join(match self {
+            GeneratedConfig::L1 => "l1-vagrant",
+            GeneratedConfig::L2 => "l2-vagrant",
+            GeneratedConfig::L2NoNested => "l2-vagrant-no-nested",
+        })
+    }
+
+    fn template(self) -> &'static str {
+        match self {
+            GeneratedConfig::L1 => "l1-vagrant-template",
+            GeneratedConfig::L2 | GeneratedConfig::L2NoNested => "l2-vagrant-template",
+        }
+    }
+
+    // Check that the migrated config is read by the launcher
+    fn check(self, value: &Value) -> Result<(), serde_yaml::Error> {
+        match self {
+            GeneratedConfig::L1 => {
+                serde_yaml::from_value::<GeneratedL1VagrantConfig>(value.clone()).map(|_| ())
+            }
+            GeneratedConfig::L2 | GeneratedConfig::L2NoNested => {
+                serde_yaml::from_value::<GeneratedL2VagrantConfig>(value.clone()).map(|_| ())
+            }
+        }
+    }
+}
+
+// Version 2 moved the L2 VM to `vms` to define multiple L2 VMs, keeping the settings shared by
+// the VMs at the top level. Fields added later in version 1 are filled with their defaults.
+fn multiple_l2_vms(config: &mut Mapping, kind: GeneratedConfig) {
+    const SHARED_KEYS: [&str; 4] = [
+        "bench_script_path",
+        "enable_provision_script",
+        "emulator_path",
+        "domain_prefix",
+    ];
+    if kind != GeneratedConfig::L1 && !config.contains_key("vms") {
+        let mut vm = std::mem::take(config);
+        for key in SHARED_KEYS {
+            if let Some(value) = vm.remove(key) {
+                config.insert(Value::from(key), value);
+            }
+        }
+        config.insert(
+            Value::from("vms"),
+            Value::Sequence(vec![Value::Mapping(vm)]),
+        );
+    }
+    let defaults: &[&str] = match kind {
+        GeneratedConfig::L1 | GeneratedConfig::L2NoNested => &["domain_prefix"],
+        GeneratedConfig::L2 => &["emulator_path"],
+    };
+    for key in defaults {
+        if !config.contains_key(*key) {
+            config.insert(Value::from(*key), Value::Null);
+        }
+    }
+}
+
+// Version 3 made nested and no-nested L2 configs the same. The host interface bridged by no-nested
+// VMs moved from `network_interface` to `bridge_device`, `network_interface` being derived from it.
+fn unified_l2_config(config: &mut Mapping, kind: GeneratedConfig) {
+    if kind == GeneratedConfig::L1 {
+        return;
+    }
+    for key in ["emulator_path", "domain_prefix"] {
+        if !config.contains_key(key) {
+            config.insert(Value::from(key), Value::Null);
+        }
+    }
+    if kind != GeneratedConfig::L2NoNested {
+        return;
+    }
+    let Some(Value::Sequence(vms)) = config.get_mut("vms") else {
+        return;
+    };
+    for vm in vms.iter_mut().filter_map(Value::as_mapping_mut) {
+        if !vm.contains_key("enable_network_bridge") {
+            vm.insert(Value::from("enable_network_bridge"), Value::from(false));
+        }
+        if !vm.contains_key("bridge_device") {
+            let network_interface = vm.get("network_interface").cloned().unwrap_or(Value::Null);
+            vm.insert(Value::from("bridge_device"), network_interface);
+        }
+    }
+}
+
+// Version 4 added the scripts of bench suites uploaded to L2 VMs
+fn bench_suites(config: &mut Mapping, kind: GeneratedConfig) {
+    if kind != GeneratedConfig::L1 && !config.contains_key("bench_suite_path") {
+        config.insert(Value::from("bench_suite_path"), Value::Null);
+    }
+}
+
+// Version 5 split `bridge_device` into `l1_bridge_device` and `host_bridge_device`, the one of the
+// placement being used, so that the same config bridges in both placements
+fn placement_bridge_devices(config: &mut Mapping, kind: GeneratedConfig) {
+    if kind == GeneratedConfig::L1 {
+        return;
+    }
+    let Some(Value::Sequence(vms)) = config.get_mut("vms") else {
+        return;
+    };
+    for vm in vms.iter_mut().filter_map(Value::as_mapping_mut) {
+        let bridge_device = vm.remove("bridge_device").unwrap_or(Value::Null);
+        let (placement_key, other_key) = match kind {
+            GeneratedConfig::L2NoNested => ("host_bridge_device", "l1_bridge_device"),
+            _ => ("l1_bridge_device", "host_bridge_device"),
+        };
+        vm.insert(Value::from(placement_key), bridge_device);
+        vm.insert(Value::from(other_key), Value::Null);
+    }
+}
+
+// Upgrade the config to the current version, returning the version it was
+fn migrate(value: &mut Value, kind: GeneratedConfig) -> Result<u32, anyhow::Error> {
+    let config = value
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("config must be a mapping"))?;
+    let version = match config.get("version") {
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| anyhow!("invalid version {version:?}"))?,
+        None => 1,
+    };
+    if version > PROJECT_VERSION {
+        return Err(anyhow!(
+            "version {version} is newer than version {PROJECT_VERSION} supported by this launcher"
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(config, kind);
+    }
+    config.insert(Value::from("version"), Value::from(PROJECT_VERSION));
+    Ok(version)
+}
+
+fn read_value(path: &Path, kind: GeneratedConfig) -> Result<(Value, u32), anyhow::Error> {
+    let mut value: Value = serde_yaml::from_reader(
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?,
+    )
+    .with_context(|| format!("failed to parse {}", path.display()))?;
+    let version = migrate(&mut value, kind)
+        .with_context(|| format!("failed to migrate {}", path.display()))?;
+    Ok((value, version))
+}
+
+// Read config.yaml of the vagrant directory, migrating it in memory if it was written by an older
+// launcher
+pub fn read_generated<T: DeserializeOwned>(
+    vagrant_dir: &Path,
+    kind: GeneratedConfig,
+) -> Result<T, anyhow::Error> {
+    let path = vagrant_dir.join("config.yaml");
+    let (value, version) = read_value(&path, kind)?;
+    if version < PROJECT_VERSION {
+        warn!(
+            "{} is of version {version}, run migrate or provision to upgrade the project directory",
+            path.display()
+        );
+    }
+    serde_yaml::from_value(value).with_context(|| format!("invalid config {}", path.display()))
+}
+
+struct Migration {
+    kind: GeneratedConfig,
+    vagrant_dir: PathBuf,
+    // migrated config
+    value: Value,
+    version: u32,
+}
+
+fn outdated(project_dir: &Path) -> Result<Vec<Migration>, anyhow::Error> {
+    let mut migrations = vec![];
+    for kind in GeneratedConfig::ALL {
+        let vagrant_dir = kind.vagrant_dir(project_dir);
+        let path = vagrant_dir.join("config.yaml");
+        if !path.exists() {
+            continue;
+        }
+        let (value, version) = read_value(&path, kind)?;
+        if version == PROJECT_VERSION {
+            continue;
+        }
+        kind.check(&value)
+            .with_context(|| format!("migrated config {} is invalid", path.display()))?;
+        migrations.push(Migration {
+            kind,
+            vagrant_dir,
+            value,
+            version,
+        });
+    }
+    Ok(migrations)
+}
+
+// Configs of the project older than the current version, with their versions
+pub fn outdated_configs(project_dir: &Path) -> Result<Vec<(PathBuf, u32)>, anyhow::Error> {
+    Ok(outdated(project_dir)?
+        .into_iter()
+        .map(|migration| (migration.vagrant_dir.join("config.yaml"), migration.version))
+        .collect())
+}
+
+// Upgrade the configs of the project directory in place and sync the template files which read
+// them. The files are backed up to `<project-dir>/backups/migrate-<timestamp>` first. Returns
+// whether the project was outdated.
+pub fn migrate_project(project_dir: &Path, resource_path: &Path) -> Result<bool, anyhow::Error> {
+    let migrations = outdated(project_dir)?;
+    if migrations.is_empty() {
+        return Ok(false);
+    }
+
+    let backup_dir = project_dir
+        .join("backups")
+        .join(format!("migrate-{}", timestamp().replace(':', "-")));
+    for Migration {
+        kind, vagrant_dir, ..
+    } in &migrations
+    {
+        let template_dir = resource_path.join(kind.template());
+        let mut files = vec![PathBuf::from("config.yaml"), PathBuf::from(STEPS_HELPER)];
+        for entry in fs::read_dir(&template_dir)? {
+            files.push(PathBuf::from(entry?.file_name()));
+        }
+        let dir_name = vagrant_dir.file_name().unwrap_or_default();
+        for file in files {
+            let source = vagrant_dir.join(&file);
+            if !source.is_file() {
+                continue;
+            }
+            let dest = backup_dir.join(dir_name).join(&file);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(&source, &dest)?;
+        }
+    }
+    info!("backed up project files to {}", backup_dir.display());
+
+    for Migration {
+        kind,
+        vagrant_dir,
+        value,
+        version,
+    } in migrations
+    {
+        fs_extra::dir::copy(
+            resource_path.join(kind.template()),
+            &vagrant_dir,
+            &fs_extra::dir::CopyOptions::new()
+                .overwrite(true)
+                .content_only(true),
+        )?;
+        fs::copy(resource_path.join(STEPS_HELPER), vagrant_dir.join(STEPS_HELPER))?;
+        serde_yaml::to_writer(fs::File::create(vagrant_dir.join("config.yaml"))?, &value)?;
+        info!(
+            "migrated {} from version {version} to {PROJECT_VERSION}",
+            vagrant_dir.join("config.yaml").display()
+        );
+    }
+    Ok(true)
+}
@@ -0,0 +1,603 @@
+use crate::bench::metadata_output_path;
+use crate::error::LauncherError;
+use crate::migrate::{read_generated, GeneratedConfig};
+use crate::phase::{cancelled, Phase};
+use crate::vagrant::shell_quote;
+use crate::{record_run_metadata, GeneratedL2VagrantConfig, NetBenchArgs};
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tracing::{info, warn};
+
+const INSTALL_IPERF3_SCRIPT: &str = "command -v iperf3 > /dev/null || (sudo apt-get update && sudo DEBIAN_FRONTEND=noninteractive apt-get install -y iperf3)";
+// Stop the measurements left running in a VM when the net-bench times out or is interrupted. `-x`
+// matches the process names only, not the shell running pkill.
+const STOP_SCRIPT: &str = "pkill -x iperf3; pkill -x ping; true";
+// First address of the VM, which belongs to the management network
+const VM_ADDRESS_SCRIPT: &str = "hostname -I | awk '{print $1}'";
+const DEFAULT_GATEWAY_SCRIPT: &str = "ip route show default | awk '{print $3; exit}'";
+
+static PING_PACKETS_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d+) packets transmitted, (\d+) received").unwrap());
+static PING_RTT_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"= ([0-9.]+)/([0-9.]+)/([0-9.]+)/([0-9.]+) ms").unwrap()
+});
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetBenchEndpoints {
+    // two L2 VMs in the same L1 VM, or two no-nested VMs
+    VmPair,
+    // L2 VM or no-nested VM, and the host
+    VmHost,
+}
+
+// One end of the network bench
+enum Endpoint {
+    // L2 VM reached via ssh from L1 VM
+    L2 {
+        l1_vagrant_dir: PathBuf,
+        host_name: String,
+    },
+    NoNested {
+        l2_vagrant_dir: PathBuf,
+        host_name: String,
+    },
+    Host,
+}
+
+fn vagrant_ssh(vagrant_dir: &Path, host_name: Option<&str>, script: &str) -> Command {
+    let mut command = Command::new("vagrant");
+    command.current_dir(vagrant_dir).arg("ssh");
+    if let Some(host_name) = host_name {
+        command.arg(host_name);
+    }
+    command.arg("-c").arg(script);
+    command
+}
+
+async fn run_command(mut command: Command, name: &str, script: &str) -> Result<String, anyhow::Error> {
+    let output = command
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "running `{script}` on {name} failed with status: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Endpoint {
+    fn name(&self) -> &str {
+        match self {
+            Endpoint::L2 { host_name, .. } | Endpoint::NoNested { host_name, .. } => host_name,
+            Endpoint::Host => "host",
+        }
+    }
+
+    fn command(&self, script: &str) -> Command {
+        match self {
+            Endpoint::L2 {
+                l1_vagrant_dir,
+                host_name,
+            } => vagrant_ssh(
+                l1_vagrant_dir,
+                None,
+                &format!("ssh {host_name} {}", shell_quote(script)),
+            ),
+            Endpoint::NoNested {
+                l2_vagrant_dir,
+                host_name,
+            } => vagrant_ssh(l2_vagrant_dir, Some(host_name), script),
+            Endpoint::Host => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script);
+                command
+            }
+        }
+    }
+
+    async fn run(&self, script: &str) -> Result<String, anyhow::Error> {
+        run_command(self.command(script), self.name(), script).await
+    }
+
+    async fn install_iperf3(&self) -> Result<(), anyhow::Error> {
+        match self {
+            Endpoint::Host => self
+                .run("command -v iperf3")
+                .await
+                .map(|_| ())
+                .map_err(|_| anyhow!("iperf3 is not installed on the host")),
+            _ => self.run(INSTALL_IPERF3_SCRIPT).await.map(|_| ()),
+        }
+    }
+
+    // Stop iperf3 and ping left in the VM, local commands are killed when they are dropped
+    async fn stop(&self) {
+        if let Endpoint::Host = self {
+            return;
+        }
+        if let Err(err) = self.run(STOP_SCRIPT).await {
+            warn!("stopping network bench of {} failed: {err}", self.name());
+        }
+    }
+
+    // Address of this endpoint reachable from the peer
+    async fn address_from(&self, peer: &Endpoint) -> Result<String, anyhow::Error> {
+        let output = match (self, peer) {
+            (Endpoint::Host, Endpoint::Host) => {
+                return Err(anyhow!("both endpoints of the network bench are the host"))
+            }
+            // L2 VM reaches the host through NAT of L1 VM, so the host is the gateway of L1 VM
+            (Endpoint::Host, Endpoint::L2 { l1_vagrant_dir, .. }) => run_command(
+                vagrant_ssh(l1_vagrant_dir, None, DEFAULT_GATEWAY_SCRIPT),
+                "L1 VM",
+                DEFAULT_GATEWAY_SCRIPT,
+            )
+            .await?,
+            (Endpoint::Host, Endpoint::NoNested { .. }) => peer.run(DEFAULT_GATEWAY_SCRIPT).await?,
+            _ => self.run(VM_ADDRESS_SCRIPT).await?,
+        };
+        let address = output.trim();
+        if address.is_empty() {
+            return Err(anyhow!("failed to get the address of {}", self.name()));
+        }
+        Ok(address.to_string())
+    }
+}
+
+// iperf3 server running until one test finishes
+enum Iperf3Server {
+    Remote,
+    Local(Child),
+}
+
+impl Iperf3Server {
+    async fn start(endpoint: &Endpoint, port: u16) -> Result<Self, anyhow::Error> {
+        let server = match endpoint {
+            Endpoint::Host => Iperf3Server::Local(
+                Command::new("iperf3")
+                    .arg("-s")
+                    .arg("-1")
+                    .arg("-p")
+                    .arg(port.to_string())
+                    .stdout(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()?,
+            ),
+            _ => {
+                endpoint.run(&format!("iperf3 -s -1 -D -p {port}")).await?;
+                Iperf3Server::Remote
+            }
+        };
+        // wait for the server to listen
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(server)
+    }
+
+    async fn stop(self, endpoint: &Endpoint) {
+        match self {
+            Iperf3Server::Local(mut child) => {
+                let _ = child.kill().await;
+            }
+            Iperf3Server::Remote => {
+                // the server exits by itself after the test, unless the client failed
+                let _ = endpoint.run("pkill -x iperf3 || true").await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Iperf3Output {
+    error: Option<String>,
+    end: Option<Iperf3End>,
+}
+
+// `end` of a failed test is an empty object
+#[derive(Debug, Deserialize)]
+struct Iperf3End {
+    sum_sent: Option<Iperf3Sum>,
+    sum_received: Option<Iperf3Sum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Iperf3Sum {
+    bits_per_second: f64,
+    retransmits: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Throughput {
+    sent_bits_per_second: f64,
+    received_bits_per_second: f64,
+    retransmits: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    min_ms: f64,
+    avg_ms: f64,
+    max_ms: f64,
+    mdev_ms: f64,
+    packet_loss_percent: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NetBenchResult {
+    endpoints: NetBenchEndpoints,
+    nested: bool,
+    client: String,
+    server: String,
+    reverse: bool,
+    duration: u32,
+    streams: u32,
+    throughput: Throughput,
+    latency: Latency,
+}
+
+// Parse JSON output of `iperf3 -c ... -J`
+pub fn parse_iperf3_output(output: &str) -> Result<Throughput, anyhow::Error> {
+    let output: Iperf3Output = serde_json::from_str(output)?;
+    if let Some(error) = output.error {
+        return Err(anyhow!("iperf3 failed: {error}"));
+    }
+    let (Some(sent), Some(received)) = output
+        .end
+        .map(|end| (end.sum_sent, end.sum_received))
+        .unwrap_or_default()
+    else {
+        return Err(anyhow!("iperf3 output has no summary"));
+    };
+    Ok(Throughput {
+        sent_bits_per_second: sent.bits_per_second,
+        received_bits_per_second: received.bits_per_second,
+        retransmits: sent.retransmits,
+    })
+}
+
+// Parse the summary of `ping -q`
+pub fn parse_ping_output(output: &str) -> Result<Latency, anyhow::Error> {
+    let packets = PING_PACKETS_PATTERN
+        .captures(output)
+        .ok_or_else(|| anyhow!("ping output has no packet statistics"))?;
+    let transmitted: f64 = packets[1].parse()?;
+    let received: f64 = packets[2].parse()?;
+    if transmitted == 0.0 {
+        return Err(anyhow!("ping transmitted no packets"));
+    }
+    let rtt = PING_RTT_PATTERN
+        .captures(output)
+        .ok_or_else(|| anyhow!("ping output has no rtt statistics"))?;
+    Ok(Latency {
+        min_ms: rtt[1].parse()?,
+        avg_ms: rtt[2].parse()?,
+        max_ms: rtt[3].parse()?,
+        mdev_ms: rtt[4].parse()?,
+        packet_loss_percent: (transmitted - received) / transmitted * 100.0,
+    })
+}
+
+fn vm_endpoints(project_dir: &Path, no_nested: bool) -> Result<Vec<Endpoint>, anyhow::Error> {
+    if no_nested {
+        let l2_vagrant_dir = project_dir.join("l2-vagrant-no-nested");
+        let l2_config: GeneratedL2VagrantConfig =
+            read_generated(&l2_vagrant_dir, GeneratedConfig::L2NoNested)?;
+        Ok(l2_config
+            .vms
+            .into_iter()
+            .map(|vm| Endpoint::NoNested {
+                l2_vagrant_dir: l2_vagrant_dir.clone(),
+                host_name: vm.l2_vagrant_config.host_name,
+            })
+            .collect())
+    } else {
+        let l1_vagrant_dir = project_dir.join("l1-vagrant");
+        let l2_config: GeneratedL2VagrantConfig =
+            read_generated(&project_dir.join("l2-vagrant"), GeneratedConfig::L2)?;
+        Ok(l2_config
+            .vms
+            .into_iter()
+            .map(|vm| Endpoint::L2 {
+                l1_vagrant_dir: l1_vagrant_dir.clone(),
+                host_name: vm.l2_vagrant_config.host_name,
+            })
+            .collect())
+    }
+}
+
+// Take the VM with the host name, or the first VM if it is not specified
+fn take_vm(vms: &mut Vec<Endpoint>, host_name: Option<&str>) -> Result<Endpoint, anyhow::Error> {
+    let index = match host_name {
+        Some(host_name) => vms
+            .iter()
+            .position(|vm| vm.name() == host_name)
+            .ok_or_else(|| anyhow!("VM {host_name} is not found"))?,
+        None if !vms.is_empty() => 0,
+        None => return Err(anyhow!("not enough VMs for the network bench")),
+    };
+    Ok(vms.remove(index))
+}
+
+async fn measure(
+    args: &NetBenchArgs,
+    client: &Endpoint,
+    server: &Endpoint,
+    server_address: &str,
+) -> Result<(Throughput, Latency), anyhow::Error> {
+    let mut iperf3_script = format!(
+        "iperf3 -c {server_address} -p {} -t {} -P {} -J",
+        args.port, args.duration, args.streams
+    );
+    if args.reverse {
+        iperf3_script += " -R";
+    }
+    info!(
+        "measuring throughput from {} to {}",
+        client.name(),
+        server.name()
+    );
+    let throughput = parse_iperf3_output(&client.run(&iperf3_script).await?)?;
+
+    info!("measuring latency from {} to {}", client.name(), server.name());
+    let ping_output = client
+        .run(&format!(
+            "ping -q -i 0.2 -w {} {server_address}",
+            args.duration
+        ))
+        .await?;
+    let latency = parse_ping_output(&ping_output)?;
+
+    Ok((throughput, latency))
+}
+
+// Install iperf3 on the endpoints and measure between them
+async fn net_bench(
+    args: &NetBenchArgs,
+    client: &Endpoint,
+    server: &Endpoint,
+) -> Result<(Throughput, Latency), anyhow::Error> {
+    client.install_iperf3().await?;
+    server.install_iperf3().await?;
+    let server_address = server.address_from(client).await?;
+
+    let iperf3_server = Iperf3Server::start(server, args.port).await?;
+    let measured = measure(args, client, server, &server_address).await;
+    iperf3_server.stop(server).await;
+    measured.map_err(LauncherError::bench)
+}
+
+pub async fn run_net_bench(args: NetBenchArgs) -> Result<(), anyhow::Error> {
+    let project_dir = args
+        .project_dir
+        .clone()
+        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    if args.duration == 0 || args.streams == 0 {
+        return Err(LauncherError::config(anyhow!(
+            "duration and streams must be positive"
+        )));
+    }
+
+    let mut vms = vm_endpoints(&project_dir, args.no_nested)?;
+    let client = take_vm(&mut vms, args.client.as_deref())?;
+    let server = match args.endpoints {
+        NetBenchEndpoints::VmPair => take_vm(&mut vms, args.server.as_deref())?,
+        NetBenchEndpoints::VmHost => {
+            if args.server.is_some() {
+                return Err(LauncherError::config(anyhow!(
+                    "--server is only used with vm-pair endpoints"
+                )));
+            }
+            Endpoint::Host
+        }
+    };
+
+    let timeout = args.bench_timeout.map(Duration::from_secs);
+    let (throughput, latency) = tokio::select! {
+        res = net_bench(&args, &client, &server) => res?,
+        err = cancelled(Phase::Bench, timeout) => {
+            // killing local `vagrant ssh` leaves iperf3 and ping running in VMs
+            warn!("{err}, stopping network bench");
+            client.stop().await;
+            server.stop().await;
+            return Err(err.into());
+        }
+    };
+
+    info!(
+        "throughput: {:.2} Gbits/sec, latency: {:.3} ms",
+        throughput.received_bits_per_second / 1e9,
+        latency.avg_ms
+    );
+    let result = NetBenchResult {
+        endpoints: args.endpoints,
+        nested: !args.no_nested,
+        client: client.name().to_string(),
+        server: server.name().to_string(),
+        reverse: args.reverse,
+        duration: args.duration,
+        streams: args.streams,
+        throughput,
+        latency,
+    };
+    let output_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| project_dir.join("net-bench-results.yaml"));
+    serde_yaml::to_writer(std::fs::File::create(&output_path)?, &result)?;
+    info!("network bench results written to {}", output_path.display());
+    // run-metadata.yaml is kept for the results of run-bench
+    record_run_metadata(
+        &project_dir,
+        args.no_nested,
+        None,
+        &metadata_output_path(&output_path),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `iperf3 -c 192.168.121.11 -t 10 -J` with the intervals and the streams removed
+    const IPERF3_OUTPUT: &str = r#"{
+	"start":	{
+		"connected":	[{
+				"socket":	5,
+				"local_host":	"192.168.121.10",
+				"local_port":	43210,
+				"remote_host":	"192.168.121.11",
+				"remote_port":	5201
+			}],
+		"version":	"iperf 3.9",
+		"system_info":	"Linux l2-vm1 5.15.0-91-generic #101-Ubuntu SMP Tue Nov 14 13:30:08 UTC 2023 x86_64",
+		"timestamp":	{
+			"time":	"Mon, 16 Oct 2023 08:00:00 GMT",
+			"timesecs":	1697443200
+		},
+		"connecting_to":	{
+			"host":	"192.168.121.11",
+			"port":	5201
+		},
+		"cookie":	"ylqhb6xyjrhx3gf5bbt6xkqdbkg2o6hxmbcy",
+		"tcp_mss_default":	1448,
+		"sock_bufsize":	0,
+		"sndbuf_actual":	16384,
+		"rcvbuf_actual":	131072,
+		"test_start":	{
+			"protocol":	"TCP",
+			"num_streams":	1,
+			"blksize":	131072,
+			"omit":	0,
+			"duration":	10,
+			"bytes":	0,
+			"blocks":	0,
+			"reverse":	0,
+			"tos":	0
+		}
+	},
+	"intervals":	[],
+	"end":	{
+		"streams":	[],
+		"sum_sent":	{
+			"start":	0,
+			"end":	10.000162,
+			"seconds":	10.000162,
+			"bytes":	11811160064,
+			"bits_per_second":	9448775197.3,
+			"retransmits":	12,
+			"sender":	true
+		},
+		"sum_received":	{
+			"start":	0,
+			"end":	10.000983,
+			"seconds":	10.000983,
+			"bytes":	11808440320,
+			"bits_per_second":	9445822193.8,
+			"sender":	true
+		},
+		"cpu_utilization_percent":	{
+			"host_total":	23.5,
+			"host_user":	0.8,
+			"host_system":	22.7,
+			"remote_total":	41.2,
+			"remote_user":	2.1,
+			"remote_system":	39.1
+		},
+		"sender_tcp_congestion":	"cubic",
+		"receiver_tcp_congestion":	"cubic"
+	}
+}"#;
+
+    // `iperf3 -J` without the server
+    const IPERF3_ERROR_OUTPUT: &str = r#"{
+	"start":	{
+		"connected":	[],
+		"version":	"iperf 3.9",
+		"system_info":	"Linux l2-vm1 5.15.0-91-generic #101-Ubuntu SMP Tue Nov 14 13:30:08 UTC 2023 x86_64"
+	},
+	"intervals":	[],
+	"end":	{
+	},
+	"error":	"unable to connect to server: Connection refused"
+}"#;
+
+    const PING_OUTPUT: &str = "PING 192.168.121.11 (192.168.121.11) 56(84) bytes of data.
+
+--- 192.168.121.11 ping statistics ---
+50 packets transmitted, 49 received, 2% packet loss, time 9803ms
+rtt min/avg/max/mdev = 0.291/0.512/1.204/0.143 ms
+";
+
+    #[test]
+    fn iperf3_output_is_parsed() {
+        assert_eq!(
+            parse_iperf3_output(IPERF3_OUTPUT).unwrap(),
+            Throughput {
+                sent_bits_per_second: 9448775197.3,
+                received_bits_per_second: 9445822193.8,
+                retransmits: Some(12),
+            }
+        );
+    }
+
+    #[test]
+    fn iperf3_errors_are_reported() {
+        let err = parse_iperf3_output(IPERF3_ERROR_OUTPUT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "iperf3 failed: unable to connect to server: Connection refused"
+        );
+        let err = parse_iperf3_output(r#"{"start": {}, "intervals": []}"#).unwrap_err();
+        assert_eq!(err.to_string(), "iperf3 output has no summary");
+        assert!(parse_iperf3_output("iperf3: error - unable to connect to server").is_err());
+    }
+
+    #[test]
+    fn ping_output_is_parsed() {
+        assert_eq!(
+            parse_ping_output(PING_OUTPUT).unwrap(),
+            Latency {
+                min_ms: 0.291,
+                avg_ms: 0.512,
+                max_ms: 1.204,
+                mdev_ms: 0.143,
+                packet_loss_percent: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn ping_errors_are_reported() {
+        let lost = "PING 10.0.0.1 (10.0.0.1) 56(84) bytes of data.
+
+--- 10.0.0.1 ping statistics ---
+50 packets transmitted, 0 received, 100% packet loss, time 10000ms
+";
+        let err = parse_ping_output(lost).unwrap_err();
+        assert_eq!(err.to_string(), "ping output has no rtt statistics");
+
+        let none = "PING 10.0.0.1 (10.0.0.1) 56(84) bytes of data.
+
+--- 10.0.0.1 ping statistics ---
+0 packets transmitted, 0 received
+";
+        let err = parse_ping_output(none).unwrap_err();
+        assert_eq!(err.to_string(), "ping transmitted no packets");
+
+        let err = parse_ping_output("ping: connect: Network is unreachable\n").unwrap_err();
+        assert_eq!(err.to_string(), "ping output has no packet statistics");
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+// Print name and XML of every libvirt network, used both on the host and inside L1 VM
+pub const LIBVIRT_NETWORKS_SCRIPT: &str = "for network in $(virsh -c qemu:///system net-list --all --name); do echo \"network: $network\"; virsh -c qemu:///system net-dumpxml \"$network\"; done";
+
+static NETWORK_NAME_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^network: (\S+)").unwrap());
+static IP_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"<ip\s+(?:family='ipv4'\s+)?address='([0-9.]+)'\s+(?:netmask='([0-9.]+)'|prefix='([0-9]+)')").unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv4Subnet {
+    address: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Subnet {
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn overlaps(&self, other: &Ipv4Subnet) -> bool {
+        let mask = self.mask() & other.mask();
+        u32::from(self.address) & mask == u32::from(other.address) & mask
+    }
+}
+
+impl FromStr for Ipv4Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("subnet must be in CIDR notation: {s}"))?;
+        let prefix = prefix.parse::<u8>()?;
+        if prefix > 32 {
+            return Err(anyhow!("invalid prefix length: {s}"));
+        }
+        Ok(Self {
+            address: address.parse()?,
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Ipv4Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+// Parse output of `ip route show default` into the interface of the first default route
+pub fn parse_default_interface(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        words.find(|word| *word == "dev")?;
+        words.next().map(str::to_string)
+    })
+}
+
+// Parse output of LIBVIRT_NETWORKS_SCRIPT into pairs of network name and its IPv4 subnets
+pub fn parse_libvirt_networks(output: &str) -> Vec<(String, Ipv4Subnet)> {
+    let mut networks = vec![];
+    let mut current_network = None;
+    for line in output.lines() {
+        if let Some(captures) = NETWORK_NAME_PATTERN.captures(line) {
+            current_network = Some(captures[1].to_string());
+            continue;
+        }
+        let (Some(network), Some(captures)) = (&current_network, IP_PATTERN.captures(line)) else {
+            continue;
+        };
+        let Ok(address) = captures[1].parse::<Ipv4Addr>() else {
+            continue;
+        };
+        let prefix = match captures.get(2) {
+            Some(netmask) => netmask
+                .as_str()
+                .parse::<Ipv4Addr>()
+                .ok()
+                .map(|netmask| u32::from(netmask).count_ones() as u8),
+            None => captures[3].parse::<u8>().ok(),
+        };
+        if let Some(prefix) = prefix {
+            networks.push((network.clone(), Ipv4Subnet { address, prefix }));
+        }
+    }
+    networks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subnet(s: &str) -> Ipv4Subnet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn subnet_from_str() {
+        assert_eq!(subnet("192.168.124.0/24").to_string(), "192.168.124.0/24");
+        assert_eq!(subnet("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert!("192.168.124.0".parse::<Ipv4Subnet>().is_err());
+        assert!("192.168.124.0/33".parse::<Ipv4Subnet>().is_err());
+        assert!("192.168.124/24".parse::<Ipv4Subnet>().is_err());
+        assert!("192.168.124.0/x".parse::<Ipv4Subnet>().is_err());
+    }
+
+    #[test]
+    fn subnet_overlaps() {
+        assert!(subnet("192.168.122.0/24").overlaps(&subnet("192.168.122.128/25")));
+        assert!(subnet("192.168.122.128/25").overlaps(&subnet("192.168.122.0/24")));
+        assert!(subnet("10.0.0.0/8").overlaps(&subnet("10.1.2.0/24")));
+        assert!(subnet("0.0.0.0/0").overlaps(&subnet("192.168.124.0/24")));
+        assert!(!subnet("192.168.122.0/24").overlaps(&subnet("192.168.124.0/24")));
+        assert!(!subnet("192.168.122.0/25").overlaps(&subnet("192.168.122.128/25")));
+    }
+
+    #[test]
+    fn libvirt_networks_are_parsed() {
+        let output = "\
+network: default
+<network>
+  <name>default</name>
+  <ip address='192.168.122.1' netmask='255.255.255.0'>
+  </ip>
+  <ip family='ipv6' address='fd00::1' prefix='64'>
+  </ip>
+</network>
+network: vagrant-net0
+<network>
+  <ip family='ipv4' address='192.168.124.1' prefix='24'>
+  </ip>
+</network>
+network: isolated
+<network>
+  <name>isolated</name>
+</network>
+";
+        assert_eq!(
+            parse_libvirt_networks(output),
+            vec![
+                ("default".to_string(), subnet("192.168.122.1/24")),
+                ("vagrant-net0".to_string(), subnet("192.168.124.1/24")),
+            ]
+        );
+        assert!(parse_libvirt_networks("").is_empty());
+    }
+}
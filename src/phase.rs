@@ -0,0 +1,252 @@
+use crate::migrate::PROJECT_VERSION;
+use crate::readiness::Probe;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use once_cell::sync::Lazy;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use strum_macros::Display;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::warn;
+
+// Long-running step of create, provision and run-bench
+#[derive(Debug, Clone, Copy, PartialEq, Display, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Phase {
+    // vagrant up, including the first provisioning and L2 VMs booted by L1 VM
+    Boot,
+    // vagrant reload --provision, and booting L2 VMs before running bench
+    Provision,
+    // bench script in L2 VMs
+    Bench,
+}
+
+// Timeouts and retries of each phase, no timeout nor retry if not specified
+#[derive(Debug, Clone, Copy, Default, clap::Args)]
+pub struct PhaseOptions {
+    #[clap(long, help = "Timeout in seconds of booting VMs")]
+    boot_timeout: Option<u64>,
+    #[clap(long, help = "Timeout in seconds of provisioning VMs")]
+    provision_timeout: Option<u64>,
+    #[clap(long, help = "Timeout in seconds of running bench script")]
+    bench_timeout: Option<u64>,
+    #[clap(long, default_value_t = 0, help = "Number of retries of failed vagrant up")]
+    boot_retries: u32,
+    #[clap(long, default_value_t = 0, help = "Number of retries of failed provisioning")]
+    provision_retries: u32,
+    #[clap(
+        long,
+        default_value_t = 30,
+        help = "Delay in seconds before the first retry, doubled on each retry"
+    )]
+    retry_backoff: u64,
+}
+
+impl PhaseOptions {
+    pub fn timeout(&self, phase: Phase) -> Option<Duration> {
+        let secs = match phase {
+            Phase::Boot => self.boot_timeout,
+            Phase::Provision => self.provision_timeout,
+            Phase::Bench => self.bench_timeout,
+        };
+        secs.map(Duration::from_secs)
+    }
+
+    // bench is not retried since its results would be mixed
+    pub fn retries(&self, phase: Phase) -> u32 {
+        match phase {
+            Phase::Boot => self.boot_retries,
+            Phase::Provision => self.provision_retries,
+            Phase::Bench => 0,
+        }
+    }
+
+    // Delay before the retry, starting from 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_secs(self.retry_backoff.saturating_mul(1 << (attempt - 1).min(16)))
+    }
+}
+
+// Retried failure of a phase
+#[derive(Debug, Serialize)]
+pub struct RetryRecord {
+    pub phase: Phase,
+    pub attempt: u32,
+    pub command: String,
+    pub exit_code: Option<i32>,
+    // provisioner which failed and is resumed by the retry
+    pub machine: Option<String>,
+    pub provisioner: Option<String>,
+    pub delay: u64,
+}
+
+// Log of a launcher run written to `<project-dir>/run-log.yaml`
+#[derive(Debug, Serialize)]
+pub struct RunLog {
+    #[serde(skip)]
+    path: PathBuf,
+    version: u32,
+    retries: Vec<RetryRecord>,
+}
+
+impl RunLog {
+    // Start the log of this run, replacing the log of the previous run
+    pub fn create(project_dir: &Path) -> Result<Self, anyhow::Error> {
+        let run_log = Self {
+            path: project_dir.join("run-log.yaml"),
+            version: PROJECT_VERSION,
+            retries: vec![],
+        };
+        run_log.save()?;
+        Ok(run_log)
+    }
+
+    pub fn record_retry(&mut self, record: RetryRecord) -> Result<(), anyhow::Error> {
+        self.retries.push(record);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        serde_yaml::to_writer(std::fs::File::create(&self.path)?, self)?;
+        Ok(())
+    }
+}
+
+// exit status of a process killed by SIGINT in shells
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+#[derive(Debug)]
+pub enum PhaseError {
+    TimedOut { phase: Phase, timeout: Duration },
+    // Ctrl-C, or SIGTERM sent by `cancel` of the daemon
+    Interrupted { phase: Phase },
+    // vagrant command exited with failure after all retries
+    Failed {
+        phase: Phase,
+        command: String,
+        status: ExitStatus,
+    },
+    // VMs did not pass the readiness probe after the phase
+    NotReady {
+        phase: Phase,
+        probe: Probe,
+        target: String,
+        timeout: Duration,
+    },
+}
+
+impl PhaseError {
+    // Exit status of the launcher telling which phase did not finish
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PhaseError::TimedOut { phase: Phase::Boot, .. } => 20,
+            PhaseError::TimedOut { phase: Phase::Provision, .. } => 21,
+            PhaseError::TimedOut { phase: Phase::Bench, .. } => 22,
+            PhaseError::Interrupted { .. } => INTERRUPTED_EXIT_CODE,
+            // failed vagrant up is a backend failure, same as LauncherError::Backend
+            PhaseError::Failed { phase: Phase::Boot, .. } => 4,
+            PhaseError::Failed { phase: Phase::Provision, .. } => 5,
+            PhaseError::Failed { phase: Phase::Bench, .. } => 6,
+            PhaseError::NotReady { .. } => 23,
+        }
+    }
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhaseError::TimedOut { phase, timeout } => {
+                write!(f, "{phase} timed out after {} seconds", timeout.as_secs())
+            }
+            PhaseError::Interrupted { phase } => write!(f, "{phase} was interrupted"),
+            PhaseError::Failed {
+                phase,
+                command,
+                status,
+            } => write!(f, "{command} failed in {phase} with status: {status}"),
+            PhaseError::NotReady {
+                phase,
+                probe,
+                target,
+                timeout,
+            } => write!(
+                f,
+                "{target} did not pass {probe} probe within {} seconds after {phase}",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PhaseError {}
+
+// Set once by the signal listener on Ctrl-C or SIGTERM
+static INTERRUPTION: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+// Number of waits for the interruption, which stop their commands before the launcher exits
+static INTERRUPTIBLE: AtomicUsize = AtomicUsize::new(0);
+
+struct InterruptibleGuard;
+
+impl InterruptibleGuard {
+    fn new() -> Self {
+        INTERRUPTIBLE.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for InterruptibleGuard {
+    fn drop(&mut self) {
+        INTERRUPTIBLE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Listen to Ctrl-C and SIGTERM for the whole process, installed once at startup. Running phases
+// are interrupted and fail with PhaseError::Interrupted, while the launcher exits with 130 right
+// away if no phase is running or on the second signal.
+pub fn listen_signals() -> Result<(), anyhow::Error> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+            if INTERRUPTIBLE.load(Ordering::SeqCst) == 0 || *INTERRUPTION.borrow() {
+                warn!("interrupted");
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            INTERRUPTION.send_replace(true);
+        }
+    });
+    Ok(())
+}
+
+// Wait for Ctrl-C or SIGTERM caught by the signal listener
+pub async fn interrupted() {
+    let _guard = InterruptibleGuard::new();
+    let mut interruption = INTERRUPTION.subscribe();
+    // the sender is never dropped
+    let _ = interruption.wait_for(|interrupted| *interrupted).await;
+}
+
+// Resolve when the phase times out or is interrupted
+pub async fn cancelled(phase: Phase, timeout: Option<Duration>) -> PhaseError {
+    let timed_out = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = timed_out => PhaseError::TimedOut {
+            phase,
+            timeout: timeout.unwrap_or_default(),
+        },
+        _ = interrupted() => PhaseError::Interrupted { phase },
+    }
+}
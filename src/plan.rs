@@ -0,0 +1,550 @@
+use crate::bench::{l2_output_path, metadata_output_path, run_bench_targets, BenchOptions, BenchTarget};
+use crate::error::LauncherError;
+use crate::logging;
+use crate::migrate::{self, PROJECT_VERSION};
+use crate::phase::{Phase, PhaseOptions, RunLog};
+use crate::readiness::{wait_l1_ready, wait_vms_ready, Probe, ReadinessOptions};
+use crate::registry::{ProjectEntry, Registry};
+use crate::vagrant::{command_line, run_vagrant_phase, VagrantCommand};
+use crate::record_run_metadata;
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitStatus};
+use std::time::Duration;
+use tracing::{Instrument, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum PlanFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::Args)]
+pub struct DryRunArgs {
+    #[clap(
+        long = "dry-run",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text",
+        help = "Print what would be done without changing anything, as text or json"
+    )]
+    pub format: Option<PlanFormat>,
+}
+// Side effect of a command, in the order it happens
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum Action {
+    CreateDir {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    // contents of the source directory copied into the destination
+    CopyDir {
+        source: PathBuf,
+        dest: PathBuf,
+    },
+    CopyFile {
+        source: PathBuf,
+        dest: PathBuf,
+    },
+    // contents are known only after the actions before it are done
+    WriteFile {
+        path: PathBuf,
+        contents: Option<String>,
+    },
+    Register {
+        project: ProjectEntry,
+    },
+    Unregister {
+        project_dir: PathBuf,
+    },
+    Run {
+        phase: Option<Phase>,
+        dir: PathBuf,
+        command: String,
+    },
+    WaitReady {
+        phase: Phase,
+        probes: Vec<Probe>,
+        targets: Vec<String>,
+    },
+    RunBench {
+        host_name: String,
+        dir: PathBuf,
+        command: String,
+        output: Option<PathBuf>,
+    },
+    // config written by an older launcher upgraded with the template files, after backing them up
+    Migrate {
+        path: PathBuf,
+        version: u32,
+        to_version: u32,
+    },
+}
+
+// What a command would do, printed by --dry-run instead of doing it
+#[derive(Debug, Serialize)]
+struct Plan {
+    command: &'static str,
+    project_dir: PathBuf,
+    actions: Vec<Action>,
+}
+
+// Side effects of the commands, done as they are requested, or recorded into the plan printed at
+// the end for --dry-run so that the plan follows the same flow as the command
+pub struct Executor {
+    plan: Plan,
+    dry_run: Option<PlanFormat>,
+    run_log: Option<RunLog>,
+}
+
+impl Executor {
+    pub fn new(command: &'static str, project_dir: &Path, args: DryRunArgs) -> Self {
+        Self {
+            plan: Plan {
+                command,
+                project_dir: project_dir.to_path_buf(),
+                actions: vec![],
+            },
+            dry_run: args.format,
+            run_log: None,
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
+
+    // Record the action for --dry-run, returning whether it must be skipped
+    fn record(&mut self, action: Action) -> bool {
+        if self.dry_run.is_none() {
+            return false;
+        }
+        self.plan.actions.push(action);
+        true
+    }
+
+    // Open `<project-dir>/logs/<command>-<timestamp>.log` for the logs of this run
+    pub fn open_log_file(&mut self) -> Result<(), anyhow::Error> {
+        if !self.is_dry_run() {
+            logging::open_log_file(&self.plan.project_dir, self.plan.command)?;
+        }
+        Ok(())
+    }
+
+    // Start run-log.yaml recording the retries of the vagrant phases of this run
+    pub fn start_run_log(&mut self) -> Result<(), anyhow::Error> {
+        if !self.is_dry_run() {
+            self.run_log = Some(RunLog::create(&self.plan.project_dir)?);
+        }
+        Ok(())
+    }
+
+    pub fn create_dir_all(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        if path.exists() || self.record(Action::CreateDir { path: path.to_path_buf() }) {
+            return Ok(());
+        }
+        fs::create_dir_all(path)?;
+        Ok(())
+    }
+
+    pub fn remove_dir(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        if !path.exists() || self.record(Action::RemoveDir { path: path.to_path_buf() }) {
+            return Ok(());
+        }
+        fs_extra::dir::remove(path)?;
+        Ok(())
+    }
+
+    // Copy the contents of the source directory into the destination, overwriting files
+    pub fn copy_dir(&mut self, source: &Path, dest: &Path) -> Result<(), anyhow::Error> {
+        let action = Action::CopyDir {
+            source: source.to_path_buf(),
+            dest: dest.to_path_buf(),
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        fs_extra::dir::create_all(dest, false)?;
+        fs_extra::dir::copy(
+            source,
+            dest,
+            &fs_extra::dir::CopyOptions::new()
+                .overwrite(true)
+                .content_only(true),
+        )
+        .with_context(|| format!("failed to copy {}", source.display()))?;
+        Ok(())
+    }
+
+    pub fn copy_file(&mut self, source: &Path, dest: &Path) -> Result<(), anyhow::Error> {
+        let action = Action::CopyFile {
+            source: source.to_path_buf(),
+            dest: dest.to_path_buf(),
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        fs::create_dir_all(dest.parent().unwrap())?;
+        fs::copy(source, dest).with_context(|| format!("failed to copy {}", source.display()))?;
+        Ok(())
+    }
+
+    pub fn write_config(&mut self, path: &Path, config: &impl Serialize) -> Result<(), anyhow::Error> {
+        let contents = serde_yaml::to_string(config)?;
+        let action = Action::WriteFile {
+            path: path.to_path_buf(),
+            contents: Some(contents.clone()),
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    // File of results, whose contents are known only after the actions before it are done
+    pub fn write_results(
+        &mut self,
+        path: &Path,
+        contents: impl FnOnce() -> Result<String, anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let action = Action::WriteFile {
+            path: path.to_path_buf(),
+            contents: None,
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents()?)?;
+        Ok(())
+    }
+
+    // Registry locked while the command changes it, or read without side effects for --dry-run
+    pub fn registry(&self) -> Result<Registry, anyhow::Error> {
+        if self.is_dry_run() {
+            return Registry::snapshot();
+        }
+        Registry::lock()
+    }
+
+    // Register the project, checking the resources left by the other projects
+    pub fn register(&mut self, registry: &mut Registry, project: ProjectEntry) -> Result<(), anyhow::Error> {
+        if self.is_dry_run() {
+            registry.check(&project).map_err(LauncherError::preflight)?;
+            self.record(Action::Register { project });
+            return Ok(());
+        }
+        registry.register(project).map_err(LauncherError::preflight)
+    }
+
+    pub fn unregister(&mut self, registry: &mut Registry) -> Result<(), anyhow::Error> {
+        let project_dir = self.plan.project_dir.clone();
+        if self.record(Action::Unregister { project_dir: project_dir.clone() }) {
+            return Ok(());
+        }
+        registry.unregister(&project_dir)
+    }
+
+    // Upgrade a project of an older version before vagrant reads its configs
+    pub fn migrate(&mut self, resource_path: &Path) -> Result<(), anyhow::Error> {
+        let project_dir = self.plan.project_dir.clone();
+        if self.is_dry_run() {
+            for (path, version) in migrate::outdated_configs(&project_dir)? {
+                self.record(Action::Migrate {
+                    path,
+                    version,
+                    to_version: PROJECT_VERSION,
+                });
+            }
+            return Ok(());
+        }
+        migrate::migrate_project(&project_dir, resource_path).map_err(LauncherError::config)?;
+        Ok(())
+    }
+
+    // Run the command, which succeeds without running for --dry-run
+    pub fn run(&mut self, command: &mut process::Command) -> Result<ExitStatus, anyhow::Error> {
+        let action = Action::Run {
+            phase: None,
+            dir: command.get_current_dir().unwrap_or(Path::new(".")).to_path_buf(),
+            command: command_line(command),
+        };
+        if self.record(action) {
+            return Ok(ExitStatus::from_raw(0));
+        }
+        Ok(command.status()?)
+    }
+
+    // Run the vagrant command of the phase in the step span
+    pub async fn vagrant(
+        &mut self,
+        phase: Phase,
+        phase_options: &PhaseOptions,
+        vagrant: &VagrantCommand,
+        step: Span,
+    ) -> Result<(), anyhow::Error> {
+        let action = Action::Run {
+            phase: Some(phase),
+            dir: vagrant.vagrant_dir().to_path_buf(),
+            command: vagrant.command_line(),
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        let run_log = self
+            .run_log
+            .as_mut()
+            .ok_or_else(|| anyhow!("run log is not started"))?;
+        run_vagrant_phase(phase, phase_options, vagrant, run_log)
+            .instrument(step)
+            .await
+    }
+
+    // Wait for L1 VM to accept ssh
+    pub async fn wait_l1_ready(
+        &mut self,
+        phase: Phase,
+        readiness: &ReadinessOptions,
+        l1_vagrant_dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        if !self.is_dry_run() {
+            return wait_l1_ready(phase, readiness, l1_vagrant_dir).await;
+        }
+        if readiness.probes().contains(&Probe::Ssh) {
+            self.record(Action::WaitReady {
+                phase,
+                probes: vec![Probe::Ssh],
+                targets: vec!["L1 VM".to_string()],
+            });
+        }
+        Ok(())
+    }
+
+    // Wait for the VMs running bench to pass every readiness probe
+    pub async fn wait_ready(
+        &mut self,
+        phase: Phase,
+        readiness: &ReadinessOptions,
+        targets: &[BenchTarget],
+    ) -> Result<(), anyhow::Error> {
+        if !self.is_dry_run() {
+            return wait_vms_ready(phase, readiness, targets).await;
+        }
+        if readiness.probes().is_empty() {
+            return Ok(());
+        }
+        let l1_vm = targets
+            .iter()
+            .any(|target| target.l1_vagrant_dir().is_some())
+            .then(|| "L1 VM".to_string());
+        self.record(Action::WaitReady {
+            phase,
+            probes: readiness.probes().to_vec(),
+            targets: l1_vm
+                .into_iter()
+                .chain(targets.iter().map(|target| target.host_name.clone()))
+                .collect(),
+        });
+        Ok(())
+    }
+
+    // Run the bench script on every target
+    pub async fn run_bench(
+        &mut self,
+        targets: &[BenchTarget],
+        script: &str,
+        options: &BenchOptions,
+        output: Option<&PathBuf>,
+        timeout: Option<Duration>,
+    ) -> Result<(), anyhow::Error> {
+        let multiple_vms = targets.len() > 1;
+        if let Some(output) = output {
+            for target in targets {
+                let output = l2_output_path(output, &target.host_name, multiple_vms);
+                self.write_results(&metadata_output_path(&output), || {
+                    target.output_metadata(script, options)
+                })?;
+            }
+        }
+        if !self.is_dry_run() {
+            return run_bench_targets(targets, script, options, output, timeout).await;
+        }
+        for target in targets {
+            let command = target.bench_command(script, options);
+            self.record(Action::RunBench {
+                host_name: target.host_name.clone(),
+                dir: command
+                    .as_std()
+                    .get_current_dir()
+                    .unwrap_or(Path::new("."))
+                    .to_path_buf(),
+                command: command_line(command.as_std()),
+                output: output.map(|output| l2_output_path(output, &target.host_name, multiple_vms)),
+            });
+        }
+        Ok(())
+    }
+
+    // Write `<project-dir>/run-metadata.yaml` of the run
+    pub fn record_run_metadata(
+        &mut self,
+        no_nested: bool,
+        bench_options: Option<&BenchOptions>,
+    ) -> Result<(), anyhow::Error> {
+        let project_dir = self.plan.project_dir.clone();
+        let metadata_path = project_dir.join("run-metadata.yaml");
+        let action = Action::WriteFile {
+            path: metadata_path.clone(),
+            contents: None,
+        };
+        if self.record(action) {
+            return Ok(());
+        }
+        record_run_metadata(&project_dir, no_nested, bench_options, &metadata_path)
+    }
+
+    // Plan recorded so far, for the tests of the commands
+    #[cfg(test)]
+    pub fn plan_json(&self) -> serde_json::Value {
+        serde_json::to_value(&self.plan).unwrap()
+    }
+
+    // Print the plan for --dry-run
+    pub fn finish(self) -> Result<(), anyhow::Error> {
+        match self.dry_run {
+            Some(format) => self.plan.print(format),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Plan {
+    fn print(&self, format: PlanFormat) -> Result<(), anyhow::Error> {
+        if format == PlanFormat::Json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+
+        println!(
+            "Dry run of {} in {}, nothing is changed:",
+            self.command,
+            self.project_dir.display()
+        );
+        for (i, action) in self.actions.iter().enumerate() {
+            let phase = |phase: &Option<Phase>| match phase {
+                Some(phase) => format!("[{phase}] "),
+                None => String::new(),
+            };
+            let description = match action {
+                Action::CreateDir { path } => format!("create {}", path.display()),
+                Action::RemoveDir { path } => format!("remove {}", path.display()),
+                Action::CopyDir { source, dest } => {
+                    format!("copy {}/* to {}", source.display(), dest.display())
+                }
+                Action::CopyFile { source, dest } => {
+                    format!("copy {} to {}", source.display(), dest.display())
+                }
+                Action::WriteFile {
+                    path,
+                    contents: Some(contents),
+                } => format!(
+                    "write {}\n{}",
+                    path.display(),
+                    contents
+                        .lines()
+                        .map(|line| format!("       | {line}"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ),
+                Action::WriteFile {
+                    path,
+                    contents: None,
+                } => format!("write {}", path.display()),
+                Action::Register { project } => format!(
+                    "register {} cpus and {} MB memory{} in the registry",
+                    project.cpus,
+                    project.memory,
+                    if project.pinned_cpus.is_empty() {
+                        String::new()
+                    } else {
+                        format!(
+                            ", pinning cpus {}",
+                            project
+                                .pinned_cpus
+                                .iter()
+                                .map(u32::to_string)
+                                .collect::<Vec<_>>()
+                                .join(",")
+                        )
+                    }
+                ),
+                Action::Unregister { project_dir } => {
+                    format!("unregister {} from the registry", project_dir.display())
+                }
+                Action::Run {
+                    phase: run_phase,
+                    dir,
+                    command,
+                } => format!("{}run in {}: {command}", phase(run_phase), dir.display()),
+                Action::WaitReady {
+                    phase: wait_phase,
+                    probes,
+                    targets,
+                } => format!(
+                    "{}wait for {} to pass {} probes",
+                    phase(&Some(*wait_phase)),
+                    targets.join(", "),
+                    probes
+                        .iter()
+                        .map(Probe::to_string)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+                Action::RunBench {
+                    host_name,
+                    dir,
+                    command,
+                    output,
+                } => format!(
+                    "[bench] run bench on {host_name} in {}: {command}{}",
+                    dir.display(),
+                    output
+                        .as_ref()
+                        .map(|output| format!(" > {}", output.display()))
+                        .unwrap_or_default()
+                ),
+                Action::Migrate {
+                    path,
+                    version,
+                    to_version,
+                } => format!(
+                    "migrate {} from version {version} to {to_version}, backing up and syncing the template files",
+                    path.display()
+                ),
+            };
+            println!("{:3}. {description}", i + 1);
+        }
+        Ok(())
+    }
+}
+
+// Project directory which may not exist yet
+pub fn project_dir(project_dir: Option<&PathBuf>) -> Result<PathBuf, anyhow::Error> {
+    let project_dir = project_dir
+        .cloned()
+        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    Ok(match fs::canonicalize(&project_dir) {
+        Ok(project_dir) => project_dir,
+        Err(_) => std::path::absolute(project_dir)?,
+    })
+}
@@ -0,0 +1,300 @@
+use crate::plan::Executor;
+use crate::schema::{Property, Schema};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Directory of the vagrant directory where local files of the steps are staged, so that vagrant
+// finds them inside L1 VM as well
+const STAGING_DIR: &str = "provision";
+
+// Ruby helper defining the steps in the Vagrantfiles, shared by the templates
+pub const STEPS_HELPER: &str = "provision_steps.rb";
+
+// Provisioners of the templates, which the steps must not be named after
+const TEMPLATE_PROVISIONERS: [&str; 12] = [
+    "run-l2-bench",
+    "init",
+    "clean-custom-qemu",
+    "custom-qemu",
+    "install-qemu",
+    "reserve-hugepages",
+    "init-user",
+    "halt-l2",
+    "up-l2",
+    "bench-script",
+    "clean-bench-suite",
+    "bench-suite",
+];
+
+fn privileged_default() -> bool {
+    true
+}
+
+// Step of the provisioning pipeline of a VM, run in order after the provisioners of the template.
+// A step is one of shell, script, upload and packages, and reboot may follow it or be a step alone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProvisionStep {
+    // provisioner name, `provision-<number>` if not set
+    name: Option<String>,
+    // inline shell commands
+    shell: Option<String>,
+    // local script run on the VM with args
+    script: Option<PathBuf>,
+    #[serde(default)]
+    args: Vec<String>,
+    // local file or directory uploaded to destination on the VM
+    upload: Option<PathBuf>,
+    destination: Option<String>,
+    // apt packages installed on the VM
+    #[serde(default)]
+    packages: Vec<String>,
+    #[serde(default)]
+    reboot: bool,
+    // shell and script steps run as the vagrant user if false
+    #[serde(default = "privileged_default")]
+    privileged: bool,
+}
+
+impl ProvisionStep {
+    // local file of the step
+    fn source(&self) -> Option<&PathBuf> {
+        self.script.as_ref().or(self.upload.as_ref())
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let kinds: Vec<&str> = [
+            ("shell", self.shell.is_some()),
+            ("script", self.script.is_some()),
+            ("upload", self.upload.is_some()),
+            ("packages", !self.packages.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(kind, set)| set.then_some(kind))
+        .collect();
+        match kinds.as_slice() {
+            [] if self.reboot => {}
+            [] => return Err(anyhow!("one of shell, script, upload, packages or reboot must be set")),
+            [_] => {}
+            _ => return Err(anyhow!("only one of {} can be set", kinds.join(", "))),
+        }
+        if !self.args.is_empty() && self.script.is_none() {
+            return Err(anyhow!("args can be set only with script"));
+        }
+        if self.upload.is_some() != self.destination.is_some() {
+            return Err(anyhow!("upload and destination must be set together"));
+        }
+        if self.upload.is_some() && self.reboot {
+            return Err(anyhow!("reboot can not follow upload"));
+        }
+        if !self.packages.is_empty() && !self.privileged {
+            return Err(anyhow!("packages are installed as root, privileged must not be false"));
+        }
+        if let Some(source) = self.source() {
+            if !source.exists() {
+                return Err(anyhow!("{} does not exist", source.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn properties() -> Property {
+    Property::new(
+        "provision",
+        Schema::array(Schema::object(vec![
+            Property::new(
+                "name",
+                Schema::String.nullable(),
+                "Provisioner name, provision-<number> if not set",
+            ),
+            Property::new("shell", Schema::String.nullable(), "Inline shell commands"),
+            Property::new(
+                "script",
+                Schema::Path.nullable(),
+                "Local script run on the VM, relative to the config file",
+            ),
+            Property::new("args", Schema::array(Schema::String), "Arguments of the script"),
+            Property::new(
+                "upload",
+                Schema::Path.nullable(),
+                "Local file or directory uploaded to destination, relative to the config file",
+            ),
+            Property::new(
+                "destination",
+                Schema::String.nullable(),
+                "Path on the VM where upload is placed",
+            ),
+            Property::new("packages", Schema::array(Schema::String), "apt packages installed"),
+            Property::new(
+                "reboot",
+                Schema::Boolean,
+                "Reboot the VM after the step, or as a step alone",
+            ),
+            Property::new(
+                "privileged",
+                Schema::Boolean,
+                "Run shell and script as root, true by default",
+            ),
+        ])),
+        "Provisioning steps run in order after the provisioners of the template",
+    )
+}
+
+pub fn validate(steps: &[ProvisionStep]) -> Result<(), anyhow::Error> {
+    let mut names = BTreeSet::new();
+    for (index, step) in steps.iter().enumerate() {
+        let name = step_name(step, index);
+        // vagrant prints the name in `Running provisioner: <name>`, which retries resume from
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(anyhow!(
+                "invalid provision step name {name:?}, use letters, digits, '-', '_' and '.'"
+            ));
+        }
+        step.validate()
+            .map_err(|err| anyhow!("invalid provision step {name}: {err}"))?;
+        if TEMPLATE_PROVISIONERS.contains(&name.as_str()) || name.starts_with("kvm-option-") {
+            return Err(anyhow!(
+                "provision step {name} conflicts with a provisioner of the template"
+            ));
+        }
+        if !names.insert(name.clone()) {
+            return Err(anyhow!("provision step {name} is duplicated"));
+        }
+    }
+    Ok(())
+}
+
+fn step_name(step: &ProvisionStep, index: usize) -> String {
+    step.name
+        .clone()
+        .unwrap_or_else(|| format!("provision-{}", index + 1))
+}
+
+// Local files of the steps of the VM and where they are staged in the vagrant directory. The
+// Vagrantfiles look for them at `provision/<host_name>/<index>-<file name>`. Sources are made
+// absolute so that the generated config is independent of the working directory.
+pub fn staged_files(
+    vagrant_dir: &Path,
+    host_name: &str,
+    steps: &mut [ProvisionStep],
+) -> Result<Vec<(PathBuf, PathBuf)>, anyhow::Error> {
+    let mut files = vec![];
+    for (index, step) in steps.iter_mut().enumerate() {
+        let source = match (&mut step.script, &mut step.upload) {
+            (Some(source), _) | (_, Some(source)) => source,
+            _ => continue,
+        };
+        *source = fs::canonicalize(&*source)
+            .with_context(|| format!("failed to find {}", source.display()))?;
+        let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+        let dest = vagrant_dir
+            .join(STAGING_DIR)
+            .join(host_name)
+            .join(format!("{index}-{file_name}"));
+        files.push((source.clone(), dest));
+    }
+    Ok(files)
+}
+
+// Copy the template to the vagrant directory with the helper its Vagrantfile requires
+pub fn copy_template(
+    executor: &mut Executor,
+    resource_path: &Path,
+    template: &str,
+    vagrant_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    executor.copy_dir(&resource_path.join(template), vagrant_dir)?;
+    executor.copy_file(&resource_path.join(STEPS_HELPER), &vagrant_dir.join(STEPS_HELPER))
+}
+
+// Directory of the staged files, replaced whenever the config of the level is written
+pub fn staging_dir(vagrant_dir: &Path) -> PathBuf {
+    vagrant_dir.join(STAGING_DIR)
+}
+
+pub fn stage(
+    executor: &mut Executor,
+    vagrant_dir: &Path,
+    files: &[(PathBuf, PathBuf)],
+) -> Result<(), anyhow::Error> {
+    executor.remove_dir(&staging_dir(vagrant_dir))?;
+    for (source, dest) in files {
+        if source.is_dir() {
+            executor.copy_dir(source, dest)?;
+        } else {
+            executor.copy_file(source, dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(yaml: &str) -> Vec<ProvisionStep> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn validate_error(yaml: &str) -> String {
+        validate(&steps(yaml)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn steps_are_named_after_their_index() {
+        let steps = steps("[{shell: 'echo 1'}, {name: install.perf, packages: [perf]}, {reboot: true}]");
+        validate(&steps).unwrap();
+        let names: Vec<String> = steps.iter().enumerate().map(|(i, step)| step_name(step, i)).collect();
+        assert_eq!(names, ["provision-1", "install.perf", "provision-3"]);
+    }
+
+    #[test]
+    fn step_names_are_validated() {
+        for name in ["install perf", "", ".hidden", "a/b", "tab\\t"] {
+            let err = validate_error(&format!("[{{name: \"{name}\", shell: 'true'}}]"));
+            assert!(err.starts_with("invalid provision step name"), "{name}: {err}");
+        }
+        assert_eq!(
+            validate_error("[{name: up-l2, shell: 'true'}]"),
+            "provision step up-l2 conflicts with a provisioner of the template"
+        );
+        assert_eq!(
+            validate_error("[{name: kvm-option-1, shell: 'true'}]"),
+            "provision step kvm-option-1 conflicts with a provisioner of the template"
+        );
+        assert_eq!(
+            validate_error("[{shell: 'true'}, {name: provision-1, shell: 'true'}]"),
+            "provision step provision-1 is duplicated"
+        );
+    }
+
+    #[test]
+    fn step_kinds_are_validated() {
+        let cases = [
+            ("[{}]", "one of shell, script, upload, packages or reboot must be set"),
+            ("[{shell: 'true', packages: [perf]}]", "only one of shell, packages can be set"),
+            ("[{shell: 'true', args: [a]}]", "args can be set only with script"),
+            ("[{upload: /tmp}]", "upload and destination must be set together"),
+            ("[{upload: /tmp, destination: /tmp, reboot: true}]", "reboot can not follow upload"),
+            (
+                "[{packages: [perf], privileged: false}]",
+                "packages are installed as root, privileged must not be false",
+            ),
+            ("[{script: /nonexistent.sh}]", "/nonexistent.sh does not exist"),
+        ];
+        for (yaml, message) in cases {
+            assert_eq!(
+                validate_error(yaml),
+                format!("invalid provision step provision-1: {message}")
+            );
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::bench::BenchTarget;
+use crate::logging::step;
+use crate::phase::{interrupted, Phase, PhaseError};
+use std::path::Path;
+use std::time::Duration;
+use serde::Serialize;
+use strum_macros::Display;
+use tokio::time::Instant;
+use tracing::{info, Instrument};
+
+// systemd finishes booting as running, or degraded if some units failed. cloud-init is waited for
+// if the box has it.
+const BOOT_FINISHED_SCRIPT: &str = "if command -v cloud-init > /dev/null; then cloud-init status --wait > /dev/null || true; fi; state=$(systemctl is-system-running --wait); [ \"$state\" = running ] || [ \"$state\" = degraded ]";
+
+// Condition checked after VMs boot or are provisioned
+#[derive(Debug, Clone, Copy, PartialEq, Display, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Probe {
+    // L1 VM accepts ssh, or the VMs for no-nested
+    Ssh,
+    // L2 VMs accept ssh from L1 VM, ignored for no-nested
+    L2Ssh,
+    // cloud-init and systemd of L2 VMs finished booting
+    Boot,
+    // 1 minute load average of L2 VMs dropped below --max-load-average
+    Load,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ReadinessOptions {
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [Probe::Ssh, Probe::L2Ssh, Probe::Boot],
+        help = "Readiness probes checked after VMs boot or are provisioned"
+    )]
+    probes: Vec<Probe>,
+    #[clap(long, default_value_t = 600, help = "Timeout in seconds of each readiness probe")]
+    readiness_timeout: u64,
+    #[clap(long, default_value_t = 5, help = "Interval in seconds of checking readiness probes")]
+    probe_interval: u64,
+    #[clap(long, default_value_t = 1.0, help = "Load average of L2 VMs required by load probe")]
+    max_load_average: f64,
+}
+
+impl ReadinessOptions {
+    pub fn probes(&self) -> &[Probe] {
+        &self.probes
+    }
+}
+
+// Run the probe command until it succeeds or the timeout elapses
+async fn wait_until(
+    phase: Phase,
+    options: &ReadinessOptions,
+    probe: Probe,
+    target: &str,
+    command: impl Fn() -> tokio::process::Command,
+) -> Result<(), anyhow::Error> {
+    let timeout = Duration::from_secs(options.readiness_timeout);
+    let deadline = Instant::now() + timeout;
+    loop {
+        let output = command().kill_on_drop(true).output();
+        let passed = tokio::select! {
+            output = tokio::time::timeout_at(deadline, output) => {
+                matches!(output, Ok(Ok(output)) if output.status.success())
+            }
+            _ = interrupted() => return Err(PhaseError::Interrupted { phase }.into()),
+        };
+        if passed {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(PhaseError::NotReady {
+                phase,
+                probe,
+                target: target.to_string(),
+                timeout,
+            }
+            .into());
+        }
+        info!("waiting for {target} to pass {probe} probe");
+        tokio::time::sleep(Duration::from_secs(options.probe_interval)).await;
+    }
+}
+
+fn l1_ssh(l1_vagrant_dir: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("vagrant");
+    command.current_dir(l1_vagrant_dir).arg("ssh").arg("-c").arg("true");
+    command
+}
+
+// Wait for L1 VM to accept ssh
+pub async fn wait_l1_ready(
+    phase: Phase,
+    options: &ReadinessOptions,
+    l1_vagrant_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    if !options.probes.contains(&Probe::Ssh) {
+        return Ok(());
+    }
+    wait_until(phase, options, Probe::Ssh, "L1 VM", || l1_ssh(l1_vagrant_dir)).await
+}
+
+// Wait for the VMs running bench to pass every readiness probe
+pub async fn wait_vms_ready(
+    phase: Phase,
+    options: &ReadinessOptions,
+    targets: &[BenchTarget],
+) -> Result<(), anyhow::Error> {
+    check_probes(phase, options, targets)
+        .instrument(step("readiness"))
+        .await
+}
+
+async fn check_probes(
+    phase: Phase,
+    options: &ReadinessOptions,
+    targets: &[BenchTarget],
+) -> Result<(), anyhow::Error> {
+    let load_script = format!(
+        "awk '{{ exit !($1 < {}) }}' /proc/loadavg",
+        options.max_load_average
+    );
+    // probes are checked in the order of the enum since later probes need ssh
+    for probe in [Probe::Ssh, Probe::L2Ssh, Probe::Boot, Probe::Load] {
+        if !options.probes.contains(&probe) {
+            continue;
+        }
+        let mut l1_checked = false;
+        for target in targets {
+            let script = match (probe, target.l1_vagrant_dir()) {
+                // L2 VMs share L1 VM
+                (Probe::Ssh, Some(l1_vagrant_dir)) => {
+                    if !l1_checked {
+                        wait_l1_ready(phase, options, l1_vagrant_dir).await?;
+                        l1_checked = true;
+                    }
+                    continue;
+                }
+                (Probe::L2Ssh, None) => continue,
+                (Probe::Ssh | Probe::L2Ssh, _) => "true",
+                (Probe::Boot, _) => BOOT_FINISHED_SCRIPT,
+                (Probe::Load, _) => load_script.as_str(),
+            };
+            wait_until(phase, options, probe, &target.host_name, || {
+                target.vm_command(script)
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use crate::host_online_cpus;
+use crate::network::Ipv4Subnet;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::ops::RangeInclusive;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+const REGISTRY_DIR_ENV: &str = "MARIGOLD_REGISTRY_DIR";
+const DEFAULT_REGISTRY_DIR: &str = "/var/tmp/marigold";
+// memory (MB) kept for the host OS and QEMU processes
+const HOST_RESERVED_MEMORY: u64 = 2048;
+// third octet of 192.168.x.0/24 management subnets derived for projects
+const DERIVED_SUBNET_RANGE: RangeInclusive<u8> = 130..=254;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredNetwork {
+    pub name: String,
+    pub address: String,
+}
+
+// Host resources reserved by a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectEntry {
+    pub project_dir: PathBuf,
+    pub nested: bool,
+    // process which registered the project, used to detect projects which failed to be created
+    pub pid: u32,
+    pub cpus: u32,
+    pub memory: u64,
+    #[serde(default)]
+    pub pinned_cpus: BTreeSet<u32>,
+    // libvirt networks created on the host
+    #[serde(default)]
+    pub networks: Vec<RegisteredNetwork>,
+    pub domain_prefix: Option<String>,
+}
+
+impl ProjectEntry {
+    fn vagrant_dir(&self) -> PathBuf {
+        if self.nested {
+            self.project_dir.join("l1-vagrant")
+        } else {
+            self.project_dir.join("l2-vagrant-no-nested")
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        !self.vagrant_dir().exists() && !Path::new("/proc").join(self.pid.to_string()).exists()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    projects: Vec<ProjectEntry>,
+}
+
+// Registry of projects shared by every user of the host. It is locked while the value is alive,
+// unless it is a read-only snapshot.
+pub struct Registry {
+    lock: Option<File>,
+    path: PathBuf,
+    projects: Vec<ProjectEntry>,
+}
+
+fn registry_dir() -> PathBuf {
+    std::env::var_os(REGISTRY_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_REGISTRY_DIR))
+}
+
+fn host_memory() -> Result<u64, anyhow::Error> {
+    fs::read_to_string("/proc/meminfo")?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kib| kib / 1024)
+        .ok_or_else(|| anyhow!("failed to read total memory from /proc/meminfo"))
+}
+
+// CPUs and memory (MB) of the host available to projects
+pub fn host_capacity() -> Result<(u32, u64), anyhow::Error> {
+    Ok((
+        host_online_cpus()?.len() as u32,
+        host_memory()?.saturating_sub(HOST_RESERVED_MEMORY),
+    ))
+}
+
+// Domain name prefix unique to the project directory
+pub fn domain_prefix(project_dir: &Path) -> String {
+    // FNV-1a, which is stable across builds unlike DefaultHasher
+    let hash = project_dir
+        .to_string_lossy()
+        .bytes()
+        .fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+    let name = project_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "project".to_string());
+    format!("{name}-{hash:08x}_")
+}
+
+impl Registry {
+    pub fn lock() -> Result<Self, anyhow::Error> {
+        let dir = registry_dir();
+        fs::create_dir_all(&dir)?;
+        // teammates running the launcher as different users share the registry
+        let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o777));
+        let lock = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join("projects.lock"))?;
+        let _ = lock.set_permissions(fs::Permissions::from_mode(0o666));
+        lock.lock()?;
+        Self::load(&dir, Some(lock))
+    }
+
+    // Projects in the registry without creating nor locking it, for --dry-run
+    pub fn snapshot() -> Result<Self, anyhow::Error> {
+        Self::load(&registry_dir(), None)
+    }
+
+    fn load(dir: &Path, lock: Option<File>) -> Result<Self, anyhow::Error> {
+        let path = dir.join("projects.yaml");
+        let mut projects = if path.exists() {
+            let registry_file: RegistryFile = serde_yaml::from_reader(File::open(&path)?)?;
+            registry_file.projects
+        } else {
+            vec![]
+        };
+        projects.retain(|project| !project.is_stale());
+
+        Ok(Self {
+            lock,
+            path,
+            projects,
+        })
+    }
+
+    pub fn projects(&self) -> &[ProjectEntry] {
+        &self.projects
+    }
+
+    fn other_projects<'a>(&'a self, project_dir: &'a Path) -> impl Iterator<Item = &'a ProjectEntry> {
+        self.projects
+            .iter()
+            .filter(move |project| project.project_dir != project_dir)
+    }
+
+    // Pick a management subnet used by neither the other projects nor the host
+    pub fn allocate_subnet(
+        &self,
+        project_dir: &Path,
+        host_networks: &[(String, Ipv4Subnet)],
+    ) -> Result<Ipv4Subnet, anyhow::Error> {
+        let mut used_subnets: Vec<Ipv4Subnet> = host_networks
+            .iter()
+            .map(|(_, subnet)| *subnet)
+            .collect();
+        for project in self.other_projects(project_dir) {
+            for network in &project.networks {
+                used_subnets.push(network.address.parse()?);
+            }
+        }
+        for octet in DERIVED_SUBNET_RANGE {
+            let subnet: Ipv4Subnet = format!("192.168.{octet}.0/24").parse()?;
+            if !used_subnets.iter().any(|used| used.overlaps(&subnet)) {
+                return Ok(subnet);
+            }
+        }
+        Err(anyhow!("no free management subnet is left on the host"))
+    }
+
+    // Register resources of the project, rejecting it if the host would be oversubscribed
+    pub fn register(&mut self, entry: ProjectEntry) -> Result<(), anyhow::Error> {
+        self.check(&entry)?;
+        self.projects
+            .retain(|project| project.project_dir != entry.project_dir);
+        self.projects.push(entry);
+        self.save()
+    }
+
+    // Check that the project fits in the host with the other projects
+    pub fn check(&self, entry: &ProjectEntry) -> Result<(), anyhow::Error> {
+        let others: Vec<&ProjectEntry> = self.other_projects(&entry.project_dir).collect();
+        let project_dirs = |projects: &[&ProjectEntry]| {
+            projects
+                .iter()
+                .map(|project| project.project_dir.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let (host_cpus, host_memory) = host_capacity()?;
+        let used_cpus: u32 = others.iter().map(|project| project.cpus).sum();
+        if used_cpus + entry.cpus > host_cpus {
+            return Err(anyhow!(
+                "project needs {} cpus but {used_cpus} of {host_cpus} cpus on the host are used by {}",
+                entry.cpus,
+                project_dirs(&others)
+            ));
+        }
+        let used_memory: u64 = others.iter().map(|project| project.memory).sum();
+        if used_memory + entry.memory > host_memory {
+            return Err(anyhow!(
+                "project needs {} MB memory but {used_memory} of {host_memory} MB on the host are used by {}",
+                entry.memory,
+                project_dirs(&others)
+            ));
+        }
+
+        for project in &others {
+            if let Some(cpu) = project.pinned_cpus.intersection(&entry.pinned_cpus).next() {
+                return Err(anyhow!(
+                    "cpu {cpu} is already pinned by {}",
+                    project.project_dir.display()
+                ));
+            }
+            if entry.domain_prefix.is_some() && project.domain_prefix == entry.domain_prefix {
+                return Err(anyhow!(
+                    "domain prefix {} is already used by {}",
+                    entry.domain_prefix.as_deref().unwrap_or_default(),
+                    project.project_dir.display()
+                ));
+            }
+            for network in &entry.networks {
+                let subnet: Ipv4Subnet = network.address.parse()?;
+                for other_network in &project.networks {
+                    // projects may share a network with the same name and subnet
+                    let collides = if other_network.name == network.name {
+                        other_network.address != network.address
+                    } else {
+                        subnet.overlaps(&other_network.address.parse()?)
+                    };
+                    if collides {
+                        return Err(anyhow!(
+                            "network {} ({}) collides with network {} ({}) of {}",
+                            network.name,
+                            network.address,
+                            other_network.name,
+                            other_network.address,
+                            project.project_dir.display()
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, project_dir: &Path) -> Result<(), anyhow::Error> {
+        self.projects
+            .retain(|project| project.project_dir != project_dir);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        if self.lock.is_none() {
+            return Err(anyhow!("snapshot of the registry is read-only"));
+        }
+        let registry_file = RegistryFile {
+            projects: self.projects.clone(),
+        };
+        serde_yaml::to_writer(File::create(&self.path)?, &registry_file)?;
+        let _ = fs::set_permissions(&self.path, fs::Permissions::from_mode(0o666));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(name: &str, projects: Vec<ProjectEntry>) -> Registry {
+        let dir = std::env::temp_dir().join(format!("marigold-registry-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Registry {
+            lock: Some(File::create(dir.join("projects.lock")).unwrap()),
+            path: dir.join("projects.yaml"),
+            projects,
+        }
+    }
+
+    fn entry(project_dir: &str, cpus: u32, memory: u64) -> ProjectEntry {
+        ProjectEntry {
+            project_dir: PathBuf::from(project_dir),
+            nested: false,
+            pid: std::process::id(),
+            cpus,
+            memory,
+            pinned_cpus: BTreeSet::new(),
+            networks: vec![],
+            domain_prefix: None,
+        }
+    }
+
+    fn with_network(mut entry: ProjectEntry, name: &str, address: &str) -> ProjectEntry {
+        entry.networks.push(RegisteredNetwork {
+            name: name.to_string(),
+            address: address.to_string(),
+        });
+        entry
+    }
+
+    fn subnet(s: &str) -> Ipv4Subnet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn snapshot_has_no_side_effects() {
+        let dir = std::env::temp_dir().join(format!("marigold-snapshot-{}", std::process::id()));
+        let mut snapshot = Registry::load(&dir, None).unwrap();
+        assert!(snapshot.projects().is_empty());
+        assert!(snapshot.register(entry("/a", 1, 1)).is_err());
+        assert!(!dir.exists());
+
+        let mut registry = registry("snapshot", vec![]);
+        registry.register(entry("/a", 1, 1)).unwrap();
+        let snapshot = Registry::load(registry.path.parent().unwrap(), None).unwrap();
+        assert_eq!(snapshot.projects()[0].project_dir, Path::new("/a"));
+    }
+
+    #[test]
+    fn allocate_subnet_skips_used_subnets() {
+        let registry = registry(
+            "allocate",
+            vec![
+                with_network(entry("/a", 1, 1), "a-net", "192.168.130.0/24"),
+                with_network(entry("/b", 1, 1), "b-net", "192.168.132.0/24"),
+            ],
+        );
+        let host_networks = vec![("default".to_string(), subnet("192.168.131.0/24"))];
+        assert_eq!(
+            registry.allocate_subnet(Path::new("/c"), &host_networks).unwrap(),
+            subnet("192.168.133.0/24")
+        );
+        // the subnet of the project itself can be reused
+        assert_eq!(
+            registry.allocate_subnet(Path::new("/a"), &[]).unwrap(),
+            subnet("192.168.130.0/24")
+        );
+    }
+
+    #[test]
+    fn allocate_subnet_fails_when_exhausted() {
+        let registry = registry("exhausted", vec![]);
+        let host_networks = vec![("wide".to_string(), subnet("192.168.0.0/16"))];
+        assert!(registry.allocate_subnet(Path::new("/a"), &host_networks).is_err());
+    }
+
+    #[test]
+    fn register_rejects_oversubscription() {
+        let (host_cpus, host_memory) = host_capacity().unwrap();
+        let mut registry = registry("oversubscribe", vec![entry("/a", host_cpus, 1)]);
+        let err = registry.register(entry("/b", 1, 1)).unwrap_err();
+        assert!(err.to_string().contains("cpus"), "{err}");
+        let err = registry.register(entry("/c", 0, host_memory)).unwrap_err();
+        assert!(err.to_string().contains("memory"), "{err}");
+        assert_eq!(registry.projects().len(), 1);
+
+        // re-registering the same project replaces its entry
+        registry.register(entry("/a", host_cpus, 2)).unwrap();
+        assert_eq!(registry.projects().len(), 1);
+        assert_eq!(registry.projects()[0].memory, 2);
+    }
+
+    #[test]
+    fn register_rejects_shared_cpus_and_networks() {
+        let mut pinned = entry("/a", 0, 0);
+        pinned.pinned_cpus = BTreeSet::from([0]);
+        let mut registry = registry(
+            "conflicts",
+            vec![pinned.clone(), with_network(entry("/b", 0, 0), "b-net", "192.168.130.0/24")],
+        );
+
+        pinned.project_dir = PathBuf::from("/c");
+        let err = registry.register(pinned).unwrap_err();
+        assert!(err.to_string().contains("cpu 0 is already pinned"), "{err}");
+
+        let err = registry
+            .register(with_network(entry("/d", 0, 0), "d-net", "192.168.130.128/25"))
+            .unwrap_err();
+        assert!(err.to_string().contains("collides"), "{err}");
+        // networks with the same name and subnet are shared
+        registry
+            .register(with_network(entry("/e", 0, 0), "b-net", "192.168.130.0/24"))
+            .unwrap();
+    }
+}
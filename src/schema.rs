@@ -0,0 +1,523 @@
+use anyhow::anyhow;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Unexpected, Visitor,
+};
+use serde_json::json;
+use serde_yaml::Value;
+use std::fmt;
+use std::path::Path;
+
+// Shape of config values. Config files are checked against it to reject unknown keys and values
+// of wrong types with their location, and it is exported as JSON Schema for editors.
+#[derive(Debug, Clone)]
+pub enum Schema {
+    Any,
+    Boolean,
+    Integer {
+        minimum: u64,
+        maximum: Option<u64>,
+    },
+    String,
+    Enum(&'static [&'static str]),
+    Array(Box<Schema>),
+    // keys other than the properties are rejected unless `additional` is given
+    Object {
+        properties: Vec<Property>,
+        additional: Option<Box<Schema>>,
+    },
+    Nullable(Box<Schema>),
+    // local path such as `script` of a provision step, relative to the config file declaring it
+    Path,
+    // path or list of paths such as `extends`
+    Paths,
+    // value or list of values such as the VMs of L2 config file
+    OneOrMany(Box<Schema>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Property {
+    name: &'static str,
+    description: &'static str,
+    required: bool,
+    // property which replaces this deprecated one
+    renamed_to: Option<&'static str>,
+    schema: Schema,
+}
+
+impl Property {
+    pub fn new(name: &'static str, schema: Schema, description: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            required: false,
+            renamed_to: None,
+            schema,
+        }
+    }
+
+    // Old name of the property `name`, accepted and renamed when config files are loaded
+    pub fn deprecated(old_name: &'static str, name: &'static str, schema: Schema) -> Self {
+        Self {
+            name: old_name,
+            description: "Deprecated",
+            required: false,
+            renamed_to: Some(name),
+            schema,
+        }
+    }
+
+    // required inside its parent, such as `size` of a data disk
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+impl Schema {
+    pub fn integer(minimum: u64) -> Self {
+        Schema::Integer {
+            minimum,
+            maximum: None,
+        }
+    }
+
+    pub fn object(properties: Vec<Property>) -> Self {
+        Schema::Object {
+            properties,
+            additional: None,
+        }
+    }
+
+    // mapping with arbitrary keys such as `kvm_options`
+    pub fn map(value: Schema) -> Self {
+        Schema::Object {
+            properties: vec![],
+            additional: Some(Box::new(value)),
+        }
+    }
+
+    pub fn array(item: Schema) -> Self {
+        Schema::Array(Box::new(item))
+    }
+
+    pub fn nullable(self) -> Self {
+        Schema::Nullable(Box::new(self))
+    }
+
+    // Object with the properties added, such as `extends` of config files
+    pub fn with_properties(self, extra: Vec<Property>) -> Self {
+        match self {
+            Schema::Object {
+                mut properties,
+                additional,
+            } => {
+                properties.extend(extra);
+                Schema::Object {
+                    properties,
+                    additional,
+                }
+            }
+            schema => schema,
+        }
+    }
+
+    // Pairs of deprecated properties of the object and the properties replacing them
+    pub fn renamed(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Schema::Object { properties, .. } => properties
+                .iter()
+                .filter_map(|property| Some((property.name, property.renamed_to?)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn non_null(&self) -> (&Schema, bool) {
+        match self {
+            Schema::Nullable(schema) => (schema, true),
+            schema => (schema, false),
+        }
+    }
+
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        match self {
+            Schema::Any => json!({}),
+            Schema::Boolean => json!({ "type": "boolean" }),
+            Schema::Integer { minimum, maximum } => {
+                let mut schema = json!({ "type": "integer", "minimum": minimum });
+                if let Some(maximum) = maximum {
+                    schema["maximum"] = json!(maximum);
+                }
+                schema
+            }
+            Schema::String | Schema::Path => json!({ "type": "string" }),
+            Schema::Enum(names) => json!({ "enum": names }),
+            Schema::Array(item) => json!({ "type": "array", "items": item.to_json_schema() }),
+            Schema::Object {
+                properties,
+                additional,
+            } => {
+                let mut json_properties = serde_json::Map::new();
+                for property in properties {
+                    let mut schema = property.schema.to_json_schema();
+                    schema["description"] = match property.renamed_to {
+                        Some(name) => json!(format!("Deprecated, use {name}")),
+                        None => json!(property.description),
+                    };
+                    if property.renamed_to.is_some() {
+                        schema["deprecated"] = json!(true);
+                    }
+                    json_properties.insert(property.name.to_string(), schema);
+                }
+                let mut schema = json!({
+                    "type": "object",
+                    "properties": json_properties,
+                    "additionalProperties": match additional {
+                        Some(additional) => additional.to_json_schema(),
+                        None => json!(false),
+                    },
+                });
+                let required: Vec<_> = properties
+                    .iter()
+                    .filter(|property| property.required)
+                    .map(|property| property.name)
+                    .collect();
+                if !required.is_empty() {
+                    schema["required"] = json!(required);
+                }
+                schema
+            }
+            Schema::Nullable(schema) => {
+                json!({ "anyOf": [schema.to_json_schema(), { "type": "null" }] })
+            }
+            Schema::Paths => json!({
+                "anyOf": [{ "type": "string" }, { "type": "array", "items": { "type": "string" } }]
+            }),
+            Schema::OneOrMany(schema) => json!({
+                "anyOf": [schema.to_json_schema(), { "type": "array", "items": schema.to_json_schema() }]
+            }),
+        }
+    }
+}
+
+// Check the YAML text, errors having the line and column of the offending key or value.
+// serde_yaml omits the location when it is line 1 column 1, so it is added here.
+pub fn validate_yaml(text: &str, schema: &Schema) -> Result<(), anyhow::Error> {
+    SchemaSeed(schema)
+        .deserialize(serde_yaml::Deserializer::from_str(text))
+        .map_err(|err| {
+            let message = err.to_string();
+            match err.location() {
+                Some(location)
+                    if location.line() == 1
+                        && location.column() == 1
+                        && !message.contains(" at line ") =>
+                {
+                    anyhow!("{message} at line 1 column 1")
+                }
+                _ => anyhow!(message),
+            }
+        })
+}
+
+pub fn validate_value(value: &Value, schema: &Schema) -> Result<(), serde_yaml::Error> {
+    SchemaSeed(schema).deserialize(value)
+}
+
+// Make the relative paths of the value absolute, as they are relative to the config file in `dir`
+pub fn resolve_paths(value: &mut Value, schema: &Schema, dir: &Path) {
+    match (schema.non_null().0, value) {
+        (Schema::Path, Value::String(path)) if Path::new(path).is_relative() => {
+            *path = dir.join(&*path).to_string_lossy().into_owned();
+        }
+        (Schema::Array(item) | Schema::OneOrMany(item), Value::Sequence(values)) => {
+            for value in values {
+                resolve_paths(value, item, dir);
+            }
+        }
+        (Schema::OneOrMany(schema), value @ Value::Mapping(_)) => resolve_paths(value, schema, dir),
+        (
+            Schema::Object {
+                properties,
+                additional,
+            },
+            Value::Mapping(mapping),
+        ) => {
+            for (key, value) in mapping.iter_mut() {
+                let schema = properties
+                    .iter()
+                    .find(|property| key.as_str() == Some(property.name))
+                    .map(|property| &property.schema)
+                    .or(additional.as_deref());
+                if let Some(schema) = schema {
+                    resolve_paths(value, schema, dir);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// `${VAR}` is checked after it is interpolated
+fn is_interpolated(text: &str) -> bool {
+    text.contains("${")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = previous + usize::from(a != *b);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn unknown_field<E: de::Error>(key: &str, properties: &[Property]) -> E {
+    let suggestion = properties
+        .iter()
+        .map(|property| (edit_distance(key, property.name), property.name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min();
+    match suggestion {
+        Some((_, name)) => E::custom(format!("unknown field `{key}`, did you mean `{name}`?")),
+        None => E::custom(format!("unknown field `{key}`")),
+    }
+}
+
+struct SchemaSeed<'a>(&'a Schema);
+
+impl<'de> DeserializeSeed<'de> for SchemaSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        match self.0 {
+            Schema::Any => deserializer.deserialize_ignored_any(IgnoredAny).map(|_| ()),
+            _ => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for SchemaSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let (schema, nullable) = self.0.non_null();
+        match schema {
+            Schema::Any => write!(formatter, "any value")?,
+            Schema::Boolean => write!(formatter, "a boolean")?,
+            Schema::Integer { minimum, maximum } => match maximum {
+                Some(maximum) => write!(formatter, "an integer from {minimum} to {maximum}")?,
+                None => write!(formatter, "an integer of at least {minimum}")?,
+            },
+            Schema::String => write!(formatter, "a string")?,
+            Schema::Path => write!(formatter, "a path")?,
+            Schema::Enum(names) => write!(
+                formatter,
+                "one of {}",
+                names
+                    .iter()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?,
+            Schema::Array(_) => write!(formatter, "a list")?,
+            Schema::Object { .. } => write!(formatter, "a mapping")?,
+            Schema::Nullable(schema) => return SchemaSeed(schema).expecting(formatter),
+            Schema::Paths => write!(formatter, "a path or a list of paths")?,
+            Schema::OneOrMany(_) => write!(formatter, "a mapping or a list of mappings")?,
+        }
+        if nullable {
+            write!(formatter, " or null")?;
+        }
+        Ok(())
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<(), E> {
+        match self.0.non_null().0 {
+            Schema::Any | Schema::Boolean => Ok(()),
+            _ => Err(E::invalid_type(Unexpected::Bool(value), &self)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<(), E> {
+        match self.0.non_null().0 {
+            Schema::Any => Ok(()),
+            Schema::Integer { minimum, maximum } => {
+                if value < *minimum || maximum.is_some_and(|maximum| value > maximum) {
+                    return Err(E::invalid_value(Unexpected::Unsigned(value), &self));
+                }
+                Ok(())
+            }
+            _ => Err(E::invalid_type(Unexpected::Unsigned(value), &self)),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<(), E> {
+        match u64::try_from(value) {
+            Ok(value) => self.visit_u64(value),
+            Err(_) => match self.0.non_null().0 {
+                Schema::Any => Ok(()),
+                Schema::Integer { .. } => Err(E::invalid_value(Unexpected::Signed(value), &self)),
+                _ => Err(E::invalid_type(Unexpected::Signed(value), &self)),
+            },
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<(), E> {
+        match self.0.non_null().0 {
+            Schema::Any => Ok(()),
+            _ => Err(E::invalid_type(Unexpected::Float(value), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<(), E> {
+        match self.0.non_null().0 {
+            Schema::Any | Schema::String | Schema::Path | Schema::Paths => Ok(()),
+            Schema::Enum(names) if names.contains(&value) => Ok(()),
+            Schema::Enum(_) if !is_interpolated(value) => {
+                Err(E::invalid_value(Unexpected::Str(value), &self))
+            }
+            _ if is_interpolated(value) => Ok(()),
+            _ => Err(E::invalid_type(Unexpected::Str(value), &self)),
+        }
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        match self.0.non_null() {
+            (Schema::Any, _) | (_, true) => Ok(()),
+            _ => Err(E::invalid_type(Unexpected::Unit, &self)),
+        }
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        self.visit_unit()
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let item = match self.0.non_null().0 {
+            Schema::Any => &Schema::Any,
+            Schema::Array(item) | Schema::OneOrMany(item) => item,
+            Schema::Paths => &Schema::String,
+            _ => return Err(de::Error::invalid_type(Unexpected::Seq, &self)),
+        };
+        while seq.next_element_seed(SchemaSeed(item))?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (properties, additional) = match self.0.non_null().0 {
+            Schema::Any => (&[][..], Some(&Schema::Any)),
+            Schema::Object {
+                properties,
+                additional,
+            } => (&properties[..], additional.as_deref()),
+            Schema::OneOrMany(schema) => return SchemaSeed(schema).visit_map(map),
+            _ => return Err(de::Error::invalid_type(Unexpected::Map, &self)),
+        };
+        while let Some(schema) = map.next_key_seed(KeySeed {
+            properties,
+            additional,
+        })? {
+            map.next_value_seed(SchemaSeed(schema))?;
+        }
+        Ok(())
+    }
+}
+
+// Key of a mapping, resolving the schema of its value
+struct KeySeed<'a> {
+    properties: &'a [Property],
+    additional: Option<&'a Schema>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for KeySeed<'a> {
+    type Value = &'a Schema;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<&'a Schema, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for KeySeed<'a> {
+    type Value = &'a Schema;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a key")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<&'a Schema, E> {
+        if let Some(property) = self.properties.iter().find(|property| property.name == key) {
+            return Ok(&property.schema);
+        }
+        self.additional
+            .ok_or_else(|| unknown_field(key, self.properties))
+    }
+
+    fn visit_u64<E: de::Error>(self, key: u64) -> Result<&'a Schema, E> {
+        self.additional
+            .ok_or_else(|| E::invalid_type(Unexpected::Unsigned(key), &"a string key"))
+    }
+
+    fn visit_i64<E: de::Error>(self, key: i64) -> Result<&'a Schema, E> {
+        self.additional
+            .ok_or_else(|| E::invalid_type(Unexpected::Signed(key), &"a string key"))
+    }
+
+    fn visit_bool<E: de::Error>(self, key: bool) -> Result<&'a Schema, E> {
+        self.additional
+            .ok_or_else(|| E::invalid_type(Unexpected::Bool(key), &"a string key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::object(vec![
+            Property::new("cpus", Schema::integer(1), "CPUs"),
+            Property::new("memory", Schema::integer(1), "Memory"),
+            Property::new(
+                "steps",
+                Schema::array(Schema::object(vec![Property::new(
+                    "script",
+                    Schema::Path.nullable(),
+                    "Script",
+                )])),
+                "Steps",
+            ),
+        ])
+    }
+
+    #[test]
+    fn errors_have_location() {
+        let err = validate_yaml("cpu: 4\nmemory: 0\n", &schema()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown field `cpu`, did you mean `cpus`? at line 1 column 1"
+        );
+        let err = validate_yaml("cpus: 4\nmemory: 0\n", &schema()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "memory: invalid value: integer `0`, expected an integer of at least 1 at line 2 column 9"
+        );
+    }
+
+    #[test]
+    fn relative_paths_are_resolved() {
+        let mut value: Value =
+            serde_yaml::from_str("steps: [{ script: a.sh }, { script: /b.sh }, { script: null }]")
+                .unwrap();
+        resolve_paths(&mut value, &schema(), Path::new("/config"));
+        let expected: Value =
+            serde_yaml::from_str("steps: [{ script: /config/a.sh }, { script: /b.sh }, { script: null }]")
+                .unwrap();
+        assert_eq!(value, expected);
+    }
+}
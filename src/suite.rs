@@ -0,0 +1,510 @@
+use crate::bench::{l2_output_path, timestamp, BenchOptions, BenchTarget};
+use crate::error::LauncherError;
+use crate::migrate::PROJECT_VERSION;
+use crate::phase::PhaseError;
+use crate::plan::Executor;
+use crate::schema::{self, Property, Schema};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Directory of the L2 vagrant directory where the scripts are staged, uploaded to the home
+// directory of L2 VMs
+pub const STAGING_DIR: &str = "bench-suite";
+// Directory of the project directory where the records of each suite run are written by default
+pub const RESULTS_DIR: &str = "suite-results";
+// Output file of the suite in the project directory if --output is not given
+pub const DEFAULT_OUTPUT: &str = "suite-output.txt";
+
+fn repeat_default() -> u32 {
+    1
+}
+
+// Benchmark of a suite. Paths are relative to the suite file.
+#[derive(Debug, Deserialize)]
+pub struct Benchmark {
+    pub name: String,
+    script: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+    // local command run with each output file, printing the parsed result as YAML or JSON
+    parser: Option<PathBuf>,
+    #[serde(default = "repeat_default")]
+    pub repeat: u32,
+    // timeout in seconds of each run, --bench-timeout if not set
+    timeout: Option<u64>,
+}
+
+impl Benchmark {
+    fn file_name(&self) -> String {
+        self.script
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Script in L2 VMs, relative to the home directory
+    pub fn vm_script(&self) -> String {
+        format!("{STAGING_DIR}/{}/{}", self.name, self.file_name())
+    }
+
+    // --bench-arg is appended to the args of the benchmark
+    pub fn options(&self, options: &BenchOptions) -> BenchOptions {
+        BenchOptions {
+            args: self.args.iter().chain(&options.args).cloned().collect(),
+            ..options.clone()
+        }
+    }
+
+    pub fn timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs).or(default)
+    }
+
+    // Output file of the run, `<stem>-<name>.<ext>` or `<stem>-<name>-<iteration>.<ext>` if the
+    // benchmark is repeated
+    pub fn output_path(&self, output_path: &Path, iteration: u32) -> PathBuf {
+        let suffix = if self.repeat > 1 {
+            format!("{}-{iteration}", self.name)
+        } else {
+            self.name.clone()
+        };
+        l2_output_path(output_path, &suffix, true)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Suite {
+    pub benchmarks: Vec<Benchmark>,
+}
+
+pub fn schema() -> Schema {
+    Schema::object(vec![Property::new(
+        "benchmarks",
+        Schema::array(Schema::object(vec![
+            Property::new("name", Schema::String, "Name of the benchmark").required(),
+            Property::new("script", Schema::String, "Script run in L2 VMs").required(),
+            Property::new("args", Schema::array(Schema::String), "Arguments of the script"),
+            Property::new(
+                "parser",
+                Schema::String.nullable(),
+                "Local command run with each output file, printing the result as YAML or JSON",
+            ),
+            Property::new("repeat", Schema::integer(1), "Number of runs, 1 by default"),
+            Property::new(
+                "timeout",
+                Schema::integer(1).nullable(),
+                "Timeout in seconds of each run, --bench-timeout if not set",
+            ),
+        ])),
+        "Benchmarks run in order",
+    )
+    .required()])
+}
+
+impl Suite {
+    // Load the suite keeping the benchmarks selected by --only and --skip
+    pub fn load(path: &Path, only: &[String], skip: &[String]) -> Result<Self, anyhow::Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        schema::validate_yaml(&text, &schema())
+            .map_err(|err| anyhow!("invalid bench suite {}: {err}", path.display()))?;
+        let mut suite: Suite = serde_yaml::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let mut names = BTreeSet::new();
+        for benchmark in &mut suite.benchmarks {
+            let name = &benchmark.name;
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+                && !name.starts_with('.');
+            if !valid {
+                return Err(anyhow!(
+                    "invalid benchmark name {name:?}, use letters, digits, '-', '_' and '.'"
+                ));
+            }
+            if !names.insert(name.clone()) {
+                return Err(anyhow!("benchmark {name} is duplicated"));
+            }
+            for path in [Some(&mut benchmark.script), benchmark.parser.as_mut()]
+                .into_iter()
+                .flatten()
+            {
+                *path = fs::canonicalize(base_dir.join(&*path)).with_context(|| {
+                    format!("failed to find {} of benchmark {name}", path.display())
+                })?;
+            }
+        }
+
+        for name in only.iter().chain(skip) {
+            if !names.contains(name) {
+                return Err(anyhow!("benchmark {name} is not in {}", path.display()));
+            }
+        }
+        suite.benchmarks.retain(|benchmark| {
+            (only.is_empty() || only.contains(&benchmark.name)) && !skip.contains(&benchmark.name)
+        });
+        if suite.benchmarks.is_empty() {
+            return Err(anyhow!("no benchmark of {} is selected", path.display()));
+        }
+        Ok(suite)
+    }
+
+    // Scripts of the benchmarks and where they are staged in the L2 vagrant directory
+    pub fn staged_files(&self, l2_vagrant_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+        self.benchmarks
+            .iter()
+            .map(|benchmark| {
+                (
+                    benchmark.script.clone(),
+                    l2_vagrant_dir.join(benchmark.vm_script()),
+                )
+            })
+            .collect()
+    }
+
+    pub fn stage(&self, executor: &mut Executor, l2_vagrant_dir: &Path) -> Result<(), anyhow::Error> {
+        executor.remove_dir(&l2_vagrant_dir.join(STAGING_DIR))?;
+        for (source, dest) in self.staged_files(l2_vagrant_dir) {
+            executor.copy_file(&source, &dest)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RunOutput {
+    host_name: String,
+    path: PathBuf,
+    parsed: Option<serde_yaml::Value>,
+    parse_error: Option<String>,
+}
+
+// Run of a benchmark on all the L2 VMs
+#[derive(Debug, Serialize)]
+struct RunRecord {
+    benchmark: String,
+    iteration: u32,
+    #[serde(flatten)]
+    options: BenchOptions,
+    started_at: String,
+    finished_at: String,
+    // why the run failed, none if it succeeded on every L2 VM
+    error: Option<String>,
+    outputs: Vec<RunOutput>,
+}
+
+impl RunRecord {
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.outputs.iter().any(|output| output.parse_error.is_some())
+    }
+}
+
+// Records written to --suite-results after each run
+#[derive(Debug, Serialize)]
+struct SuiteResults {
+    #[serde(skip)]
+    path: PathBuf,
+    version: u32,
+    records: Vec<RunRecord>,
+}
+
+impl SuiteResults {
+    fn record(&mut self, executor: &mut Executor, record: RunRecord) -> Result<(), anyhow::Error> {
+        self.records.push(record);
+        let path = self.path.clone();
+        executor.write_results(&path, || Ok(serde_yaml::to_string(self)?))
+    }
+}
+
+async fn parse_output(parser: &Path, output_path: &Path) -> Result<serde_yaml::Value, anyhow::Error> {
+    let output = tokio::process::Command::new(parser)
+        .arg(output_path)
+        .output()
+        .await
+        .with_context(|| format!("failed to run {}", parser.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed with status: {}: {}",
+            parser.display(),
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    serde_yaml::from_slice(&output.stdout)
+        .with_context(|| format!("invalid output of {}", parser.display()))
+}
+
+// File recording the suite run started now, `<project-dir>/suite-results/<timestamp>.yaml`
+pub fn default_results_path(project_dir: &Path) -> PathBuf {
+    project_dir
+        .join(RESULTS_DIR)
+        .join(format!("{}.yaml", timestamp().replace(':', "-")))
+}
+
+// Run the benchmarks in order on the booted L2 VMs. A failed run is recorded and the suite goes
+// on, unless it is interrupted.
+pub async fn run_suite(
+    executor: &mut Executor,
+    suite: &Suite,
+    targets: &[BenchTarget],
+    options: &BenchOptions,
+    output_path: &Path,
+    results_path: &Path,
+    timeout: Option<Duration>,
+) -> Result<(), anyhow::Error> {
+    let mut results = SuiteResults {
+        path: results_path.to_path_buf(),
+        version: PROJECT_VERSION,
+        records: vec![],
+    };
+    let multiple_vms = targets.len() > 1;
+    let mut failed = 0;
+    let mut runs = 0;
+    for benchmark in &suite.benchmarks {
+        for iteration in 1..=benchmark.repeat {
+            info!(
+                "running benchmark {} ({iteration}/{})",
+                benchmark.name, benchmark.repeat
+            );
+            let run_output_path = benchmark.output_path(output_path, iteration);
+            let options = benchmark.options(options);
+            let started_at = timestamp();
+            let result = executor
+                .run_bench(
+                    targets,
+                    &benchmark.vm_script(),
+                    &options,
+                    Some(&run_output_path),
+                    benchmark.timeout(timeout),
+                )
+                .await;
+            let finished_at = timestamp();
+
+            let mut outputs = vec![];
+            for target in targets {
+                let path = l2_output_path(&run_output_path, &target.host_name, multiple_vms);
+                let (parsed, parse_error) = match (&benchmark.parser, &result) {
+                    // outputs of a dry run are not written
+                    (Some(parser), Ok(())) if !executor.is_dry_run() => match parse_output(parser, &path).await {
+                        Ok(parsed) => (Some(parsed), None),
+                        Err(err) => (None, Some(format!("{err:#}"))),
+                    },
+                    _ => (None, None),
+                };
+                outputs.push(RunOutput {
+                    host_name: target.host_name.clone(),
+                    path,
+                    parsed,
+                    parse_error,
+                });
+            }
+            let interrupted = matches!(
+                result.as_ref().err().and_then(|err| err.downcast_ref::<PhaseError>()),
+                Some(PhaseError::Interrupted { .. })
+            );
+            let record = RunRecord {
+                benchmark: benchmark.name.clone(),
+                iteration,
+                options,
+                started_at,
+                finished_at,
+                error: result.as_ref().err().map(|err| format!("{err:#}")),
+                outputs,
+            };
+            runs += 1;
+            if record.failed() {
+                failed += 1;
+                warn!(
+                    "benchmark {} ({iteration}/{}) failed",
+                    benchmark.name, benchmark.repeat
+                );
+            }
+            results.record(executor, record)?;
+            if interrupted {
+                return result;
+            }
+        }
+    }
+    if !executor.is_dry_run() {
+        info!("suite results written to {}", results.path.display());
+    }
+
+    if failed > 0 {
+        return Err(LauncherError::bench(anyhow!(
+            "{failed} of {runs} benchmark runs failed, see {}",
+            results.path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{DryRunArgs, PlanFormat};
+
+    const SUITE: &str = "
+benchmarks:
+  - name: fio
+    script: fio.sh
+    args: [--size, 1G]
+  - name: iperf
+    script: iperf.sh
+    repeat: 2
+  - name: stream
+    script: stream.sh
+";
+
+    // Suite file with its scripts in a temporary directory
+    fn suite_dir(name: &str, suite: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("marigold-suite-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for script in ["fio.sh", "iperf.sh", "stream.sh"] {
+            fs::write(dir.join(script), "#!/bin/sh\n").unwrap();
+        }
+        fs::write(dir.join("suite.yaml"), suite).unwrap();
+        dir
+    }
+
+    fn names(suite: &Suite) -> Vec<&str> {
+        suite.benchmarks.iter().map(|benchmark| benchmark.name.as_str()).collect()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn only_and_skip_select_benchmarks() {
+        let path = suite_dir("select", SUITE).join("suite.yaml");
+        let suite = Suite::load(&path, &[], &[]).unwrap();
+        assert_eq!(names(&suite), ["fio", "iperf", "stream"]);
+        assert_eq!(suite.benchmarks[0].script, fs::canonicalize(path.with_file_name("fio.sh")).unwrap());
+
+        let suite = Suite::load(&path, &strings(&["stream", "fio"]), &[]).unwrap();
+        assert_eq!(names(&suite), ["fio", "stream"]);
+        let suite = Suite::load(&path, &[], &strings(&["iperf"])).unwrap();
+        assert_eq!(names(&suite), ["fio", "stream"]);
+        let suite = Suite::load(&path, &strings(&["fio", "iperf"]), &strings(&["fio"])).unwrap();
+        assert_eq!(names(&suite), ["iperf"]);
+
+        let err = Suite::load(&path, &strings(&["unknown"]), &[]).unwrap_err();
+        assert!(err.to_string().starts_with("benchmark unknown is not in"), "{err}");
+        let err = Suite::load(&path, &[], &strings(&["unknown"])).unwrap_err();
+        assert!(err.to_string().starts_with("benchmark unknown is not in"), "{err}");
+        let err = Suite::load(&path, &strings(&["fio"]), &strings(&["fio"])).unwrap_err();
+        assert!(err.to_string().starts_with("no benchmark of"), "{err}");
+    }
+
+    #[test]
+    fn benchmark_names_are_validated() {
+        for name in ["", ".hidden", "a/b", "a b"] {
+            let suite = format!("benchmarks:\n  - name: {name:?}\n    script: fio.sh\n");
+            let path = suite_dir("invalid", &suite).join("suite.yaml");
+            let err = Suite::load(&path, &[], &[]).unwrap_err();
+            assert!(err.to_string().starts_with("invalid benchmark name"), "{name}: {err}");
+        }
+
+        let suite = "benchmarks:\n  - {name: fio, script: fio.sh}\n  - {name: fio, script: iperf.sh}\n";
+        let path = suite_dir("duplicated", suite).join("suite.yaml");
+        let err = Suite::load(&path, &[], &[]).unwrap_err();
+        assert_eq!(err.to_string(), "benchmark fio is duplicated");
+
+        let suite = "benchmarks:\n  - {name: fio, script: missing.sh}\n";
+        let path = suite_dir("missing", suite).join("suite.yaml");
+        let err = Suite::load(&path, &[], &[]).unwrap_err();
+        assert!(err.to_string().starts_with("failed to find missing.sh"), "{err}");
+    }
+
+    #[test]
+    fn repeated_benchmarks_have_an_output_per_iteration() {
+        let path = suite_dir("output", SUITE).join("suite.yaml");
+        let suite = Suite::load(&path, &[], &[]).unwrap();
+        let output = Path::new("/results/out.txt");
+        assert_eq!(suite.benchmarks[0].output_path(output, 1), Path::new("/results/out-fio.txt"));
+        assert_eq!(suite.benchmarks[1].output_path(output, 1), Path::new("/results/out-iperf-1.txt"));
+        assert_eq!(suite.benchmarks[1].output_path(output, 2), Path::new("/results/out-iperf-2.txt"));
+    }
+
+    #[tokio::test]
+    async fn run_suite_plans_each_run() {
+        let dir = suite_dir("run", SUITE);
+        let suite = Suite::load(&dir.join("suite.yaml"), &[], &strings(&["stream"])).unwrap();
+        let project_dir = dir.join("project");
+        let targets = [
+            BenchTarget::no_nested(&project_dir.join("l2-vagrant"), "vm1", None),
+            BenchTarget::no_nested(&project_dir.join("l2-vagrant"), "vm2", None),
+        ];
+        let options = BenchOptions {
+            args: strings(&["-v"]),
+            ..Default::default()
+        };
+        let mut executor = Executor::new(
+            "bench",
+            &project_dir,
+            DryRunArgs {
+                format: Some(PlanFormat::Text),
+            },
+        );
+        let results_path = default_results_path(&project_dir);
+        run_suite(
+            &mut executor,
+            &suite,
+            &targets,
+            &options,
+            &project_dir.join(DEFAULT_OUTPUT),
+            &results_path,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let plan = executor.plan_json();
+        let actions = plan["actions"].as_array().unwrap();
+        let outputs: Vec<&str> = actions
+            .iter()
+            .filter(|action| action["action"] == "run-bench")
+            .map(|action| action["output"].as_str().unwrap())
+            .collect();
+        let output = |name: &str| project_dir.join(name).to_string_lossy().into_owned();
+        assert_eq!(
+            outputs,
+            [
+                output("suite-output-fio-vm1.txt"),
+                output("suite-output-fio-vm2.txt"),
+                output("suite-output-iperf-1-vm1.txt"),
+                output("suite-output-iperf-1-vm2.txt"),
+                output("suite-output-iperf-2-vm1.txt"),
+                output("suite-output-iperf-2-vm2.txt"),
+            ]
+        );
+        let command = actions
+            .iter()
+            .find(|action| action["action"] == "run-bench")
+            .unwrap()["command"]
+            .as_str()
+            .unwrap();
+        // --bench-arg follows the args of the benchmark
+        let position = |arg: &str| command.find(arg).unwrap();
+        assert!(position("--size") < position("1G") && position("1G") < position("-v"), "{command}");
+
+        // the results of each suite run are written to their own file
+        let results: BTreeSet<&str> = actions
+            .iter()
+            .filter(|action| action["action"] == "write-file")
+            .filter_map(|action| action["path"].as_str())
+            .filter(|path| !path.ends_with(".metadata.yaml"))
+            .collect();
+        assert_eq!(results, BTreeSet::from([results_path.to_str().unwrap()]));
+        assert_eq!(results_path.parent().unwrap(), project_dir.join(RESULTS_DIR));
+        assert!(!results_path.to_string_lossy().contains(':'));
+    }
+}
@@ -0,0 +1,349 @@
+use crate::logging::step;
+use crate::phase::{cancelled, Phase, PhaseError, PhaseOptions, RetryRecord, RunLog};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+
+// provisioners defined before this provisioner are skipped by the Vagrantfiles
+const RESUME_PROVISIONER_ENV: &str = "MARIGOLD_RESUME_PROVISIONER";
+
+// vagrant of L2 VMs in L1 VM, run through `vagrant ssh` or by a provisioner of L1 VM. `[v]` keeps
+// the pattern from matching the shell running pkill.
+const L2_VAGRANT_PATTERN: &str = "[v]agrant (up|reload|provision)";
+
+static RUNNING_PROVISIONER_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^==> (\S+): Running provisioner: (\S+)").unwrap());
+
+pub fn shell_quote(script: &str) -> String {
+    format!("'{}'", script.replace('\'', r"'\''"))
+}
+
+// Command line of the command as typed in a shell
+pub fn command_line(command: &std::process::Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            let plain = arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,".contains(c));
+            if plain && !arg.is_empty() {
+                arg.into_owned()
+            } else {
+                shell_quote(&arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Machine names defined in config.yaml of the vagrant directory, in the order of definition
+pub fn vagrant_machines(vagrant_dir: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let config: serde_yaml::Value =
+        serde_yaml::from_reader(std::fs::File::open(vagrant_dir.join("config.yaml"))?)?;
+    let host_name = |vm: &serde_yaml::Value| vm["host_name"].as_str().map(str::to_string);
+    Ok(match config["vms"].as_sequence() {
+        Some(vms) => vms.iter().filter_map(host_name).collect(),
+        None => host_name(&config).into_iter().collect(),
+    })
+}
+
+// vagrant subcommand run on the host, or in L1 VM for L2 VMs
+pub struct VagrantCommand {
+    vagrant_dir: PathBuf,
+    // directory of L2 VMs in L1 VM
+    l1_dir: Option<String>,
+    args: Vec<String>,
+    // directory of config.yaml defining the machines
+    machines_dir: PathBuf,
+}
+
+impl VagrantCommand {
+    pub fn new(vagrant_dir: &Path, args: &[&str]) -> Self {
+        Self {
+            vagrant_dir: vagrant_dir.to_path_buf(),
+            l1_dir: None,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            machines_dir: vagrant_dir.to_path_buf(),
+        }
+    }
+
+    // Run vagrant of l2_vagrant_dir synced to l1_dir in L1 VM of l1_vagrant_dir
+    pub fn in_l1(
+        l1_vagrant_dir: &Path,
+        l2_vagrant_dir: &Path,
+        l1_dir: &str,
+        args: &[&str],
+    ) -> Self {
+        Self {
+            vagrant_dir: l1_vagrant_dir.to_path_buf(),
+            l1_dir: Some(l1_dir.to_string()),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            machines_dir: l2_vagrant_dir.to_path_buf(),
+        }
+    }
+
+    pub fn vagrant_dir(&self) -> &Path {
+        &self.vagrant_dir
+    }
+
+    // Whether the command runs vagrant of L2 VMs in L1 VM, directly or by the provisioners of L1
+    // VM which boot L2 VMs
+    fn runs_l2_vagrant(&self) -> bool {
+        if self.l1_dir.is_some() {
+            return true;
+        }
+        std::fs::File::open(self.vagrant_dir.join("config.yaml"))
+            .ok()
+            .and_then(|file| serde_yaml::from_reader::<_, serde_yaml::Value>(file).ok())
+            .is_some_and(|config| config.get("l2_vagrant_dir").is_some())
+    }
+
+    // Stop vagrant of L2 VMs left running in L1 VM when the local vagrant is killed
+    async fn stop_l2_vagrant(&self) {
+        let status = tokio::process::Command::new("vagrant")
+            .current_dir(&self.vagrant_dir)
+            .arg("ssh")
+            .arg("-c")
+            .arg(format!("pkill -f {}", shell_quote(L2_VAGRANT_PATTERN)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .await;
+        match status {
+            // pkill exits with 1 if no process matched
+            Ok(status) if status.success() || status.code() == Some(1) => {}
+            Ok(status) => warn!("stopping vagrant in L1 VM failed with status: {status}"),
+            Err(err) => warn!("stopping vagrant in L1 VM failed: {err}"),
+        }
+    }
+
+    // Command line run on the host without retries
+    pub fn command_line(&self) -> String {
+        command_line(self.command(&self.args, None).as_std())
+    }
+
+    fn command(&self, args: &[String], resume_provisioner: Option<&str>) -> tokio::process::Command {
+        // machines boot one by one, so that the last provisioner in the output is of the machine
+        // which failed. vagrant-libvirt boots them in parallel otherwise.
+        let mut args = args.to_vec();
+        if args.first().is_some_and(|arg| arg == "up") {
+            args.insert(1, "--no-parallel".to_string());
+        }
+        let mut command = tokio::process::Command::new("vagrant");
+        command.current_dir(&self.vagrant_dir);
+        match &self.l1_dir {
+            Some(l1_dir) => {
+                let env = resume_provisioner
+                    .map(|provisioner| format!("{RESUME_PROVISIONER_ENV}={provisioner} "))
+                    .unwrap_or_default();
+                command
+                    .arg("ssh")
+                    .arg("-c")
+                    .arg(format!("cd {l1_dir} && {env}vagrant {}", args.join(" ")));
+            }
+            None => {
+                command.args(&args);
+                if let Some(provisioner) = resume_provisioner {
+                    command.env(RESUME_PROVISIONER_ENV, provisioner);
+                }
+            }
+        }
+        command
+    }
+}
+
+// Machine and name of the provisioner starting on the line of vagrant output
+fn running_provisioner(line: &str) -> Option<(String, String)> {
+    RUNNING_PROVISIONER_PATTERN
+        .captures(line)
+        .map(|captures| (captures[1].to_string(), captures[2].to_string()))
+}
+
+// Machines defined after the machine, which the command did not reach
+fn machines_after(machines: Vec<String>, machine: &str) -> Vec<String> {
+    machines
+        .into_iter()
+        .skip_while(|name| name != machine)
+        .skip(1)
+        .collect()
+}
+
+// Run a command logging its stdout in a step of each provisioner, and return the machine and the
+// name of the last provisioner which started
+async fn run_watching_provisioners(
+    mut command: tokio::process::Command,
+) -> Result<(ExitStatus, Option<(String, String)>), anyhow::Error> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut child_stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut last_provisioner = None;
+    let mut provisioner_step = None;
+    while let Some(line) = child_stdout.next_line().await? {
+        if let Some((machine, provisioner)) = running_provisioner(&line) {
+            // the step of the previous provisioner ends here
+            provisioner_step = Some(step(&format!("provisioner {provisioner} ({machine})")));
+            last_provisioner = Some((machine, provisioner));
+        }
+        match &provisioner_step {
+            Some(span) => span.in_scope(|| info!(target: "vagrant", "{line}")),
+            None => info!(target: "vagrant", "{line}"),
+        }
+    }
+    Ok((child.wait().await?, last_provisioner))
+}
+
+async fn run_with_retries(
+    phase: Phase,
+    phase_options: &PhaseOptions,
+    vagrant: &VagrantCommand,
+    run_log: &mut RunLog,
+) -> Result<ExitStatus, anyhow::Error> {
+    let mut args = vagrant.args.clone();
+    // failed provisioner resumed by `vagrant provision <machine>`
+    let mut resume: Option<(String, String)> = None;
+    let mut attempt = 0;
+    loop {
+        let command_args = match &resume {
+            Some((machine, _)) => vec!["provision".to_string(), machine.clone()],
+            None => args.clone(),
+        };
+        let resume_provisioner = resume.as_ref().map(|(_, provisioner)| provisioner.as_str());
+        let (status, last_provisioner) =
+            run_watching_provisioners(vagrant.command(&command_args, resume_provisioner)).await?;
+        if status.success() {
+            let Some((machine, _)) = resume.take() else {
+                return Ok(status);
+            };
+            // continue the original command with the machines after the resumed one
+            let remaining = machines_after(vagrant_machines(&vagrant.machines_dir)?, &machine);
+            if remaining.is_empty() {
+                return Ok(status);
+            }
+            args = vagrant.args.iter().cloned().chain(remaining).collect();
+            continue;
+        }
+
+        if attempt >= phase_options.retries(phase) {
+            return Ok(status);
+        }
+        attempt += 1;
+        // failures before any provisioner, such as box download, rerun the command
+        if last_provisioner.is_some() {
+            resume = last_provisioner;
+        }
+        let delay = phase_options.backoff(attempt);
+        let (machine, provisioner) = resume.clone().unzip();
+        run_log.record_retry(RetryRecord {
+            phase,
+            attempt,
+            command: format!("vagrant {}", command_args.join(" ")),
+            exit_code: status.code(),
+            machine,
+            provisioner,
+            delay: delay.as_secs(),
+        })?;
+        match &resume {
+            Some((machine, provisioner)) => warn!(
+                "{phase} failed at provisioner {provisioner} of {machine}, resuming it in {} seconds ({attempt}/{})",
+                delay.as_secs(),
+                phase_options.retries(phase)
+            ),
+            None => warn!(
+                "{phase} failed, retrying in {} seconds ({attempt}/{})",
+                delay.as_secs(),
+                phase_options.retries(phase)
+            ),
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Run the vagrant command of the phase, retrying failures with backoff. The timeout covers
+// every retry of the phase.
+pub async fn run_vagrant_phase(
+    phase: Phase,
+    phase_options: &PhaseOptions,
+    vagrant: &VagrantCommand,
+    run_log: &mut RunLog,
+) -> Result<(), anyhow::Error> {
+    let status = tokio::select! {
+        res = run_with_retries(phase, phase_options, vagrant, run_log) => res?,
+        err = cancelled(phase, phase_options.timeout(phase)) => {
+            // killing local vagrant leaves vagrant of L2 VMs running in L1 VM
+            if vagrant.runs_l2_vagrant() {
+                warn!("{err}, stopping vagrant of L2 VMs in L1 VM");
+                vagrant.stop_l2_vagrant().await;
+            }
+            return Err(err.into());
+        }
+    };
+    if !status.success() {
+        return Err(PhaseError::Failed {
+            phase,
+            command: format!("vagrant {}", vagrant.args.join(" ")),
+            status,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn up_boots_machines_one_by_one() {
+        let dir = Path::new("/tmp/l2-vagrant");
+        assert_eq!(
+            VagrantCommand::new(dir, &["up"]).command_line(),
+            "vagrant up --no-parallel"
+        );
+        assert_eq!(
+            VagrantCommand::new(dir, &["reload", "--provision"]).command_line(),
+            "vagrant reload --provision"
+        );
+        let in_l1 = VagrantCommand::in_l1(dir, dir, "/home/vagrant/l2-vagrant", &["up", "--provision"]);
+        assert!(
+            in_l1
+                .command_line()
+                .ends_with("'cd /home/vagrant/l2-vagrant && vagrant up --no-parallel --provision'"),
+            "{}",
+            in_l1.command_line()
+        );
+    }
+
+    #[test]
+    fn failed_machine_is_resumed() {
+        // `vagrant up --no-parallel` of three machines failing at the second one
+        let output = "\
+Bringing machine 'vm1' up with 'libvirt' provider...
+Bringing machine 'vm2' up with 'libvirt' provider...
+Bringing machine 'vm3' up with 'libvirt' provider...
+==> vm1: Creating domain with the following settings...
+==> vm1: Running provisioner: apt (shell)...
+    vm1: Running: inline script
+==> vm1: Running provisioner: bench-script (file)...
+==> vm2: Creating domain with the following settings...
+==> vm2: Running provisioner: apt (shell)...
+    vm2: Running: inline script
+==> vm2: Running provisioner: install-perf (shell)...
+    vm2: E: Unable to locate package perf
+";
+        let last_provisioner = output.lines().filter_map(running_provisioner).next_back();
+        assert_eq!(
+            last_provisioner,
+            Some(("vm2".to_string(), "install-perf".to_string()))
+        );
+        let machines = ["vm1", "vm2", "vm3"].map(str::to_string).to_vec();
+        assert_eq!(machines_after(machines.clone(), "vm2"), ["vm3"]);
+        assert!(machines_after(machines, "vm3").is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+// HTTP API of the daemon running jobs with example/fake-backend.sh
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const TOKEN: &str = "api-test-token";
+
+// Daemon killed when the test ends
+struct Daemon {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Daemon {
+    fn start(dir: &Path) -> Self {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let child = Command::new(env!("CARGO_BIN_EXE_launcher"))
+            .arg("daemon")
+            .arg("--state-dir")
+            .arg(dir.join("state"))
+            .arg("--listen")
+            .arg(addr.to_string())
+            .arg("--backend")
+            .arg(manifest_dir.join("example/fake-backend.sh"))
+            .env("MARIGOLD_API_TOKEN", TOKEN)
+            .env("MARIGOLD_REGISTRY_DIR", dir.join("registry"))
+            .env("FAKE_BACKEND_SLEEP", "0.2")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started_at = Instant::now();
+        while TcpStream::connect(addr).is_err() {
+            assert!(started_at.elapsed() < Duration::from_secs(10), "daemon did not start");
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Self { child, addr }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path}", self.addr));
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {token}"));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header("Content-Type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = Client::new().request(request.body(body).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn get_json(&self, path: &str) -> Value {
+        let (status, body) = self.request(Method::GET, path, Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("marigold-api-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("work")).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn api_runs_jobs_with_fake_backend() {
+    let dir = test_dir("jobs");
+    let daemon = Daemon::start(&dir);
+
+    // every endpoint requires the token
+    let (status, _) = daemon.request(Method::GET, "/jobs", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = daemon.request(Method::GET, "/jobs", Some("wrong-token"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = daemon.request(Method::GET, "/jobs/1", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let work_dir = dir.join("work");
+    let (status, body) = daemon
+        .request(
+            Method::POST,
+            "/jobs",
+            Some(TOKEN),
+            Some(json!({
+                "name": "api-test",
+                "args": [
+                    "run-bench",
+                    "--bench-script", "./run-bench.sh",
+                    "--project-dir", "./dest",
+                    "--output", "./output.txt",
+                ],
+                "working_dir": work_dir,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", String::from_utf8_lossy(&body));
+    let id = serde_json::from_slice::<Value>(&body).unwrap()["id"].as_u64().unwrap();
+
+    let job = daemon.get_json(&format!("/jobs/{id}")).await;
+    assert_eq!(job["name"], "api-test");
+    let state = job["state"].as_str().unwrap();
+    assert!(["queued", "running", "succeeded"].contains(&state), "{job}");
+    let (status, _) = daemon.request(Method::GET, "/jobs/999", Some(TOKEN), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // the log is streamed until the job finishes
+    let (status, log) = daemon
+        .request(Method::GET, &format!("/jobs/{id}/log"), Some(TOKEN), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let log = String::from_utf8(log).unwrap();
+    assert!(log.contains("fake backend: run-bench"), "{log}");
+    assert!(log.ends_with("up\nprovision\nbench\n"), "{log}");
+
+    let job = daemon.get_json(&format!("/jobs/{id}")).await;
+    assert_eq!(job["state"], "succeeded", "{job}");
+    assert_eq!(job["exit_code"], 0);
+
+    let results = daemon.get_json(&format!("/jobs/{id}/results")).await;
+    let output_path = work_dir.join("output.txt");
+    assert_eq!(
+        results["files"][output_path.to_str().unwrap()],
+        "fake result of run-bench\n",
+        "{results}"
+    );
+    assert_eq!(results["metadata"]["subcommand"], "run-bench");
+    assert_eq!(results["metadata"]["backend"], "fake");
+
+    drop(daemon);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn api_returns_suite_results_of_each_job() {
+    let dir = test_dir("suite");
+    let daemon = Daemon::start(&dir);
+    let work_dir = dir.join("work");
+
+    // jobs of the same project run in order, each recording its suite runs in its own file
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let (status, body) = daemon
+            .request(
+                Method::POST,
+                "/jobs",
+                Some(TOKEN),
+                Some(json!({
+                    "args": ["run-bench", "--bench-suite", "./suite.yaml", "--project-dir", "./dest"],
+                    "working_dir": work_dir,
+                })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", String::from_utf8_lossy(&body));
+        ids.push(serde_json::from_slice::<Value>(&body).unwrap()["id"].as_u64().unwrap());
+    }
+    let started_at = Instant::now();
+    for id in &ids {
+        while daemon.get_json(&format!("/jobs/{id}")).await["state"] != "succeeded" {
+            assert!(started_at.elapsed() < Duration::from_secs(30), "job {id} did not finish");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    let mut suite_results = vec![];
+    for id in &ids {
+        let job = daemon.get_json(&format!("/jobs/{id}")).await;
+        let path = job["suite_results"].as_str().unwrap().to_string();
+        assert!(path.starts_with(work_dir.join("dest/suite-results/").to_str().unwrap()), "{job}");
+        let results = daemon.get_json(&format!("/jobs/{id}/results")).await;
+        let files: Vec<&String> = results["files"].as_object().unwrap().keys().collect();
+        assert_eq!(files, [&path], "{results}");
+        suite_results.push(path);
+    }
+    assert_ne!(suite_results[0], suite_results[1]);
+
+    drop(daemon);
+    let _ = std::fs::remove_dir_all(&dir);
+}